@@ -1,22 +1,166 @@
 #[macro_use]
 extern crate clap;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use semver::Version;
+use serde_derive::Deserialize;
 use walkdir::WalkDir;
 
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Write};
 use std::path::Path;
 
 use nbkit::{
+    builder::{pack, shlibs, SonameMap},
     core::pkgdb::{InfoLocal, PkgInfo, SetInfo},
     core::wrappers::{DependencyWrap, VersionWrap},
+    core::{NbError, PkgDb},
+    nbpm::exit_with_err,
     repo::REPO_PKG_INFO,
-    utils,
+    utils, TypeErr, HOST_ARCH,
 };
 
+/// Metadata of a package, read from a manifest file or from the command line. Every field is
+/// optional, missing fields are asked interactively.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    name: Option<String>,
+    version: Option<String>,
+    description: Option<String>,
+    depends: Option<Vec<String>>,
+    arch: Option<String>,
+}
+
+impl Manifest {
+    /// Loads a `Manifest` from a toml file.
+    fn load(path: &Path) -> Result<Manifest, TypeErr> {
+        let manifest_str = fs::read_to_string(path)?;
+        Ok(toml::from_str::<Manifest>(&manifest_str)?)
+    }
+
+    /// Overrides the values of the manifest with the ones given in the command line (if some).
+    fn merge_args(&mut self, args: &ArgMatches) {
+        if let Some(v) = args.value_of("name") {
+            self.name = Some(v.to_string());
+        }
+        if let Some(v) = args.value_of("pkg-version") {
+            self.version = Some(v.to_string());
+        }
+        if let Some(v) = args.value_of("description") {
+            self.description = Some(v.to_string());
+        }
+        if let Some(v) = args.values_of("depends") {
+            self.depends = Some(v.map(|d| d.to_string()).collect());
+        }
+        if let Some(v) = args.value_of("arch") {
+            self.arch = Some(v.to_string());
+        }
+    }
+}
+
+/// Validated metadata of the package being generated.
+#[derive(Default)]
+struct Metadata {
+    name: Option<String>,
+    version: Option<Version>,
+    description: Option<String>,
+    depends: Option<Vec<DependencyWrap>>,
+    arch: Option<String>,
+}
+
+impl Metadata {
+    /// Validates all the values of the given `Manifest`.
+    fn from(manifest: Manifest) -> Result<Metadata, TypeErr> {
+        let mut meta = Metadata::default();
+        if let Some(name) = manifest.name {
+            meta.set_name(&name)?;
+        }
+        if let Some(version) = manifest.version {
+            meta.set_version(&version)?;
+        }
+        if let Some(description) = manifest.description {
+            meta.set_description(&description)?;
+        }
+        for dep in manifest.depends.unwrap_or_default() {
+            meta.add_dependency(&dep)?;
+        }
+        if let Some(arch) = manifest.arch {
+            meta.set_arch(&arch)?;
+        }
+        Ok(meta)
+    }
+
+    fn set_name(&mut self, name: &str) -> Result<(), TypeErr> {
+        let name = name.trim();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "=<>^~*/".contains(c)) {
+            return Err(format!("Invalid package name: {:?}", name).into());
+        }
+        self.name = Some(name.to_string());
+        Ok(())
+    }
+
+    fn set_version(&mut self, version: &str) -> Result<(), TypeErr> {
+        match Version::parse(version.trim()) {
+            Ok(v) => self.version = Some(v),
+            Err(e) => return Err(format!("Invalid version {:?}: {}", version, e).into()),
+        }
+        Ok(())
+    }
+
+    fn set_description(&mut self, description: &str) -> Result<(), TypeErr> {
+        if description.trim().is_empty() {
+            return Err("Empty description".into());
+        }
+        self.description = Some(description.trim().to_string());
+        Ok(())
+    }
+
+    fn set_arch(&mut self, arch: &str) -> Result<(), TypeErr> {
+        let arch = arch.trim();
+        if arch.is_empty() || arch.contains(char::is_whitespace) {
+            return Err(format!("Invalid architecture: {:?}", arch).into());
+        }
+        self.arch = Some(arch.to_string());
+        Ok(())
+    }
+
+    /// Checks if the package depends on `name`, whatever the version requirement.
+    fn depends_on(&self, name: &str) -> bool {
+        match &self.depends {
+            Some(deps) => deps.iter().any(|d| d.inner().0 == name),
+            None => false,
+        }
+    }
+
+    fn add_dependency(&mut self, dep: &str) -> Result<(), TypeErr> {
+        let query = utils::parse_pkg_str_info(dep.trim())?;
+        if query.0.is_empty() {
+            return Err(format!("Invalid dependency: {:?}", dep).into());
+        }
+        self.depends
+            .get_or_insert_with(Vec::new)
+            .push(DependencyWrap::from(query));
+        Ok(())
+    }
+
+    /// Returns the names of the required fields that are not set.
+    fn missing_fields(&self) -> Vec<&str> {
+        let mut missing = vec![];
+        if self.name.is_none() {
+            missing.push("name");
+        }
+        if self.version.is_none() {
+            missing.push("version");
+        }
+        if self.description.is_none() {
+            missing.push("description");
+        }
+        missing
+    }
+}
+
 fn main() {
     let args = App::new("nbinfo-gen")
         .author(crate_authors!())
@@ -30,88 +174,294 @@ fn main() {
                 .value_name("paths")
                 .help("files or directories of the package")
                 .multiple(true)
-                .required(true),
+                .required_unless("root"),
+        )
+        .arg(
+            Arg::with_name("root")
+                .short("r")
+                .long("root")
+                .takes_value(true)
+                .value_name("staging")
+                .help("staging root of the package, paths are recorded relative to it"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .short("m")
+                .long("manifest")
+                .takes_value(true)
+                .value_name("path")
+                .help("read the metadata of the package from a toml manifest file"),
+        )
+        .arg(
+            Arg::with_name("name")
+                .short("n")
+                .long("name")
+                .takes_value(true)
+                .value_name("name")
+                .help("name of the package"),
+        )
+        .arg(
+            Arg::with_name("pkg-version")
+                .short("v")
+                .long("pkg-version")
+                .takes_value(true)
+                .value_name("version")
+                .help("semver formatted version of the package"),
+        )
+        .arg(
+            Arg::with_name("description")
+                .short("d")
+                .long("description")
+                .takes_value(true)
+                .value_name("text")
+                .help("brief description of the package"),
+        )
+        .arg(
+            Arg::with_name("depends")
+                .short("D")
+                .long("depends")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("dependency")
+                .help("dependency of the package, for example: linux>=5.5.3"),
+        )
+        .arg(
+            Arg::with_name("arch")
+                .short("a")
+                .long("arch")
+                .takes_value(true)
+                .value_name("arch")
+                .help("architecture of the package, `any` if architecture independent [default: host]"),
+        )
+        .arg(
+            Arg::with_name("index")
+                .short("i")
+                .long("index")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("path")
+                .help("package database (repo index or installed packages) used to find the packages providing the needed shared libraries"),
+        )
+        .arg(
+            Arg::with_name("soname-map")
+                .short("s")
+                .long("soname-map")
+                .takes_value(true)
+                .value_name("path")
+                .help("toml file mapping sonames to the packages providing them, for example: \"libz.so.1\" = \"zlib\""),
+        )
+        .arg(
+            Arg::with_name("pack")
+                .short("P")
+                .long("pack")
+                .takes_value(true)
+                .value_name("dir")
+                .requires("root")
+                .help("pack the files of the package into dir/<name>/<name>.tar.xz with its .sha256, for example: -P repo/x86_64/bin/core"),
+        )
+        .arg(
+            Arg::with_name("non-interactive")
+                .short("y")
+                .long("non-interactive")
+                .takes_value(false)
+                .help("fail instead of asking for missing metadata"),
         )
         .get_matches();
 
-    let mut paths = vec![]; // list of paths to all the FILES of the package (dirs not included)
-    for p in args.values_of("target-paths").unwrap() {
+    if let Err(e) = run(&args) {
+        exit_with_err(e);
+    }
+}
+
+fn run(args: &ArgMatches) -> Result<(), TypeErr> {
+    let targets: Vec<&str> = match args.values_of("target-paths") {
+        Some(t) => t.collect(),
+        None => vec!["."], // all the contents of the staging root
+    };
+    let (root, paths) = match args.value_of("root") {
+        Some(root) => {
+            let root = Path::new(root);
+            let paths = pack::staged_target_paths(root, &targets)?
+                .into_iter()
+                .filter(|p| p != REPO_PKG_INFO)
+                .collect();
+            (root, paths)
+        }
+        None => (Path::new(""), collect_paths(targets)?),
+    };
+
+    let mut manifest = match args.value_of("manifest") {
+        Some(path) => Manifest::load(Path::new(path))?,
+        None => Manifest::default(),
+    };
+    manifest.merge_args(args);
+    let mut meta = Metadata::from(manifest)?;
+
+    // ask for the missing metadata, if any and if there is someone to ask
+    let missing = meta.missing_fields();
+    if !missing.is_empty() {
+        if args.is_present("non-interactive") || !io::stdin().is_terminal() {
+            return Err(format!("Missing package metadata: {}", missing.join(", ")).into());
+        }
+        interactive_menu(&mut meta)?;
+    }
+
+    // detect the shared libraries needed and provided by the package
+    let libs = shlibs::scan_paths(root, &paths)?;
+    if args.is_present("index") || args.is_present("soname-map") {
+        let sonames = load_soname_map(args)?;
+        propose_depends(
+            &mut meta,
+            &sonames,
+            &libs,
+            args.is_present("non-interactive"),
+        )?;
+    }
+
+    // it's safe to call unwrap here, as `missing_fields` ensures the metadata is complete
+    let vreq = VersionWrap::from(meta.version.unwrap());
+    let setinfo = SetInfo::Local(InfoLocal::from(paths.clone()));
+    let mut pkginfo = PkgInfo::from(vreq, meta.depends, meta.description.unwrap(), Some(setinfo));
+    pkginfo.set_arch(Some(meta.arch.unwrap_or_else(|| HOST_ARCH.to_string())));
+    pkginfo.set_provides(libs.provides.into_iter().collect());
+
+    let name = meta.name.unwrap();
+    let mut info = HashMap::new();
+    info.insert(name.clone(), pkginfo);
+
+    let serialized = toml::to_string(&info)?;
+    let mut file = File::create(root.join(REPO_PKG_INFO))?;
+    file.write_all(serialized.as_bytes())?;
+
+    if let Some(dir) = args.value_of("pack") {
+        let archive = pack::pack_pkg(root, &paths, &Path::new(dir).join(&name), &name)?;
+        println!("Packed {}", archive.display());
+    }
+    Ok(())
+}
+
+/// Creates the `SonameMap` from the package databases and the soname map file given in the
+/// command line. The entries of the soname map file take precedence.
+fn load_soname_map(args: &ArgMatches) -> Result<SonameMap, TypeErr> {
+    let mut sonames = SonameMap::new();
+    for path in args.values_of("index").into_iter().flatten() {
+        sonames.extend(SonameMap::from_pkgdb(&PkgDb::load(Path::new(path))?));
+    }
+    if let Some(path) = args.value_of("soname-map") {
+        sonames.extend(SonameMap::load(Path::new(path))?);
+    }
+    Ok(sonames)
+}
+
+/// Proposes the packages providing the shared libraries needed by the package as dependencies.
+/// If `non_interactive` is `true`, the proposed dependencies are added without asking. The
+/// needed libraries with no known provider are reported.
+fn propose_depends(
+    meta: &mut Metadata,
+    sonames: &SonameMap,
+    libs: &shlibs::SharedLibs,
+    non_interactive: bool,
+) -> Result<(), TypeErr> {
+    // it's safe to call unwrap here, as the metadata is complete at this point
+    let name = meta.name.clone().unwrap();
+    let (providers, unresolved) = sonames.resolve(&name, &libs.needed);
+
+    for (pkg, needed) in providers {
+        if meta.depends_on(&pkg) {
+            continue;
+        }
+        let prompt = format!(
+            "Needs {} from {}, add dependency? [Y/n] ",
+            needed.join(", "),
+            pkg
+        );
+        let add = if non_interactive {
+            println!("Adding dependency {} (needs {})", pkg, needed.join(", "));
+            true
+        } else {
+            !utils::read_line(&prompt)?.trim().eq_ignore_ascii_case("n")
+        };
+        if add {
+            meta.add_dependency(&pkg)?;
+        }
+    }
+
+    for soname in unresolved {
+        eprintln!("Warning: No package provides the needed library {}", soname);
+    }
+    Ok(())
+}
+
+/// Returns the list of paths to all the FILES of the package (dirs not included), exactly as
+/// given. Used when no staging root is given.
+fn collect_paths(targets: Vec<&str>) -> Result<Vec<String>, TypeErr> {
+    let mut paths = vec![];
+    for p in targets {
         let path = Path::new(p);
 
         if !path.exists() {
-            panic!("path does not exist");
+            return Err(Box::new(NbError::MissingFile(p.to_string())));
         } else if path.is_file() {
             paths.push(p.to_string());
         } else if path.is_dir() {
             for entry in WalkDir::new(path) {
-                paths.push(match entry {
-                    Ok(v) if v.path().is_file() => format!("{}", v.path().display()),
-                    Ok(_) => continue,
-                    Err(_) => panic!("path error"),
-                });
+                let entry = entry?;
+                if entry.path().is_file() {
+                    paths.push(format!("{}", entry.path().display()));
+                }
             }
         }
     }
+    Ok(paths)
+}
 
-    let mut name = None;
-    let mut version = None;
-    let mut description = None;
-    let mut depends: Option<Vec<DependencyWrap>> = None;
-
+/// Shows a menu to set the metadata of the package interactively, until all the required
+/// metadata is set.
+fn interactive_menu(meta: &mut Metadata) -> Result<(), TypeErr> {
     loop {
         println!("---------------------------------------");
-        println!("(1) Name: {:?}", name);
-        println!("(2) Version: {:?}", version);
-        println!("(3) Description: {:?}", description);
-        println!("(4) Add dependency: {:?}\n", depends);
+        println!("(1) Name: {:?}", meta.name);
+        println!("(2) Version: {:?}", meta.version);
+        println!("(3) Description: {:?}", meta.description);
+        println!("(4) Add dependency: {:?}", meta.depends);
+        println!("(5) Architecture: {:?}\n", meta.arch);
         println!("(0) Done\n");
         println!("---------------------------------------");
 
-        let n = utils::read_line("Select an action: ")
-            .unwrap()
+        // `read_line` fails if the input ended, so this loop always ends
+        let n = match utils::read_line("Select an action: ")?
+            .trim()
             .parse::<usize>()
-            .unwrap();
+        {
+            Ok(n) if n <= 5 => n,
+            _ => {
+                eprintln!("Error: Invalid action");
+                continue;
+            }
+        };
 
         if n == 0 {
-            if name.is_none() {
-                eprintln!("Error: Name missing");
-            } else if version.is_none() {
-                eprintln!("Error: Version missing");
-            } else if description.is_none() {
-                eprintln!("Error: Description missing");
+            let missing = meta.missing_fields();
+            if missing.is_empty() {
+                return Ok(());
             }
-            break;
-        }
-        match utils::read_line("Set new value: ") {
-            Ok(v) => match n {
-                1 => name = Some(v),
-                2 => match Version::parse(&v) {
-                    Ok(ver) => version = Some(ver),
-                    Err(e) => eprintln!("Error: {}", e),
-                },
-                3 => description = Some(v),
-                4 => match utils::parse_pkg_str_info(&v) {
-                    Ok(q) => match &mut depends {
-                        Some(d) => d.push(DependencyWrap::from(q)),
-                        None => depends = Some(vec![DependencyWrap::from(q)]),
-                    },
-                    Err(e) => eprintln!("Error: {}", e),
-                },
-                _ => unreachable!(),
-            },
-            Err(e) => eprintln!("Error: {}", e),
-        }
-    }
-
-    let vreq = VersionWrap::from(version.unwrap());
-    let setinfo = SetInfo::Local(InfoLocal::from(paths));
-    let pkginfo = PkgInfo::from(vreq, depends, description.unwrap(), Some(setinfo));
-
-    let mut info = HashMap::new();
-    info.insert(name.unwrap(), pkginfo);
-
-    let serialized = toml::to_string(&info).unwrap();
+            eprintln!("Error: Missing {}", missing.join(", "));
+            continue;
+        }
 
-    let mut file = File::create(REPO_PKG_INFO).unwrap();
-    file.write_all(serialized.as_bytes()).unwrap();
+        let v = utils::read_line("Set new value: ")?;
+        let res = match n {
+            1 => meta.set_name(&v),
+            2 => meta.set_version(&v),
+            3 => meta.set_description(&v),
+            4 => meta.add_dependency(&v),
+            5 => meta.set_arch(&v),
+            _ => unreachable!(),
+        };
+        if let Err(e) = res {
+            eprintln!("Error: {}", e);
+        }
+    }
 }
@@ -1,19 +1,44 @@
+use clap::{ArgMatches, Shell};
+use regex::Regex;
+use serde::Serialize;
+
 use std::fs;
+use std::io;
 use std::path::Path;
 
-use nbkit::core::{PkgDb, Set};
+use nbkit::core::{pkgdb::PkgInfo, InstallReason, PkgDb, SearchMatch, SearchMode, Set, SetInfo};
+use nbkit::fetch::DefaultFetcher;
+use nbkit::nbpm::dryrun::DryRun;
+use nbkit::nbpm::history::HistoryEntry;
+use nbkit::nbpm::info::PkgDetails;
+use nbkit::nbpm::list::ListFilter;
+use nbkit::nbpm::lock::Lockfile;
+use nbkit::nbpm::manifest::Manifest;
+use nbkit::nbpm::progress::{human_size, parse_size};
 use nbkit::nbpm::{self, *};
-use nbkit::{repo::*, utils};
+use nbkit::{utils, TypeErr};
 
 fn main() {
     let args = cli::init_cli_args().get_matches();
+    // global options are also accepted after the subcommand, so they are read from its matches
+    let globals = args.subcommand().1.unwrap_or(&args);
+    let json = globals.is_present("json");
+
+    // ---------- completions --------- //
+    if let Some(sub_cmd) = args.subcommand_matches("completions") {
+        // it's safe to call unwrap here, as clap only accepts valid shells
+        let shell: Shell = sub_cmd.value_of("shell").unwrap().parse().unwrap();
+        cli::init_cli_args().gen_completions_to("nbpm", shell, &mut io::stdout());
+        return;
+    }
+    // -------------------------------- //
 
     // load the configuration
-    let config = match args.value_of("config") {
+    let mut config = match globals.value_of("config") {
         // a custom configuration file path has been given
         Some(path) => match Config::from(Path::new(path)) {
             Ok(c) => c,
-            Err(e) => exit_with_err(e),
+            Err(e) => fail(e, json),
         },
         // if no custom path is given, the default path is used
         None => {
@@ -33,101 +58,687 @@ fn main() {
         }
     };
 
+    // command line options override the configuration file
+    if let Some(root) = globals.value_of("root") {
+        config.set_root(root);
+    }
+    if let Some(arch) = globals.value_of("arch") {
+        config.set_arch(arch);
+    }
+    if globals.is_present("allow-foreign-arch") {
+        config.set_allow_foreign_arch(true);
+    }
+    if globals.is_present("yes") {
+        config.set_noconfirm(true);
+    }
+
+    // transport used to download files from the repository
+    let fetcher = DefaultFetcher::new();
+    let ui = TerminalUi::new(json, config.noconfirm());
+
     // a closure to save the local `PkgDb` if it's changed
     let save_local_db = |db_ref: &PkgDb| {
         let db_path = format!("{}/{}", config.home(), LOCAL_DB_PATH);
         match toml::to_string_pretty(db_ref) {
             Ok(s) => {
                 if let Err(e) = fs::write(db_path, s.as_bytes()) {
-                    exit_with_err(Box::new(e));
+                    fail(Box::new(e), json);
                 }
             }
             Err(e) => {
-                exit_with_err(Box::new(e));
+                fail(Box::new(e), json);
             }
         }
     };
 
     // ------------ update ------------ //
-    if args.is_present("update-repos") {
-        // full url to the remote repository index
-        let index_url = format!("{}/{}", config.repo_url(), REPO_INDEX_PATH);
-        println!("Updating repo index from: {}", index_url);
+    if args.subcommand_matches("update").is_some() {
+        match nbpm::utils::update_index(&config, &fetcher, &ui) {
+            Ok(mirror) if json => print_json(&serde_json::json!({ "mirror": mirror })),
+            Ok(mirror) => println!("Updated repo index from: {}", mirror),
+            Err(e) => fail(e, json),
+        }
+    }
+    // -------------------------------- //
 
-        // path to store the new index db
-        let index_path = format!("{}/{}", config.home(), LOCAL_INDEX_PATH);
+    // ------------ search ------------ //
+    if let Some(sub_cmd) = args.subcommand_matches("search") {
+        // it's safe to call unwrap here, as `pattern` is required
+        let pattern = sub_cmd.value_of("pattern").unwrap();
+        let index_db = load_db(&config, Set::Universe, json);
+        let local_db = load_db(&config, Set::Local, json);
+        let mode = if sub_cmd.is_present("regex") {
+            SearchMode::Regex
+        } else if sub_cmd.is_present("fuzzy") {
+            SearchMode::Fuzzy
+        } else {
+            SearchMode::Substring
+        };
+        let mut matches = match index_db.search(pattern, mode) {
+            Ok(m) => m,
+            Err(e) => fail(e, json),
+        };
+        SearchMatch::mark_installed(&mut matches, &local_db);
+        // it's safe to index here, as there is always at least the url of the repository
+        SearchMatch::mark_repo(&mut matches, &config.mirrors()[0]);
 
-        if let Err(e) = utils::download(&index_url, Path::new(&index_path)) {
-            eprintln!("Cannot update repository index.");
-            exit_with_err(e);
+        if json {
+            print_json(&matches);
+        } else if matches.is_empty() {
+            eprintln!("No package matches {} =(", pattern);
+        }
+        for m in matches.iter().filter(|_| !json) {
+            let installed = match &m.installed {
+                Some(v) if *v == m.version => " [installed]".to_string(),
+                Some(v) => format!(" [installed: {}]", v),
+                None => String::new(),
+            };
+            let repo = match &m.repo {
+                Some(r) => format!(" ({})", r),
+                None => String::new(),
+            };
+            println!(
+                "{} {}{}{}\n    {}",
+                m.location.as_deref().unwrap_or(&m.name),
+                m.version,
+                installed,
+                repo,
+                m.description
+            );
         }
-        println!("Updating done!");
     }
     // -------------------------------- //
 
-    // ------------ search ------------ //
-    if let Some(pkg_name) = args.value_of("search") {
-        let index_db = match nbpm::utils::load_pkgdb(&config, Set::Universe) {
-            Ok(v) => v,
-            Err(e) => exit_with_err(Box::new(e)),
+    // ------------- info ------------- //
+    if let Some(sub_cmd) = args.subcommand_matches("info") {
+        let name = sub_cmd.value_of("package").unwrap();
+        let index_db = load_db(&config, Set::Universe, json);
+        let local_db = load_db(&config, Set::Local, json);
+        match nbpm::info::pkg_details(name, &index_db, &local_db) {
+            Ok(details) if json => print_json(&details),
+            Ok(details) => print_pkg_details(&details),
+            Err(e) => fail(e, json),
+        }
+    }
+    // -------------------------------- //
+
+    // ------------- list ------------- //
+    if let Some(sub_cmd) = args.subcommand_matches("list") {
+        let local_db = load_db(&config, Set::Local, json);
+        let filter = match list_filter(sub_cmd) {
+            Ok(f) => f,
+            Err(e) => fail(e, json),
         };
-        match index_db.get_pkg_info(pkg_name) {
-            Some(info) => println!(
-                "{} - {}    {}",
-                pkg_name,
-                info.version(),
-                info.description()
-            ),
-            None => {
-                eprintln!("Package {} not found =(", pkg_name);
+        // the index is only required by the filters that need it
+        let index_db = if filter.upgradable || filter.set.is_some() {
+            load_db(&config, Set::Universe, json)
+        } else {
+            nbpm::utils::load_pkgdb(&config, Set::Universe)
+                .unwrap_or_else(|_| PkgDb::with_set(Set::Universe))
+        };
+        let list = nbpm::list::list_installed(&local_db, &index_db, &filter);
+        if json {
+            print_json(&list);
+        } else if sub_cmd.is_present("export") {
+            list.iter().for_each(|p| println!("{}", p.name));
+        } else {
+            list.iter().for_each(|p| match &p.upgrade {
+                Some(v) => println!("{} {} -> {}", p.name, p.version, v),
+                None => println!("{} {}", p.name, p.version),
+            });
+        }
+    }
+    // -------------------------------- //
+
+    // ------------- files ------------ //
+    if let Some(sub_cmd) = args.subcommand_matches("files") {
+        let name = sub_cmd.value_of("package").unwrap();
+        let local_db = load_db(&config, Set::Local, json);
+        match nbpm::files::pkg_files(name, &local_db) {
+            Ok(files) if json => print_json(&files),
+            Ok(files) => files.iter().for_each(|f| println!("{}", f)),
+            Err(e) => fail(e, json),
+        }
+    }
+    // -------------------------------- //
+
+    // ------------- owns ------------- //
+    if let Some(sub_cmd) = args.subcommand_matches("owns") {
+        let path = sub_cmd.value_of("path").unwrap();
+        let local_db = load_db(&config, Set::Local, json);
+        let owners = nbpm::files::owners(path, &config, &local_db);
+        if json {
+            print_json(&owners);
+        } else if owners.is_empty() {
+            eprintln!("{} does not belong to any package", path);
+            std::process::exit(1);
+        } else {
+            owners
+                .iter()
+                .for_each(|o| println!("{} belongs to {}", path, o));
+        }
+    }
+    // -------------------------------- //
+
+    // ------------ verify ------------ //
+    if let Some(sub_cmd) = args.subcommand_matches("verify") {
+        let names: Option<Vec<&str>> = sub_cmd.values_of("packages").map(|v| v.collect());
+        let local_db = load_db(&config, Set::Local, json);
+        let checks = match nbpm::files::verify_files(names.as_deref(), &local_db) {
+            Ok(c) => c,
+            Err(e) => fail(e, json),
+        };
+        if json {
+            print_json(&checks);
+        }
+        for check in checks.iter().filter(|_| !json) {
+            if check.missing.is_empty() {
+                println!("[*] {}: {} files ok", check.name, check.files);
+            } else {
+                println!(
+                    "[!] {}: {} of {} files missing",
+                    check.name,
+                    check.missing.len(),
+                    check.files
+                );
+                check.missing.iter().for_each(|m| println!("    {}", m));
             }
         }
+        if checks.iter().any(|c| !c.missing.is_empty()) {
+            std::process::exit(1);
+        }
     }
     // -------------------------------- //
 
-    // ------------ install ----------- //
-    if let Some(names_list) = args.values_of("install") {
-        let index_db = match nbpm::utils::load_pkgdb(&config, Set::Universe) {
-            Ok(v) => v,
-            Err(e) => exit_with_err(Box::new(e)),
+    // ------------- cache ------------ //
+    if let Some(sub_cmd) = args.subcommand_matches("cache") {
+        if sub_cmd.is_present("clean") {
+            match nbpm::cache::clean_cache(&config) {
+                Ok(freed) if json => print_json(&serde_json::json!({ "freed": freed })),
+                Ok(freed) => println!("Removed the cache, {} freed", human_size(freed)),
+                Err(e) => fail(e, json),
+            }
+        } else {
+            let entries = match nbpm::cache::cache_entries(&config) {
+                Ok(e) => e,
+                Err(e) => fail(e, json),
+            };
+            if json {
+                print_json(&entries);
+            } else {
+                entries
+                    .iter()
+                    .for_each(|e| println!("{} ({})", e.path, human_size(e.size)));
+                let total = entries.iter().map(|e| e.size).sum();
+                println!("Total: {}", human_size(total));
+            }
+        }
+    }
+    // -------------------------------- //
+
+    // ------------ history ----------- //
+    if let Some(sub_cmd) = args.subcommand_matches("history") {
+        let last = match sub_cmd.value_of("last").map(|n| n.parse::<usize>()) {
+            Some(Ok(n)) => Some(n),
+            Some(Err(e)) => fail(Box::new(e), json),
+            None => None,
         };
-        let names: Vec<&str> = names_list.collect();
+        let history = match nbpm::history::read_history(&config) {
+            Ok(h) => h,
+            Err(e) => fail(e, json),
+        };
+        let skip = history.len().saturating_sub(last.unwrap_or(history.len()));
+        let history = &history[skip..];
+        if json {
+            print_json(&history);
+        }
+        for entry in history.iter().filter(|_| !json) {
+            println!("{}  {}", utils::format_unix_time(entry.date), entry.command);
+            for p in &entry.changes {
+                println!("    {:<8} {}", p.action.to_string(), p);
+            }
+        }
+    }
+    // -------------------------------- //
+
+    // ------------ install ----------- //
+    if let Some(sub_cmd) = args.subcommand_matches("install") {
+        let names: Vec<&str> = sub_cmd.values_of("packages").unwrap().collect();
+        let index_db = load_db(&config, Set::Universe, json);
 
         // TODO: Lock the database file
         // open the local package database
-        let mut local_db = match nbpm::utils::load_pkgdb(&config, Set::Local) {
-            Ok(v) => v,
-            Err(e) => exit_with_err(Box::new(e)),
+        let mut local_db = load_db(&config, Set::Local, json);
+
+        if sub_cmd.is_present("dry-run") {
+            match nbpm::dryrun::dry_run_install(
+                &names, &config, &local_db, &index_db, &fetcher, &ui,
+            ) {
+                Ok(d) if json => print_json(&d),
+                Ok(d) => print_dry_run(&d),
+                Err(e) => fail(e, json),
+            }
+            return;
+        }
+
+        match nbpm::install::install_handler(
+            &names,
+            &config,
+            &mut local_db,
+            &index_db,
+            &fetcher,
+            &ui,
+        ) {
+            Ok(t) => {
+                save_local_db(&local_db);
+                let command = format!("install {}", names.join(" "));
+                finish_transaction(&t, &command, &config, &ui, json);
+            }
+            Err(e) => {
+                save_local_db(&local_db);
+                if !json {
+                    eprintln!("[!] Installation failed");
+                }
+                fail(e, json);
+            }
+        }
+    }
+    // -------------------------------- //
+
+    // ------------ upgrade ----------- //
+    if let Some(sub_cmd) = args.subcommand_matches("upgrade") {
+        let index_db = load_db(&config, Set::Universe, json);
+        let mut local_db = load_db(&config, Set::Local, json);
+
+        if sub_cmd.is_present("dry-run") {
+            match nbpm::dryrun::dry_run_upgrade(&config, &local_db, &index_db, &fetcher, &ui) {
+                Ok(d) if json => print_json(&d),
+                Ok(d) => print_dry_run(&d),
+                Err(e) => fail(e, json),
+            }
+            return;
+        }
+
+        match nbpm::install::upgrade_handler(&config, &mut local_db, &index_db, &fetcher, &ui) {
+            Ok(t) => {
+                save_local_db(&local_db);
+                finish_transaction(&t, "upgrade", &config, &ui, json);
+            }
+            Err(e) => {
+                save_local_db(&local_db);
+                if !json {
+                    eprintln!("[!] Upgrade failed");
+                }
+                fail(e, json);
+            }
+        }
+    }
+    // -------------------------------- //
+
+    // ------------- apply ------------ //
+    if let Some(sub_cmd) = args.subcommand_matches("apply") {
+        let path = sub_cmd.value_of("manifest").unwrap();
+        let prune = sub_cmd.is_present("prune");
+        let manifest = match Manifest::load(Path::new(path)) {
+            Ok(m) => m,
+            Err(e) => fail(e, json),
         };
+        let index_db = load_db(&config, Set::Universe, json);
+        let mut local_db = load_db(&config, Set::Local, json);
 
-        if let Err(e) = nbpm::install::install_handler(&names, &config, &mut local_db, &index_db) {
-            eprintln!("[!] Installation failed");
-            exit_with_err(e);
+        if sub_cmd.is_present("dry-run") {
+            match nbpm::dryrun::dry_run_apply(
+                &manifest, prune, &config, &local_db, &index_db, &fetcher, &ui,
+            ) {
+                Ok(d) if json => print_json(&d),
+                Ok(d) => print_dry_run(&d),
+                Err(e) => fail(e, json),
+            }
+            return;
+        }
+
+        match nbpm::manifest::apply_handler(
+            &manifest,
+            prune,
+            &config,
+            &mut local_db,
+            &index_db,
+            &fetcher,
+            &ui,
+        ) {
+            Ok(t) => {
+                save_local_db(&local_db);
+                let flags = if prune { "--prune " } else { "" };
+                let command = format!("apply {}{}", flags, path);
+                finish_transaction(&t, &command, &config, &ui, json);
+            }
+            Err(e) => {
+                save_local_db(&local_db);
+                fail(e, json);
+            }
+        }
+    }
+    // -------------------------------- //
+
+    // ------------- lock ------------- //
+    if let Some(sub_cmd) = args
+        .subcommand_matches("lock")
+        .and_then(|l| l.subcommand_matches("export"))
+    {
+        let local_db = load_db(&config, Set::Local, json);
+        let lock = Lockfile::from_pkgdb(&local_db);
+        let unhashed = lock.unhashed(&local_db);
+        if !unhashed.is_empty() {
+            eprintln!(
+                "Warning: The hash of the following packages is unknown, reinstall them to lock \
+                their hash: {}",
+                unhashed.join(" ")
+            );
+        }
+        match sub_cmd.value_of("output") {
+            Some(path) => {
+                if let Err(e) = lock.save(Path::new(path)) {
+                    fail(e, json);
+                }
+                if json {
+                    print_json(&serde_json::json!({ "lockfile": path }));
+                } else {
+                    println!("Locked {} packages in {}", lock.packages.len(), path);
+                }
+            }
+            None if json => print_json(&lock),
+            None => match toml::to_string(&lock) {
+                Ok(s) => print!("{}", s),
+                Err(e) => fail(Box::new(e), json),
+            },
+        }
+    }
+
+    if let Some(sub_cmd) = args
+        .subcommand_matches("lock")
+        .and_then(|l| l.subcommand_matches("install"))
+    {
+        let path = sub_cmd.value_of("lockfile").unwrap();
+        let prune = sub_cmd.is_present("prune");
+        let mut config = config.clone();
+        if sub_cmd.is_present("allow-unhashed") {
+            config.set_allow_unhashed(true);
+        }
+        let lock = match Lockfile::load(Path::new(path)) {
+            Ok(l) => l,
+            Err(e) => fail(e, json),
+        };
+        let index_db = load_db(&config, Set::Universe, json);
+        let mut local_db = load_db(&config, Set::Local, json);
+
+        if sub_cmd.is_present("dry-run") {
+            match nbpm::dryrun::dry_run_lock_install(
+                &lock, prune, &config, &local_db, &index_db, &fetcher, &ui,
+            ) {
+                Ok(d) if json => print_json(&d),
+                Ok(d) => print_dry_run(&d),
+                Err(e) => fail(e, json),
+            }
+            return;
+        }
+
+        match nbpm::lock::lock_install_handler(
+            &lock,
+            prune,
+            &config,
+            &mut local_db,
+            &index_db,
+            &fetcher,
+            &ui,
+        ) {
+            Ok(t) => {
+                save_local_db(&local_db);
+                let mut flags = String::new();
+                if prune {
+                    flags.push_str("--prune ");
+                }
+                if sub_cmd.is_present("allow-unhashed") {
+                    flags.push_str("--allow-unhashed ");
+                }
+                let command = format!("lock install {}{}", flags, path);
+                finish_transaction(&t, &command, &config, &ui, json);
+            }
+            Err(e) => {
+                save_local_db(&local_db);
+                if !json {
+                    eprintln!("[!] Installation failed");
+                }
+                fail(e, json);
+            }
         }
-        save_local_db(&local_db);
     }
     // -------------------------------- //
 
     // ------------ remove ------------ //
     if let Some(sub_cmd) = args.subcommand_matches("remove") {
-        let names_list = sub_cmd.values_of("packages").unwrap();
+        let names: Vec<&str> = sub_cmd.values_of("packages").unwrap().collect();
+        let recursive = sub_cmd.is_present("recursive");
         // TODO: Lock the database file
         // open the local package database
-        let mut local_db = match nbpm::utils::load_pkgdb(&config, Set::Local) {
-            Ok(v) => v,
-            Err(e) => exit_with_err(Box::new(e)),
-        };
+        let mut local_db = load_db(&config, Set::Local, json);
+
+        if sub_cmd.is_present("dry-run") {
+            match nbpm::dryrun::dry_run_remove(&names, recursive, true, &local_db) {
+                Ok(d) if json => print_json(&d),
+                Ok(d) => print_dry_run(&d),
+                Err(e) => fail(e, json),
+            }
+            return;
+        }
 
-        let to_remove_names: Vec<&str> = names_list.collect();
-        if let Err(e) = nbpm::remove::remove_handler(
-            &to_remove_names,
-            sub_cmd.is_present("recursive"),
-            true, // ask for user confirmation before removing the packages
+        match nbpm::remove::remove_handler(
+            &names,
+            recursive,
             true, // check for conflicts
             &mut local_db,
+            &ui,
         ) {
-            exit_with_err(e);
+            Ok(t) => {
+                save_local_db(&local_db);
+                let flags = if recursive { "-R " } else { "" };
+                let command = format!("remove {}{}", flags, names.join(" "));
+                finish_transaction(&t, &command, &config, &ui, json);
+            }
+            Err(e) => {
+                save_local_db(&local_db);
+                fail(e, json);
+            }
+        }
+    }
+    // -------------------------------- //
+}
+
+/// Exits printing the error, as JSON if `json` is `true`.
+fn fail(err: TypeErr, json: bool) -> ! {
+    if json {
+        exit_with_json_err(err)
+    } else {
+        exit_with_err(err)
+    }
+}
+
+/// Loads the index or the local `PkgDb`, exiting on error.
+fn load_db(config: &Config, set: Set, json: bool) -> PkgDb {
+    match nbpm::utils::load_pkgdb(config, set) {
+        Ok(db) => db,
+        Err(e) => fail(e, json),
+    }
+}
+
+/// Prints a value as JSON to the standard output.
+fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(s) => println!("{}", s),
+        Err(e) => exit_with_json_err(Box::new(e)),
+    }
+}
+
+/// Builds the `ListFilter` of the `list` subcommand from its arguments.
+fn list_filter(sub_cmd: &ArgMatches) -> Result<ListFilter, TypeErr> {
+    let reason = if sub_cmd.is_present("explicit") {
+        Some(InstallReason::Explicit)
+    } else if sub_cmd.is_present("deps") {
+        Some(InstallReason::Dependency)
+    } else {
+        None
+    };
+    Ok(ListFilter {
+        reason,
+        orphans: sub_cmd.is_present("orphans"),
+        upgradable: sub_cmd.is_present("upgradable"),
+        set: sub_cmd.value_of("set").map(|s| s.to_string()),
+        name: sub_cmd.value_of("name").map(Regex::new).transpose()?,
+        min_size: sub_cmd.value_of("min-size").map(parse_size).transpose()?,
+        max_size: sub_cmd.value_of("max-size").map(parse_size).transpose()?,
+    })
+}
+
+/// Prints the result of an operation and records it in the history, with the `command` that
+/// made it. If the operation was not done because the `ui` could not ask for confirmation, exits
+/// with an error.
+fn finish_transaction(
+    t: &Transaction,
+    command: &str,
+    config: &Config,
+    ui: &TerminalUi,
+    json: bool,
+) {
+    if !t.done && ui.refuses_prompts() {
+        fail(Box::new(NbpmError::NonInteractive), json);
+    }
+    if t.done && !t.plan.is_empty() {
+        let entry = HistoryEntry {
+            date: utils::unix_time(),
+            command: command.to_string(),
+            changes: t.plan.clone(),
+        };
+        if let Err(e) = nbpm::history::record(config, entry) {
+            eprintln!("Warning: Cannot record the changes in the history: {}", e);
+        }
+    }
+    if json {
+        print_json(t);
+    } else if !t.done {
+        println!("Operation cancelled");
+    }
+}
+
+/// Prints everything an operation would do, as computed by its dry run.
+fn print_dry_run(dry_run: &DryRun) {
+    if dry_run.plan.is_empty() {
+        println!("Nothing to do");
+        return;
+    }
+    println!("Packages to be changed ({}):", dry_run.plan.len());
+    for p in &dry_run.plan {
+        println!("    {:<8} {}", p.action.to_string(), p);
+    }
+    if !dry_run.downloads.is_empty() {
+        println!("\nDownloads ({}):", dry_run.downloads.len());
+        for d in &dry_run.downloads {
+            println!("    {} ({}) from {}", d.name, human_size(d.size), d.mirror);
+        }
+    }
+    if !dry_run.writes.is_empty() {
+        println!("\nFiles to be written ({}):", dry_run.writes.len());
+        for f in &dry_run.writes {
+            println!("    {} [{}]", f.path, f.package);
         }
-        save_local_db(&local_db);
+    }
+    if !dry_run.deletes.is_empty() {
+        println!("\nFiles to be deleted ({}):", dry_run.deletes.len());
+        for f in &dry_run.deletes {
+            println!("    {} [{}]", f.path, f.package);
+        }
+    }
+    if !dry_run.conflicts.is_empty() {
+        println!("\nFile conflicts ({}):", dry_run.conflicts.len());
+        for c in &dry_run.conflicts {
+            let owner = c.owner.as_deref().unwrap_or("no package");
+            println!("    {} [{}, owned by {}]", c.path, c.package, owner);
+        }
+    }
+    println!();
+    if dry_run.download_size > 0 {
+        println!("Download size   : {}", human_size(dry_run.download_size));
+    }
+    let usage = human_size(dry_run.disk_usage.unsigned_abs());
+    if dry_run.disk_usage < 0 {
+        println!("Disk space freed: {}", usage);
+    } else {
+        println!("Disk space used : {}", usage);
+    }
+    println!("\nDry run, no changes were made");
+}
+
+/// Prints all the information of a package, first as available in the repository and then as
+/// installed in the system.
+fn print_pkg_details(details: &PkgDetails) {
+    println!("Name           : {}", details.name);
+    if let Some(info) = &details.index {
+        println!("\n[Repository]");
+        print_pkg_info(info, &details.index_required_by);
+    }
+    if let Some(info) = &details.local {
+        println!("\n[Installed]");
+        print_pkg_info(info, &details.local_required_by);
+        let reason = info.install_reason().map(|r| r.to_string());
+        println!(
+            "Install reason : {}",
+            reason.as_deref().unwrap_or("unknown")
+        );
+        let date = info.install_date().map(utils::format_unix_time);
+        println!("Install date   : {}", date.as_deref().unwrap_or("unknown"));
+        if let Some(size) = details.installed_size {
+            println!("Installed size : {}", human_size(size));
+        }
+        if let Some(count) = details.file_count {
+            println!("Files          : {}", count);
+        }
+        match &details.upgrade {
+            Some(v) => println!("Upgrade        : {} -> {}", info.version(), v),
+            None => println!("Upgrade        : none"),
+        }
+    }
+}
+
+/// Prints the fields of a `PkgInfo`, and the packages that depend on it.
+fn print_pkg_info(info: &PkgInfo, required_by: &[String]) {
+    println!("Version        : {}", info.version());
+    println!("Description    : {}", info.description());
+    println!("Architecture   : {}", info.arch().unwrap_or("unknown"));
+    let depends: Vec<String> = info
+        .depends()
+        .unwrap_or_default()
+        .iter()
+        .map(|(name, req)| format!("{} {}", name, req))
+        .collect();
+    println!("Depends on     : {}", list_or_none(&depends));
+    println!("Required by    : {}", list_or_none(required_by));
+    println!("Provides       : {}", list_or_none(info.provides()));
+    match info.set_info() {
+        Some(SetInfo::Universe(u)) => {
+            println!("Set            : universe");
+            println!("Location       : {}", u.location());
+        }
+        Some(SetInfo::Local(l)) => {
+            println!("Set            : local");
+            println!("Origin         : {}", l.origin().unwrap_or("unknown"));
+        }
+        None => println!("Set            : none (meta-package)"),
+    }
+}
+
+/// Joins the items of a list, or returns `none` if empty.
+fn list_or_none(list: &[String]) -> String {
+    if list.is_empty() {
+        "none".to_string()
+    } else {
+        list.join("  ")
     }
 }
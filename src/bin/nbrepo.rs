@@ -0,0 +1,274 @@
+#[macro_use]
+extern crate clap;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use nbkit::builder::{self, Isolation};
+use nbkit::core::PkgDb;
+use nbkit::fetch::DefaultFetcher;
+use nbkit::nbpm::{exit_with_err, Config};
+use nbkit::repo::{check, index, REPO_INDEX_PATH};
+
+fn main() {
+    let args = App::new("nbrepo")
+        .author(crate_authors!())
+        .about("Helper program for maintaining nebula repositories")
+        .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("repo")
+                .short("r")
+                .long("repo")
+                .takes_value(true)
+                .value_name("path")
+                .default_value(".")
+                .help("path to the repo/{arch} directory of the repository"),
+        )
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("Generate the index of the repository from all the binary packages"),
+        )
+        .subcommand(SubCommand::with_name("check").about("Check the consistency of the repository"))
+        .subcommand(
+            SubCommand::with_name("build")
+                .about("Build packages from their templates in src")
+                .arg(
+                    Arg::with_name("work-dir")
+                        .short("w")
+                        .long("work-dir")
+                        .takes_value(true)
+                        .value_name("path")
+                        .help("directory where the packages are built [default: $TMPDIR/nbrepo]"),
+                )
+                .arg(
+                    Arg::with_name("isolated")
+                        .short("i")
+                        .long("isolated")
+                        .takes_value(false)
+                        .help("build inside a chroot containing only the build dependencies"),
+                )
+                .arg(
+                    Arg::with_name("locations")
+                        .help("locations of the packages inside src, for example: core/foo")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Rebuild packages from src and compare them with the published packages")
+                .arg(
+                    Arg::with_name("work-dir")
+                        .short("w")
+                        .long("work-dir")
+                        .takes_value(true)
+                        .value_name("path")
+                        .help("directory where the packages are rebuilt [default: $TMPDIR/nbrepo]"),
+                )
+                .arg(
+                    Arg::with_name("isolated")
+                        .short("i")
+                        .long("isolated")
+                        .takes_value(false)
+                        .help("rebuild inside a chroot containing only the build dependencies"),
+                )
+                .arg(
+                    Arg::with_name("locations")
+                        .help("locations of the packages inside src [default: all the packages]")
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("add")
+                .about("Add or update packages in the index of the repository")
+                .arg(
+                    Arg::with_name("locations")
+                        .help("locations of the packages inside bin, for example: core/foo")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("remove")
+                .about("Remove packages from the index of the repository")
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .takes_value(false)
+                        .help("remove the packages even if other packages depend on them"),
+                )
+                .arg(
+                    Arg::with_name("packages")
+                        .help("names of the packages to remove")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .get_matches();
+
+    // it's safe to call unwrap here, as `repo` has a default value
+    let repo = Path::new(args.value_of("repo").unwrap());
+
+    // ------------ index ------------- //
+    if args.subcommand_matches("index").is_some() {
+        let index_db = match index::generate_index(repo) {
+            Ok(db) => db,
+            Err(e) => exit_with_err(e),
+        };
+        if let Err(e) = index::save_index(repo, &index_db) {
+            exit_with_err(e);
+        }
+        println!(
+            "Generated {} with {} packages",
+            repo.join(REPO_INDEX_PATH).display(),
+            index_db.len()
+        );
+    }
+    // -------------------------------- //
+
+    // ------------ check ------------- //
+    if args.subcommand_matches("check").is_some() {
+        let problems = match check::check_repo(repo) {
+            Ok(p) => p,
+            Err(e) => exit_with_err(e),
+        };
+        if problems.is_empty() {
+            println!("No problems found");
+        } else {
+            println!("Found {} problems:", problems.len());
+            problems.iter().for_each(|p| println!("  - {}", p));
+            exit(1);
+        }
+    }
+    // -------------------------------- //
+
+    // ------------ build ------------- //
+    if let Some(sub_cmd) = args.subcommand_matches("build") {
+        let work_dir = work_dir(sub_cmd);
+        let isolated = isolated_setup(repo, sub_cmd);
+
+        for location in sub_cmd.values_of("locations").unwrap() {
+            println!("[*] Building {}...", location);
+            let res = match &isolated {
+                Some((config, index_db)) => builder::build_pkg_isolated(
+                    repo,
+                    location,
+                    &work_dir,
+                    Isolation::detect(),
+                    config,
+                    index_db,
+                    &DefaultFetcher::new(),
+                ),
+                None => builder::build_pkg(repo, location, &work_dir),
+            };
+            match res {
+                Ok(built) => {
+                    built
+                        .warnings
+                        .iter()
+                        .for_each(|w| eprintln!("Warning: {}", w));
+                    println!("[*] Built {}", built.archive.display());
+                }
+                Err(e) => exit_with_err(e),
+            }
+        }
+    }
+    // -------------------------------- //
+
+    // ------------ verify ------------ //
+    if let Some(sub_cmd) = args.subcommand_matches("verify") {
+        let work_dir = work_dir(sub_cmd);
+        let isolated = isolated_setup(repo, sub_cmd);
+        let locations = match sub_cmd.values_of("locations") {
+            Some(l) => l.map(|l| l.to_string()).collect(),
+            None => match check::find_srcs(repo) {
+                Ok(l) => l,
+                Err(e) => exit_with_err(e),
+            },
+        };
+
+        let mut failed = vec![];
+        for location in &locations {
+            println!("[*] Verifying {}...", location);
+            let res = match &isolated {
+                Some((config, index_db)) => builder::verify_pkg_isolated(
+                    repo,
+                    location,
+                    &work_dir,
+                    Isolation::detect(),
+                    config,
+                    index_db,
+                    &DefaultFetcher::new(),
+                ),
+                None => builder::verify_pkg(repo, location, &work_dir),
+            };
+            match res {
+                Ok(warnings) => {
+                    warnings.iter().for_each(|w| eprintln!("Warning: {}", w));
+                    println!("[*] {} is reproducible", location);
+                }
+                Err(e) => failed.push((location, e)),
+            }
+        }
+        if !failed.is_empty() {
+            println!("{} of {} packages failed:", failed.len(), locations.len());
+            for (location, e) in &failed {
+                println!("  - {}: {}", location, e);
+            }
+            exit(1);
+        }
+    }
+    // -------------------------------- //
+
+    // ------------- add -------------- //
+    if let Some(sub_cmd) = args.subcommand_matches("add") {
+        let locations: Vec<&str> = sub_cmd.values_of("locations").unwrap().collect();
+        if let Err(e) = index::add_pkgs(repo, &locations) {
+            exit_with_err(e);
+        }
+        locations.iter().for_each(|l| println!("Added {}", l));
+    }
+    // -------------------------------- //
+
+    // ------------ remove ------------ //
+    if let Some(sub_cmd) = args.subcommand_matches("remove") {
+        let names: Vec<&str> = sub_cmd.values_of("packages").unwrap().collect();
+        if let Err(e) = index::remove_pkgs(repo, &names, !sub_cmd.is_present("force")) {
+            exit_with_err(e);
+        }
+        names.iter().for_each(|n| println!("Removed {}", n));
+    }
+}
+
+/// Returns the work directory given to the `build` and `verify` subcommands.
+fn work_dir(sub_cmd: &ArgMatches) -> PathBuf {
+    match sub_cmd.value_of("work-dir") {
+        Some(p) => PathBuf::from(p),
+        None => env::temp_dir().join("nbrepo"),
+    }
+}
+
+/// If the `isolated` flag is given, returns the `Config` and the index used to bootstrap the
+/// build environments. Isolated builds install their build dependencies from this same
+/// repository.
+fn isolated_setup(repo: &Path, sub_cmd: &ArgMatches) -> Option<(Config, PkgDb)> {
+    if !sub_cmd.is_present("isolated") {
+        return None;
+    }
+    let index_db = match index::load_index(repo) {
+        Ok(db) => db,
+        Err(e) => exit_with_err(e),
+    };
+    let mut config = Config::new();
+    match fs::canonicalize(repo) {
+        Ok(p) => config.set_repo_url(&p.to_string_lossy()),
+        Err(e) => exit_with_err(Box::new(e)),
+    }
+    Some((config, index_db))
+}
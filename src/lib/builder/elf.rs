@@ -0,0 +1,347 @@
+//! Minimal parser of the dynamic section of ELF files, used to detect the shared libraries
+//! needed and provided by the files of a package.
+
+use std::convert::{TryFrom, TryInto};
+use std::fs;
+use std::path::Path;
+
+use crate::TypeErr;
+
+/// Magic number at the beginning of every ELF file.
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+// program header types
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+// dynamic section tags
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_SONAME: u64 = 14;
+
+/// Shared library information of an ELF file, read from its dynamic section.
+#[derive(Debug, Default, Clone)]
+pub struct ElfDynamic {
+    /// Sonames of the shared libraries the file needs (`DT_NEEDED` entries).
+    pub needed: Vec<String>,
+    /// Soname of the file, if the file is a shared library (`DT_SONAME` entry).
+    pub soname: Option<String>,
+}
+
+/// Reads the dynamic section of the ELF file in `path`. If the file is not an ELF file, is
+/// malformed or has no dynamic section (for example, static binaries), `None` is returned.
+pub fn read_dynamic(path: &Path) -> Result<Option<ElfDynamic>, TypeErr> {
+    let data = fs::read(path)?;
+    Ok(parse_dynamic(&data))
+}
+
+/// Reads integers of an ELF file, with the class (32 or 64 bits) and endianness of the file.
+struct Reader<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    is_le: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&self, off: usize) -> Option<[u8; N]> {
+        self.data.get(off..off.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u16(&self, off: usize) -> Option<u16> {
+        let b = self.bytes::<2>(off)?;
+        Some(if self.is_le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, off: usize) -> Option<u32> {
+        let b = self.bytes::<4>(off)?;
+        Some(if self.is_le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn u64(&self, off: usize) -> Option<u64> {
+        let b = self.bytes::<8>(off)?;
+        Some(if self.is_le {
+            u64::from_le_bytes(b)
+        } else {
+            u64::from_be_bytes(b)
+        })
+    }
+
+    /// Reads an address sized integer: 8 bytes for 64 bit files and 4 bytes for 32 bit files.
+    fn addr(&self, off: usize) -> Option<u64> {
+        if self.is_64 {
+            self.u64(off)
+        } else {
+            self.u32(off).map(u64::from)
+        }
+    }
+
+    /// Reads the null terminated string starting at `off`.
+    fn string(&self, off: usize) -> Option<String> {
+        let bytes = self.data.get(off..)?;
+        let end = bytes.iter().position(|b| *b == 0)?;
+        Some(String::from_utf8_lossy(&bytes[..end]).to_string())
+    }
+}
+
+/// A program header of an ELF file.
+struct Segment {
+    p_type: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+fn parse_dynamic(data: &[u8]) -> Option<ElfDynamic> {
+    if data.get(..4)? != ELF_MAGIC {
+        return None;
+    }
+    let r = Reader {
+        data,
+        is_64: *data.get(4)? == 2,
+        is_le: *data.get(5)? == 1,
+    };
+
+    // read the program headers
+    let (phoff, phentsize, phnum) = if r.is_64 {
+        (r.u64(0x20)?, r.u16(0x36)?, r.u16(0x38)?)
+    } else {
+        (u64::from(r.u32(0x1c)?), r.u16(0x2a)?, r.u16(0x2c)?)
+    };
+    // offsets come from the file, so all the arithmetic is checked to not overflow
+    let phoff = usize::try_from(phoff).ok()?;
+    let mut segments = vec![];
+    for i in 0..usize::from(phnum) {
+        let off = phoff.checked_add(i.checked_mul(usize::from(phentsize))?)?;
+        let at = |delta: usize| off.checked_add(delta);
+        segments.push(if r.is_64 {
+            Segment {
+                p_type: r.u32(off)?,
+                offset: r.u64(at(8)?)?,
+                vaddr: r.u64(at(16)?)?,
+                filesz: r.u64(at(32)?)?,
+            }
+        } else {
+            Segment {
+                p_type: r.u32(off)?,
+                offset: u64::from(r.u32(at(4)?)?),
+                vaddr: u64::from(r.u32(at(8)?)?),
+                filesz: u64::from(r.u32(at(16)?)?),
+            }
+        });
+    }
+
+    // read the entries of the dynamic section
+    let dynamic = segments.iter().find(|s| s.p_type == PT_DYNAMIC)?;
+    let entry_size = if r.is_64 { 16 } else { 8 };
+    let mut strtab = None;
+    let mut needed = vec![];
+    let mut soname = None;
+    let mut off = usize::try_from(dynamic.offset).ok()?;
+    let end = off.checked_add(usize::try_from(dynamic.filesz).ok()?)?;
+    while off.checked_add(entry_size)? <= end {
+        let tag = r.addr(off)?;
+        let val = r.addr(off.checked_add(entry_size / 2)?)?;
+        match tag {
+            DT_NULL => break,
+            DT_NEEDED => needed.push(val),
+            DT_SONAME => soname = Some(val),
+            DT_STRTAB => strtab = Some(val),
+            _ => (),
+        }
+        off += entry_size;
+    }
+
+    // the string table is given as a virtual address, find its offset in the file
+    let strtab = strtab?;
+    let strtab_off = segments
+        .iter()
+        .filter(|s| s.p_type == PT_LOAD)
+        .find(|s| s.vaddr <= strtab && s.vaddr.checked_add(s.filesz).is_some_and(|e| strtab < e))
+        .and_then(|s| (strtab - s.vaddr).checked_add(s.offset))?;
+    let string = |val: u64| r.string(usize::try_from(strtab_off.checked_add(val)?).ok()?);
+
+    Some(ElfDynamic {
+        needed: needed.into_iter().filter_map(string).collect(),
+        soname: match soname {
+            Some(val) => string(val),
+            None => None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Virtual address where the fixtures are loaded.
+    const BASE: u64 = 0x40_0000;
+
+    /// Writes the integer `val` of `size` bytes at `off`.
+    fn put(data: &mut Vec<u8>, off: usize, val: u64, size: usize, le: bool) {
+        if data.len() < off + size {
+            data.resize(off + size, 0);
+        }
+        let bytes = if le {
+            val.to_le_bytes()[..size].to_vec()
+        } else {
+            val.to_be_bytes()[8 - size..].to_vec()
+        };
+        data[off..off + size].copy_from_slice(&bytes);
+    }
+
+    /// Builds a minimal ELF file with a `PT_LOAD` segment covering the whole file and a
+    /// `PT_DYNAMIC` segment with the given `soname` and `needed` entries.
+    fn elf(is_64: bool, le: bool, soname: Option<&str>, needed: &[&str]) -> Vec<u8> {
+        let (ehsize, phentsize, addr) = if is_64 { (64, 56, 8) } else { (52, 32, 4) };
+        let mut data = vec![];
+        data.extend_from_slice(ELF_MAGIC);
+        data.push(if is_64 { 2 } else { 1 });
+        data.push(if le { 1 } else { 2 });
+
+        // string table
+        let mut strtab = vec![0];
+        let mut add_str = |s: &str| {
+            let off = strtab.len() as u64;
+            strtab.extend_from_slice(s.as_bytes());
+            strtab.push(0);
+            off
+        };
+        let mut entries: Vec<(u64, u64)> = needed.iter().map(|n| (DT_NEEDED, add_str(n))).collect();
+        if let Some(soname) = soname {
+            entries.push((DT_SONAME, add_str(soname)));
+        }
+        let dyn_off = ehsize + 2 * phentsize;
+        let dyn_size = (entries.len() + 2) * 2 * addr;
+        let strtab_off = dyn_off + dyn_size;
+        entries.push((DT_STRTAB, BASE + strtab_off as u64));
+        entries.push((DT_NULL, 0));
+        let file_size = (strtab_off + strtab.len()) as u64;
+
+        // ELF header
+        if is_64 {
+            put(&mut data, 0x20, ehsize as u64, 8, le);
+            put(&mut data, 0x36, phentsize as u64, 2, le);
+            put(&mut data, 0x38, 2, 2, le);
+        } else {
+            put(&mut data, 0x1c, ehsize as u64, 4, le);
+            put(&mut data, 0x2a, phentsize as u64, 2, le);
+            put(&mut data, 0x2c, 2, 2, le);
+        }
+        data.resize(ehsize, 0);
+
+        // program headers: type, offset, virtual address and size in the file
+        let segments = [
+            (PT_LOAD, 0, BASE, file_size),
+            (
+                PT_DYNAMIC,
+                dyn_off as u64,
+                BASE + dyn_off as u64,
+                dyn_size as u64,
+            ),
+        ];
+        for (i, (p_type, offset, vaddr, filesz)) in segments.iter().enumerate() {
+            let off = ehsize + i * phentsize;
+            put(&mut data, off, u64::from(*p_type), 4, le);
+            if is_64 {
+                put(&mut data, off + 8, *offset, 8, le);
+                put(&mut data, off + 16, *vaddr, 8, le);
+                put(&mut data, off + 32, *filesz, 8, le);
+            } else {
+                put(&mut data, off + 4, *offset, 4, le);
+                put(&mut data, off + 8, *vaddr, 4, le);
+                put(&mut data, off + 16, *filesz, 4, le);
+            }
+        }
+
+        // dynamic section and string table
+        for (i, (tag, val)) in entries.iter().enumerate() {
+            let off = dyn_off + i * 2 * addr;
+            put(&mut data, off, *tag, addr, le);
+            put(&mut data, off + addr, *val, addr, le);
+        }
+        data.extend_from_slice(&strtab);
+        data
+    }
+
+    fn assert_dynamic(data: &[u8], soname: Option<&str>, needed: &[&str]) {
+        let dynamic = parse_dynamic(data).unwrap();
+        assert_eq!(dynamic.soname.as_deref(), soname);
+        assert_eq!(dynamic.needed, needed);
+    }
+
+    #[test]
+    fn parses_64_bit_little_endian() {
+        let data = elf(true, true, Some("libfoo.so.1"), &["libc.so.6", "libz.so.1"]);
+        assert_dynamic(&data, Some("libfoo.so.1"), &["libc.so.6", "libz.so.1"]);
+    }
+
+    #[test]
+    fn parses_32_bit() {
+        let data = elf(false, true, None, &["libc.so.6"]);
+        assert_dynamic(&data, None, &["libc.so.6"]);
+        let data = elf(false, false, Some("libbar.so.2"), &["libm.so.6"]);
+        assert_dynamic(&data, Some("libbar.so.2"), &["libm.so.6"]);
+    }
+
+    #[test]
+    fn ignores_non_elf_files() {
+        assert!(parse_dynamic(b"").is_none());
+        assert!(parse_dynamic(b"#!/bin/sh\necho hi\n").is_none());
+    }
+
+    #[test]
+    fn truncated_files_do_not_panic() {
+        for is_64 in &[true, false] {
+            let data = elf(*is_64, true, Some("libfoo.so.1"), &["libc.so.6"]);
+            for len in 0..data.len() {
+                // the last byte is the terminator of the last string
+                if let Some(dynamic) = parse_dynamic(&data[..len]) {
+                    assert!(dynamic.soname.is_none());
+                }
+            }
+            // the header is needed to find anything
+            assert!(parse_dynamic(&data[..52]).is_none());
+        }
+    }
+
+    #[test]
+    fn overflowing_offsets_do_not_panic() {
+        let data = elf(true, true, Some("libfoo.so.1"), &["libc.so.6"]);
+        let patch = |off: usize, size: usize| {
+            let mut data = data.clone();
+            put(&mut data, off, u64::MAX, size, true);
+            data
+        };
+        // program headers offset
+        assert!(parse_dynamic(&patch(0x20, 8)).is_none());
+        // offset and size of the dynamic segment
+        assert!(parse_dynamic(&patch(64 + 56 + 8, 8)).is_none());
+        assert!(parse_dynamic(&patch(64 + 56 + 32, 8)).is_none());
+        // address and size of the loaded segment
+        assert!(parse_dynamic(&patch(64 + 16, 8)).is_none());
+        assert!(parse_dynamic(&patch(64 + 32, 8)).is_none());
+        // a string offset (the value of the DT_NEEDED entry)
+        let dynamic = parse_dynamic(&patch(64 + 2 * 56 + 8, 8)).unwrap();
+        assert!(dynamic.needed.is_empty());
+        assert_eq!(dynamic.soname.as_deref(), Some("libfoo.so.1"));
+    }
+
+    #[test]
+    fn read_dynamic_reads_files() {
+        let path = std::env::temp_dir().join(format!("nbkit-test-elf-{}", std::process::id()));
+        fs::write(&path, elf(true, true, None, &["libc.so.6"])).unwrap();
+        let dynamic = read_dynamic(&path).unwrap().unwrap();
+        assert_eq!(dynamic.needed, ["libc.so.6"]);
+        fs::remove_file(&path).unwrap();
+        assert!(read_dynamic(&path).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::BuildError;
+use crate::core::{NbError, PkgDb, Set};
+use crate::fetch::Fetcher;
+use crate::nbpm::{install, Config, Event, Prompt, Ui, DEF_NBPM_PATH, LOCAL_DB_PATH};
+use crate::{utils, Query, TypeErr};
+
+/// Value of the `PATH` environment variable inside the build environments.
+pub const ENV_PATH: &str = "/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Script run in the namespaces of a `BuildEnv` to mount `/proc` and `/dev` in the root (`$1`)
+/// and run the template script (`$2`) inside it.
+const MOUNT_AND_CHROOT: &str =
+    "mount -t proc proc \"$1/proc\" && mount --rbind /dev \"$1/dev\" && \
+                                exec chroot \"$1\" /bin/sh -c \"$2\"";
+
+/// Mechanism used to run the templates inside the root of a `BuildEnv`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Isolation {
+    /// Use `chroot` as the current user, requires running as root.
+    Chroot,
+    /// Use `chroot` inside a new user namespace (created with `unshare`), so root is not needed.
+    UserNamespace,
+}
+
+impl Isolation {
+    /// Selects `Chroot` if the current user is root, and `UserNamespace` otherwise.
+    pub fn detect() -> Isolation {
+        match utils::run_cmd_output("id", &["-u"]) {
+            Ok(uid) if uid.trim() == "0" => Isolation::Chroot,
+            _ => Isolation::UserNamespace,
+        }
+    }
+}
+
+/// A throwaway root directory, containing only the packages needed to build a package, where
+/// templates are run isolated from the build host.
+///
+/// The packages are installed into the root using nbpm's install logic. The local `PkgDb` of the
+/// root is saved inside it (in `etc/nbpm/local_db.toml`, the default nbpm home of the root), so it
+/// is independent from the one of the build host.
+pub struct BuildEnv {
+    root: PathBuf,
+    isolation: Isolation,
+}
+
+impl BuildEnv {
+    /// Creates a new build environment in `root`, and installs the given packages (and their
+    /// dependencies) into it. The packages are resolved from `index_db` and downloaded from the
+    /// repository of `config` using `fetcher`, and the installation is reported to `ui` (no
+    /// confirmation is asked, as the plan is always accepted). If `root` already exists, it is
+    /// deleted first.
+    ///
+    /// # Errors
+    ///
+    /// If the version of a package in `index_db` does not satisfy its requirement in `pkgs`, a
+    /// `BuildError::UnsatisfiedDependency` error is returned before installing anything. For
+    /// errors installing the packages, see `install::install_handler`.
+    ///
+    /// The packages are downloaded to a working directory next to the root (`<root>.nbpm`),
+    /// removed once they are installed, so nbpm's own working directory is not touched.
+    ///
+    /// **NOTE**: Templates are run with the `sh` of the root, so a package providing `sh` must be
+    /// among the installed packages.
+    pub fn bootstrap(
+        root: &Path,
+        pkgs: &[Query],
+        isolation: Isolation,
+        config: &Config,
+        index_db: &PkgDb,
+        fetcher: &dyn Fetcher,
+        ui: &dyn Ui,
+    ) -> Result<BuildEnv, TypeErr> {
+        // the packages of the index are the ones that would be installed
+        for (name, req) in pkgs {
+            if let Some(info) = index_db.get_pkg_info(name) {
+                if !req.matches(info.version()) {
+                    return Err(Box::new(BuildError::UnsatisfiedDependency(
+                        name.to_string(),
+                        req.to_string(),
+                        info.version().to_string(),
+                    )));
+                }
+            }
+        }
+        let mut names: Vec<&str> = pkgs.iter().map(|(name, _)| name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+
+        if root.exists() {
+            fs::remove_dir_all(root)?;
+        }
+        fs::create_dir_all(root)?;
+        let root = fs::canonicalize(root)?;
+        let work_dir = root.with_extension("nbpm");
+
+        // the same configuration, but installing the packages into the new root
+        let mut env_config = config.clone();
+        env_config.set_root(&root.to_string_lossy());
+        env_config.set_work_dir(&work_dir.to_string_lossy());
+
+        let mut local_db = PkgDb::with_set(Set::Local);
+        let res = if names.is_empty() {
+            Ok(())
+        } else {
+            install::install_handler(
+                &names,
+                &env_config,
+                &mut local_db,
+                index_db,
+                fetcher,
+                &AcceptPlan(ui),
+            )
+            .map(|_| ())
+        };
+        if work_dir.is_dir() {
+            fs::remove_dir_all(&work_dir)?;
+        }
+        res?;
+        let db_dir = root.join(DEF_NBPM_PATH.trim_start_matches('/'));
+        fs::create_dir_all(&db_dir)?;
+        local_db.save(&db_dir.join(LOCAL_DB_PATH))?;
+
+        // mount points of /proc and /dev, see `run`
+        fs::create_dir_all(root.join("proc"))?;
+        fs::create_dir_all(root.join("dev"))?;
+        Ok(BuildEnv { root, isolation })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Runs the `script` with the `sh` of the root, with the root as the `/` directory. `dir`
+    /// is the working directory of the script and `envs` the environment variables to set, both
+    /// relative to the root.
+    ///
+    /// The script runs in new mount and PID namespaces, with `/proc` and `/dev` of the host
+    /// mounted in the root. The mounts disappear with the namespaces when the script exits, so
+    /// nothing is left mounted in the root even if the script fails.
+    ///
+    /// # Errors
+    ///
+    /// If the isolation command (`unshare`) cannot be started, a `NbError::CmdStartChild` error
+    /// is returned. If the script fails, a `BuildError::BuildFailed` error is returned.
+    pub fn run(
+        &self,
+        script: &str,
+        dir: &str,
+        envs: &[(&str, &str)],
+        location: &str,
+    ) -> Result<(), TypeErr> {
+        let script = format!("cd {} && {}", dir, script);
+        let root = self.root.to_string_lossy();
+        let mut args = vec!["unshare"];
+        if self.isolation == Isolation::UserNamespace {
+            args.push("--map-root-user");
+        }
+        // the mounts are done with the `sh` of the host, before entering the root
+        args.extend(&[
+            "--mount",
+            "--pid",
+            "--fork",
+            "/bin/sh",
+            "-c",
+            MOUNT_AND_CHROOT,
+            "sh",
+            &root,
+            &script,
+        ]);
+
+        let mut command = Command::new(args[0]);
+        // the PATH is used both to find the isolation command in the host and inside the root
+        command.args(&args[1..]).env_clear().env("PATH", ENV_PATH);
+        for (key, val) in envs {
+            command.env(key, val);
+        }
+        let status = match command.status() {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(Box::new(NbError::CmdStartChild(format!(
+                    "{}: {}",
+                    args[0], e
+                ))))
+            }
+        };
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Box::new(BuildError::BuildFailed(
+                location.to_string(),
+                format!("template exited with {}", status),
+            )))
+        }
+    }
+}
+
+/// Forwards all the events to the wrapped `Ui`, but accepts every prompt without asking it.
+struct AcceptPlan<'a>(&'a dyn Ui);
+
+impl Ui for AcceptPlan<'_> {
+    fn event(&self, event: Event) {
+        self.0.event(event);
+    }
+
+    fn confirm(&self, _prompt: Prompt) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbpm::test_utils::TestEnv;
+    use crate::nbpm::ui::SilentUi;
+    use semver::VersionReq;
+
+    fn query(name: &str, req: &str) -> Query {
+        (name.to_string(), VersionReq::parse(req).unwrap())
+    }
+
+    #[test]
+    fn bootstrap_installs_pkgs_and_saves_local_db() {
+        let mut env = TestEnv::new("bootstrap-saves-db");
+        env.add_pkg("foo", "1.2.0", &[], &["usr/bin/foo"]);
+        let root = env.dir.join("env");
+        let deps = [query("foo", ">=1.0"), query("foo", "<2")];
+
+        let build_env = BuildEnv::bootstrap(
+            &root,
+            &deps,
+            Isolation::UserNamespace,
+            &env.config,
+            &env.index_db(),
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap();
+        let root = build_env.root();
+        assert_eq!(
+            fs::read_to_string(root.join("usr/bin/foo")).unwrap(),
+            "usr/bin/foo 1.2.0"
+        );
+        let local_db = PkgDb::load(&root.join("etc/nbpm").join(LOCAL_DB_PATH)).unwrap();
+        assert_eq!(
+            local_db.get_pkg_info("foo").unwrap().version().to_string(),
+            "1.2.0"
+        );
+    }
+
+    #[test]
+    fn bootstrap_rejects_unsatisfied_dependency() {
+        let mut env = TestEnv::new("bootstrap-unsatisfied");
+        env.add_pkg("foo", "1.2.0", &[], &["usr/bin/foo"]);
+        let root = env.dir.join("env");
+
+        let err = BuildEnv::bootstrap(
+            &root,
+            &[query("foo", ">=2.0")],
+            Isolation::UserNamespace,
+            &env.config,
+            &env.index_db(),
+            &env.fetcher,
+            &SilentUi,
+        )
+        .err()
+        .unwrap();
+        match err.downcast_ref::<BuildError>() {
+            Some(BuildError::UnsatisfiedDependency(name, req, version)) => {
+                assert_eq!(name, "foo");
+                assert_eq!(req, ">=2.0.0");
+                assert_eq!(version, "1.2.0");
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        assert!(env.fetcher.requests().is_empty());
+        assert!(!root.exists());
+    }
+}
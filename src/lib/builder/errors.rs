@@ -0,0 +1,62 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum BuildError {
+    /// A required variable is not set in the template. Contains the location of the package and
+    /// the name of the variable.
+    MissingMetadata(String, String),
+    /// A variable of the template has an invalid value. Contains the location of the package, the
+    /// name of the variable and the cause.
+    InvalidMetadata(String, String, String),
+    /// The build of the package failed. Contains the location of the package and the cause.
+    BuildFailed(String, String),
+    /// A path of the package is outside the staging root directory. Contains the path and the
+    /// staging root.
+    PathOutsideRoot(String, String),
+    /// The rebuilt package is not identical to the published one. Contains the location of the
+    /// package, the published hash and the hash of the rebuilt package.
+    NotReproducible(String, String, String),
+    /// The package needs a shared library provided by a package that is not in its `depends`.
+    /// Contains the location of the package, the needed sonames and the providing package.
+    UndeclaredDependency(String, Vec<String>, String),
+    /// The version of a build dependency in the repository does not satisfy the requirement of
+    /// the template. Contains the name of the dependency, the requirement and the version.
+    UnsatisfiedDependency(String, String, String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            BuildError::MissingMetadata(loc, var) => {
+                write!(f, "Template of {} does not set `{}`", loc, var)
+            }
+            BuildError::InvalidMetadata(loc, var, err) => {
+                write!(f, "Invalid `{}` in template of {}: {}", var, loc, err)
+            }
+            BuildError::BuildFailed(loc, err) => write!(f, "Cannot build {}: {}", loc, err),
+            BuildError::PathOutsideRoot(path, root) => {
+                write!(f, "Path {} is outside the staging root {}", path, root)
+            }
+            BuildError::NotReproducible(loc, expected, actual) => write!(
+                f,
+                "Rebuilt {} does not match the published package: expected hash {}, got {}",
+                loc, expected, actual
+            ),
+            BuildError::UndeclaredDependency(loc, needed, pkg) => write!(
+                f,
+                "{} needs {} from {}, which is not in its depends",
+                loc,
+                needed.join(", "),
+                pkg
+            ),
+            BuildError::UnsatisfiedDependency(name, req, version) => write!(
+                f,
+                "Build dependency {} {} cannot be satisfied, the repository has version {}",
+                name, req, version
+            ),
+        }
+    }
+}
+
+impl Error for BuildError {}
@@ -0,0 +1,414 @@
+//! This module contains the builder of nebula packages. Packages are built from the `template`
+//! of the package in the `src` directory of the repository, and the resulting binary package and
+//! its hash are stored in the matching `bin` directory.
+//!
+//! The build process of a package is the following:
+//!
+//! 1. The sources of the package are copied to a clean build directory.
+//! 2. The `build` function of the `template` is executed inside the build directory, with
+//!    `DESTDIR` pointing to a clean staging directory. For isolated builds, both directories are
+//!    inside a `BuildEnv` and the template is run chrooted in it.
+//! 3. The `nbinfo.toml` of the package is generated from the staged files and the metadata of
+//!    the template (see `Template`). The sonames of the shared libraries in the staged files
+//!    are recorded as the `provides` of the package (see `shlibs`).
+//! 4. The staging directory is packed into `<pkg>.tar.xz` and its hash is written to
+//!    `<pkg>.sha256`.
+//!
+//! Packing is reproducible (see `pack::pack_dir`), and templates are run with
+//! `SOURCE_DATE_EPOCH` set, so packages can be rebuilt and compared with the published ones (see
+//! `verify_pkg`).
+
+use walkdir::WalkDir;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::core::{pkgdb::PkgInfo, wrappers::VersionWrap, InfoLocal, NbError, PkgDb, SetInfo};
+use crate::fetch::Fetcher;
+use crate::nbpm::{ui::SilentUi, Config};
+use crate::repo::index::{load_index, location_pkg_name, pkg_hash_path, read_hash_file};
+use crate::repo::{REPO_BIN_DIR, REPO_PKG_INFO, REPO_SRC_DIR, REPO_TEMPLATE};
+use crate::{utils, Query, TypeErr};
+
+pub mod elf;
+pub mod env;
+pub mod errors;
+pub mod pack;
+pub mod shlibs;
+pub mod template;
+
+pub use env::{BuildEnv, Isolation};
+pub use errors::BuildError;
+pub use shlibs::SonameMap;
+pub use template::Template;
+
+/// Name of the directory (inside the work directory of a package) where the template is run.
+pub const BUILD_DIR: &str = "build";
+
+/// Name of the directory (inside the work directory of a package) where the files of the package
+/// are installed by the template.
+pub const STAGING_DIR: &str = "dest";
+
+/// Name of the directory (inside the work directory of a package) where the root of the
+/// `BuildEnv` of isolated builds is created.
+pub const ENV_ROOT_DIR: &str = "root";
+
+/// Name of the directory (inside the work directory of a package) where packages rebuilt for
+/// verification are written.
+pub const VERIFY_DIR: &str = "verify";
+
+/// A package built by `build_pkg` or `build_pkg_isolated`.
+#[derive(Debug)]
+pub struct BuiltPkg {
+    /// Path to the binary package written to `bin`.
+    pub archive: PathBuf,
+    /// Problems of the package that do not stop the build, for example, needed libraries
+    /// provided by packages not in the `depends` of the template
+    /// (`BuildError::UndeclaredDependency`).
+    pub warnings: Vec<BuildError>,
+}
+
+/// Shell script that sources the template and runs its `build` function.
+const TEMPLATE_BUILD_SCRIPT: &str = "set -e; . ./template; build";
+
+/// Builds the package in `location` (relative to `src`) of the repository in `repo`, running the
+/// template directly on the build host. The package is built inside `work_dir/<pkg>`, which is
+/// cleaned before the build. On success, the written binary package is returned, see `BuiltPkg`.
+///
+/// # Errors
+///
+/// If the package has no `template`, a `NbError::MissingFile` error is returned. For errors
+/// related to the template, see `Template::load`. If the `build` function of the template fails,
+/// a `BuildError::BuildFailed` error is returned.
+pub fn build_pkg(repo: &Path, location: &str, work_dir: &Path) -> Result<BuiltPkg, TypeErr> {
+    let (template, staging) = stage_pkg(repo, location, work_dir)?;
+    pack_staged(
+        repo,
+        location,
+        &template,
+        &staging,
+        &bin_pkg_dir(repo, location),
+    )
+}
+
+/// Same as `build_pkg`, but the template is run isolated inside a `BuildEnv` (created in
+/// `work_dir/<pkg>/root`) that only contains the `makedepends` and `depends` of the package, so
+/// builds do not depend on the packages installed on the build host.
+///
+/// The packages of the build environment are resolved from `index_db` and downloaded from the
+/// repository in `config` using `fetcher` (see `BuildEnv::bootstrap`).
+pub fn build_pkg_isolated(
+    repo: &Path,
+    location: &str,
+    work_dir: &Path,
+    isolation: Isolation,
+    config: &Config,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+) -> Result<BuiltPkg, TypeErr> {
+    let (template, staging) = stage_pkg_isolated(
+        repo, location, work_dir, isolation, config, index_db, fetcher,
+    )?;
+    pack_staged(
+        repo,
+        location,
+        &template,
+        &staging,
+        &bin_pkg_dir(repo, location),
+    )
+}
+
+/// Rebuilds the package in `location` like `build_pkg` does, and checks that the rebuilt package
+/// is byte-identical to the one published in `bin`, comparing its hash with the published
+/// `.sha256` file. The rebuilt package is written to `work_dir/<pkg>/verify`, the published one is
+/// not modified. On success, the warnings of the rebuild are returned (see `BuiltPkg`).
+///
+/// # Errors
+///
+/// If the package has no published hash, a `NbError::MissingFile` error is returned. If the
+/// hashes differ, a `BuildError::NotReproducible` error is returned. Errors of the build itself
+/// are the same as in `build_pkg`.
+pub fn verify_pkg(
+    repo: &Path,
+    location: &str,
+    work_dir: &Path,
+) -> Result<Vec<BuildError>, TypeErr> {
+    let (template, staging) = stage_pkg(repo, location, work_dir)?;
+    verify_staged(repo, location, &template, &staging, work_dir)
+}
+
+/// Same as `verify_pkg`, but the package is rebuilt like `build_pkg_isolated` does.
+pub fn verify_pkg_isolated(
+    repo: &Path,
+    location: &str,
+    work_dir: &Path,
+    isolation: Isolation,
+    config: &Config,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+) -> Result<Vec<BuildError>, TypeErr> {
+    let (template, staging) = stage_pkg_isolated(
+        repo, location, work_dir, isolation, config, index_db, fetcher,
+    )?;
+    verify_staged(repo, location, &template, &staging, work_dir)
+}
+
+/// Runs the template of the package in `location` on the build host, and returns the template
+/// and the staging directory containing the files of the package.
+fn stage_pkg(repo: &Path, location: &str, work_dir: &Path) -> Result<(Template, PathBuf), TypeErr> {
+    let template = load_template(repo, location)?;
+    let pkg_work = clean_pkg_work(work_dir, location)?;
+
+    let build_dir = pkg_work.join(BUILD_DIR);
+    let staging = pkg_work.join(STAGING_DIR);
+    copy_dir(&repo.join(REPO_SRC_DIR).join(location), &build_dir)?;
+    fs::create_dir_all(&staging)?;
+
+    run_template(&build_dir, &staging, location)?;
+    Ok((template, staging))
+}
+
+/// Same as `stage_pkg`, but the template is run isolated inside a `BuildEnv`.
+fn stage_pkg_isolated(
+    repo: &Path,
+    location: &str,
+    work_dir: &Path,
+    isolation: Isolation,
+    config: &Config,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+) -> Result<(Template, PathBuf), TypeErr> {
+    let template = load_template(repo, location)?;
+    let pkg_work = clean_pkg_work(work_dir, location)?;
+
+    // install the build dependencies in a new root
+    let deps: Vec<Query> = template
+        .makedepends()
+        .iter()
+        .chain(template.depends().iter())
+        .cloned()
+        .collect();
+    let env = BuildEnv::bootstrap(
+        &pkg_work.join(ENV_ROOT_DIR),
+        &deps,
+        isolation,
+        config,
+        index_db,
+        fetcher,
+        &SilentUi,
+    )?;
+
+    // the build and staging directories are inside the root of the environment
+    let build_dir = env.root().join(BUILD_DIR);
+    let staging = env.root().join(STAGING_DIR);
+    copy_dir(&repo.join(REPO_SRC_DIR).join(location), &build_dir)?;
+    fs::create_dir_all(&staging)?;
+
+    let build_dir_env = format!("/{}", BUILD_DIR);
+    let staging_env = format!("/{}", STAGING_DIR);
+    let epoch = pack::pack_mtime()?.to_string();
+    env.run(
+        TEMPLATE_BUILD_SCRIPT,
+        &build_dir_env,
+        &[("DESTDIR", &staging_env), (pack::SOURCE_DATE_EPOCH, &epoch)],
+        location,
+    )?;
+    Ok((template, staging))
+}
+
+/// Packs the `staging` directory of the package in `location` into `work_dir/<pkg>/verify`, and
+/// compares the hash of the result with the published hash of the package.
+fn verify_staged(
+    repo: &Path,
+    location: &str,
+    template: &Template,
+    staging: &Path,
+    work_dir: &Path,
+) -> Result<Vec<BuildError>, TypeErr> {
+    let hash_path = pkg_hash_path(repo, location);
+    if !hash_path.is_file() {
+        return Err(Box::new(NbError::MissingFile(
+            hash_path.display().to_string(),
+        )));
+    }
+    let expected = read_hash_file(&hash_path)?;
+
+    let verify_dir = work_dir.join(location_pkg_name(location)).join(VERIFY_DIR);
+    let built = pack_staged(repo, location, template, staging, &verify_dir)?;
+    let actual = utils::file2hash(&built.archive)?;
+    if expected != actual {
+        return Err(Box::new(BuildError::NotReproducible(
+            location.to_string(),
+            expected,
+            actual,
+        )));
+    }
+    Ok(built.warnings)
+}
+
+/// Returns the directory of the `bin` directory of the repository where the package in
+/// `location` is published.
+fn bin_pkg_dir(repo: &Path, location: &str) -> PathBuf {
+    repo.join(REPO_BIN_DIR).join(location)
+}
+
+/// Loads the `Template` of the package in `location`.
+fn load_template(repo: &Path, location: &str) -> Result<Template, TypeErr> {
+    let template_path = repo.join(REPO_SRC_DIR).join(location).join(REPO_TEMPLATE);
+    if !template_path.is_file() {
+        return Err(Box::new(NbError::MissingFile(
+            template_path.display().to_string(),
+        )));
+    }
+    Template::load(&template_path, location)
+}
+
+/// Creates a clean work directory for the package in `location` inside `work_dir`, and returns
+/// its path.
+fn clean_pkg_work(work_dir: &Path, location: &str) -> Result<PathBuf, TypeErr> {
+    let pkg_work = work_dir.join(location_pkg_name(location));
+    if pkg_work.exists() {
+        fs::remove_dir_all(&pkg_work)?;
+    }
+    fs::create_dir_all(&pkg_work)?;
+    Ok(pkg_work)
+}
+
+/// Generates the `nbinfo.toml` of the package from the files in `staging` and the metadata of the
+/// `template`, and packs the `staging` directory into `pkg_dir` (see `pack::pack_pkg`). Returns
+/// the written binary package.
+fn pack_staged(
+    repo: &Path,
+    location: &str,
+    template: &Template,
+    staging: &Path,
+    pkg_dir: &Path,
+) -> Result<BuiltPkg, TypeErr> {
+    let name = location_pkg_name(location);
+    // generate the info file of the package from the staged files and directories
+    let paths = pack::staged_paths(staging)?;
+    let libs = shlibs::scan_paths(staging, &paths)?;
+    let mut info = PkgInfo::from(
+        VersionWrap::from(template.version().clone()),
+        template.depends_wrap(),
+        template.description().to_string(),
+        Some(SetInfo::Local(InfoLocal::from(paths.clone()))),
+    );
+    info.set_arch(Some(template.arch().to_string()));
+    info.set_provides(libs.provides.into_iter().collect());
+
+    // needed libraries provided by packages that are not in the depends of the template
+    let sonames = SonameMap::from_pkgdb(&load_index(repo)?);
+    let (providers, _) = sonames.resolve(name, &libs.needed);
+    let warnings = providers
+        .into_iter()
+        .filter(|(pkg, _)| !template.depends().iter().any(|(dep, _)| dep == pkg))
+        .map(|(pkg, needed)| BuildError::UndeclaredDependency(location.to_string(), needed, pkg))
+        .collect();
+    let mut info_map = HashMap::new();
+    info_map.insert(name.to_string(), info);
+    fs::write(staging.join(REPO_PKG_INFO), toml::to_string(&info_map)?)?;
+
+    let archive = pack::pack_pkg(staging, &paths, pkg_dir, name)?;
+    Ok(BuiltPkg { archive, warnings })
+}
+
+/// Runs the `build` function of the template in `build_dir`, with `DESTDIR` pointing to
+/// `staging`. The output of the template is not captured.
+fn run_template(build_dir: &Path, staging: &Path, location: &str) -> Result<(), TypeErr> {
+    let staging = fs::canonicalize(staging)?;
+    let status = match Command::new("sh")
+        .args(["-c", TEMPLATE_BUILD_SCRIPT])
+        .current_dir(build_dir)
+        .env("DESTDIR", &staging)
+        .env(pack::SOURCE_DATE_EPOCH, pack::pack_mtime()?.to_string())
+        .status()
+    {
+        Ok(s) => s,
+        Err(e) => return Err(Box::new(NbError::CmdStartChild(format!("sh: {}", e)))),
+    };
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Box::new(BuildError::BuildFailed(
+            location.to_string(),
+            format!("template exited with {}", status),
+        )))
+    }
+}
+
+/// Recursively copies the contents of the `from` directory into the `to` directory.
+pub fn copy_dir(from: &Path, to: &Path) -> Result<(), TypeErr> {
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        let dest = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&dest)?;
+        } else {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::REPO_INDEX_PATH;
+
+    use std::env;
+
+    /// Creates a repository in a temporary directory for the test `name`, with an empty index and
+    /// the package `core/foo`, whose template installs its `contents` file.
+    fn test_repo(name: &str) -> PathBuf {
+        let repo = env::temp_dir().join(format!("nbkit-test-{}-{}", name, std::process::id()));
+        if repo.exists() {
+            fs::remove_dir_all(&repo).unwrap();
+        }
+        let src = repo.join(REPO_SRC_DIR).join("core/foo");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(repo.join(REPO_INDEX_PATH), "set = 'universe'\n").unwrap();
+        fs::write(
+            src.join(REPO_TEMPLATE),
+            "version=\"1.0.0\"\ndescription=\"Foo\"\n\nbuild() {\n    mkdir -p \"$DESTDIR/usr/share\"\n    cp contents \"$DESTDIR/usr/share/foo\"\n}\n",
+        )
+        .unwrap();
+        fs::write(src.join("contents"), "foo 1").unwrap();
+        repo
+    }
+
+    #[test]
+    fn verify_pkg_accepts_identical_rebuild() {
+        let repo = test_repo("verify-same");
+        let work = repo.join("work");
+        build_pkg(&repo, "core/foo", &work).unwrap();
+        let warnings = verify_pkg(&repo, "core/foo", &work).unwrap();
+        assert!(warnings.is_empty());
+        fs::remove_dir_all(&repo).unwrap();
+    }
+
+    #[test]
+    fn verify_pkg_rejects_different_rebuild() {
+        let repo = test_repo("verify-different");
+        let work = repo.join("work");
+        let built = build_pkg(&repo, "core/foo", &work).unwrap();
+        let published = utils::file2hash(&built.archive).unwrap();
+
+        // the sources changed after the package was published
+        fs::write(repo.join(REPO_SRC_DIR).join("core/foo/contents"), "foo 2").unwrap();
+        let err = verify_pkg(&repo, "core/foo", &work).unwrap_err();
+        match err.downcast_ref::<BuildError>() {
+            Some(BuildError::NotReproducible(loc, expected, actual)) => {
+                assert_eq!(loc, "core/foo");
+                assert_eq!(*expected, published);
+                assert_ne!(actual, expected);
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        // the published package is not modified
+        assert_eq!(utils::file2hash(&built.archive).unwrap(), published);
+        fs::remove_dir_all(&repo).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use walkdir::WalkDir;
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::BuildError;
+use crate::core::NbError;
+use crate::repo::{REPO_HASH_EXT, REPO_PKG_EXT, REPO_PKG_INFO};
+use crate::{utils, TypeErr};
+
+/// Default modification time (seconds since the Unix epoch) set to all the entries of the
+/// archives created by `pack_dir`, so archives do not depend on when the files were staged. It
+/// can be overridden with the `SOURCE_DATE_EPOCH` environment variable, see `pack_mtime`.
+pub const PACK_MTIME: u64 = 0;
+
+/// Environment variable with the timestamp used for reproducible builds, see
+/// <https://reproducible-builds.org/specs/source-date-epoch/>.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Compression program (and level) used by `pack_dir`. The level and the number of threads are
+/// fixed, as both change the compressed output.
+const PACK_COMPRESSOR: &str = "xz -6 -T1";
+
+/// Returns the modification time set to the entries of the archives created by `pack_dir`: the
+/// value of `SOURCE_DATE_EPOCH` if set, `PACK_MTIME` otherwise.
+///
+/// # Errors
+///
+/// If `SOURCE_DATE_EPOCH` is not a valid number of seconds, an error is returned.
+pub fn pack_mtime() -> Result<u64, TypeErr> {
+    match env::var(SOURCE_DATE_EPOCH) {
+        Ok(epoch) => match epoch.trim().parse::<u64>() {
+            Ok(e) => Ok(e),
+            Err(e) => Err(format!("Invalid {} {:?}: {}", SOURCE_DATE_EPOCH, epoch, e).into()),
+        },
+        Err(_) => Ok(PACK_MTIME),
+    }
+}
+
+/// Returns the paths (relative to `root`) of all the files and directories inside the `root`
+/// directory, sorted. The `root` directory itself is not included.
+pub fn staged_paths(root: &Path) -> Result<Vec<String>, TypeErr> {
+    staged_target_paths(root, &["."])
+}
+
+/// Returns the paths (relative to `root`) of the given `targets` (relative to `root` too, even if
+/// absolute), sorted.
+/// If a target is a directory, all its contents are included. The directories containing the
+/// targets are also included (except `root` itself), so they can be removed when the package is
+/// removed, if empty.
+///
+/// # Errors
+///
+/// If a target does not exist, a `NbError::MissingFile` error is returned. If a target is outside
+/// `root` (for example, `../foo`), a `BuildError::PathOutsideRoot` error is returned.
+pub fn staged_target_paths(root: &Path, targets: &[&str]) -> Result<Vec<String>, TypeErr> {
+    let root = fs::canonicalize(root)?;
+    let mut paths = BTreeSet::new();
+    for target in targets {
+        // absolute targets are also considered relative to `root`
+        let path = root.join(target.trim_start_matches('/'));
+        if !path.exists() {
+            return Err(Box::new(NbError::MissingFile(path.display().to_string())));
+        }
+        let path = fs::canonicalize(&path)?;
+        if !path.starts_with(&root) {
+            return Err(Box::new(BuildError::PathOutsideRoot(
+                target.to_string(),
+                root.display().to_string(),
+            )));
+        }
+
+        // directories containing the target
+        for ancestor in path.ancestors().skip(1) {
+            match ancestor.strip_prefix(&root) {
+                Ok(rel) if rel.as_os_str().is_empty() => break,
+                Ok(rel) => paths.insert(rel.to_string_lossy().to_string()),
+                Err(_) => break,
+            };
+        }
+        // the target and all its contents
+        for entry in WalkDir::new(&path) {
+            let rel = entry?.path().strip_prefix(&root)?.to_path_buf();
+            if !rel.as_os_str().is_empty() {
+                paths.insert(rel.to_string_lossy().to_string());
+            }
+        }
+    }
+    Ok(paths.into_iter().collect())
+}
+
+/// Packs the `paths` (relative to `staging`, as returned by `staged_target_paths`) and the
+/// `nbinfo.toml` of the `staging` directory as the package `name`, writing
+/// `pkg_dir/<name>.tar.xz` and its hash in `pkg_dir/<name>.sha256` (the layout of the `bin`
+/// directory of repositories). Files of `staging` that are not in `paths` are not packed. The
+/// `pkg_dir` directory is created if missing. Returns the path to the written archive.
+///
+/// **NOTE**: The `nbinfo.toml` of the package must be already written in `staging`.
+pub fn pack_pkg(
+    staging: &Path,
+    paths: &[String],
+    pkg_dir: &Path,
+    name: &str,
+) -> Result<PathBuf, TypeErr> {
+    fs::create_dir_all(pkg_dir)?;
+    let archive = pkg_dir.join(format!("{}.{}", name, REPO_PKG_EXT));
+    let mut entries = paths.to_vec();
+    entries.push(REPO_PKG_INFO.to_string());
+    pack_dir(staging, &entries, &archive)?;
+    write_hash(
+        &archive,
+        &pkg_dir.join(format!("{}.{}", name, REPO_HASH_EXT)),
+    )?;
+    Ok(archive)
+}
+
+/// Compresses the `entries` of the `staging` directory (paths relative to it) into the `archive`
+/// (a `.tar.xz` file). Directories are not packed recursively: only the listed entries are
+/// packed, so the contents of a directory must be listed too. The entries are stored relative to
+/// the root of the archive.
+///
+/// Archives are reproducible: packing the same entries always gives a byte-identical archive.
+/// Entries are sorted by name, owned by root (uid and gid 0), readable by everyone but writable
+/// only by the owner (so the umask of the build does not matter), and have `pack_mtime` as
+/// modification time, and the archive is always compressed with the same level.
+pub fn pack_dir(staging: &Path, entries: &[String], archive: &Path) -> Result<(), TypeErr> {
+    let mut entries = entries.to_vec();
+    entries.sort();
+    entries.dedup();
+
+    // the list of entries is passed in a file, as it might not fit in the command line
+    let list_path = archive.with_extension("list");
+    let mut list = vec![];
+    for entry in &entries {
+        list.extend_from_slice(entry.as_bytes());
+        list.push(0);
+    }
+    fs::write(&list_path, list)?;
+
+    let staging_str = staging.to_string_lossy();
+    let archive_str = archive.to_string_lossy();
+    let list_str = list_path.to_string_lossy();
+    let mtime = format!("--mtime=@{}", pack_mtime()?);
+    let compressor = format!("--use-compress-program={}", PACK_COMPRESSOR);
+    let args = vec![
+        "--format=gnu",
+        "--no-recursion",
+        &mtime,
+        "--owner=0",
+        "--group=0",
+        "--numeric-owner",
+        "--mode=u+rw,go+rX,go-w",
+        &compressor,
+        "-C",
+        &staging_str,
+        "-cf",
+        &archive_str,
+        "--null",
+        "--verbatim-files-from",
+        "-T",
+        &list_str,
+    ];
+    let res = utils::run_cmd("tar", &args);
+    fs::remove_file(&list_path)?;
+    res
+}
+
+/// Computes the SHA256 hash of the `archive` and writes it in `hash_path`.
+pub fn write_hash(archive: &Path, hash_path: &Path) -> Result<(), TypeErr> {
+    let hash = utils::file2hash(archive)?;
+    fs::write(hash_path, format!("{}\n", hash))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an empty temporary directory for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("nbkit-test-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Stages the same tree in `staging` with the given `umask`, creating the files in the order
+    /// of `files` and setting their modification time to `mtime`.
+    fn stage(staging: &Path, umask: &str, files: &[&str], mtime: &str) {
+        let mut script = format!("umask {} && mkdir -p usr/bin usr/share", umask);
+        for file in files {
+            script.push_str(&format!(" && echo {0} > {0}", file));
+        }
+        script.push_str(&format!(
+            " && chmod +x usr/bin/tool && touch -d @{} usr usr/bin usr/share {}",
+            mtime,
+            files.join(" ")
+        ));
+        fs::create_dir_all(staging).unwrap();
+        let staging_str = staging.to_string_lossy();
+        utils::run_cmd("sh", &["-c", &format!("cd {} && {}", staging_str, script)]).unwrap();
+    }
+
+    #[test]
+    fn pack_dir_is_reproducible() {
+        let dir = temp_dir("pack-reproducible");
+        let files = ["usr/bin/tool", "usr/share/a", "usr/share/b"];
+        let reversed = ["usr/share/b", "usr/share/a", "usr/bin/tool"];
+        stage(&dir.join("one"), "022", &files, "1000000000");
+        stage(&dir.join("two"), "077", &reversed, "1600000000");
+
+        let entries = staged_paths(&dir.join("one")).unwrap();
+        assert_eq!(entries, staged_paths(&dir.join("two")).unwrap());
+        pack_dir(&dir.join("one"), &entries, &dir.join("one.tar.xz")).unwrap();
+        // the order of the entries does not matter either
+        let mut shuffled = entries.clone();
+        shuffled.reverse();
+        pack_dir(&dir.join("two"), &shuffled, &dir.join("two.tar.xz")).unwrap();
+
+        assert_eq!(
+            utils::file2hash(&dir.join("one.tar.xz")).unwrap(),
+            utils::file2hash(&dir.join("two.tar.xz")).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pack_dir_normalizes_modes_and_owners() {
+        let dir = temp_dir("pack-modes");
+        let staging = dir.join("staging");
+        stage(&staging, "077", &["usr/bin/tool", "usr/share/a"], "0");
+        // world writable files are not packed as writable
+        let shared = staging.join("usr/share/b");
+        fs::write(&shared, "b").unwrap();
+        utils::run_cmd("chmod", &["666", &shared.to_string_lossy()]).unwrap();
+
+        let archive = dir.join("pkg.tar.xz");
+        pack_dir(&staging, &staged_paths(&staging).unwrap(), &archive).unwrap();
+        let listing = utils::run_cmd_output(
+            "tar",
+            &["--numeric-owner", "-tvJf", &archive.to_string_lossy()],
+        )
+        .unwrap();
+
+        let mode_of = |path: &str| -> String {
+            let line = listing
+                .lines()
+                .find(|l| l.trim_end_matches('/').ends_with(&format!(" {}", path)))
+                .unwrap_or_else(|| panic!("{} not in archive:\n{}", path, listing));
+            assert!(line.contains(" 0/0 "), "{}", line);
+            line.split_whitespace().next().unwrap().to_string()
+        };
+        assert_eq!(mode_of("usr"), "drwxr-xr-x");
+        assert_eq!(mode_of("usr/bin/tool"), "-rwxr-xr-x");
+        assert_eq!(mode_of("usr/share/a"), "-rw-r--r--");
+        assert_eq!(mode_of("usr/share/b"), "-rw-r--r--");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pack_dir_packs_only_the_entries() {
+        let dir = temp_dir("pack-entries");
+        let staging = dir.join("staging");
+        stage(&staging, "022", &["usr/bin/tool", "usr/share/a"], "0");
+
+        let archive = dir.join("pkg.tar.xz");
+        let entries = vec![
+            "usr".to_string(),
+            "usr/share".to_string(),
+            "usr/share/a".to_string(),
+        ];
+        pack_dir(&staging, &entries, &archive).unwrap();
+        let listing = utils::run_cmd_output("tar", &["-tJf", &archive.to_string_lossy()]).unwrap();
+        let packed: Vec<&str> = listing.lines().map(|l| l.trim_end_matches('/')).collect();
+        assert_eq!(packed, vec!["usr", "usr/share", "usr/share/a"]);
+        assert!(!archive.with_extension("list").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
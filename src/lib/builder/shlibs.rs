@@ -0,0 +1,188 @@
+//! Detection of the shared library dependencies of packages.
+//!
+//! The ELF files of a package are scanned (see `elf::read_dynamic`) to collect the sonames of the
+//! shared libraries the package needs and the ones it provides. The needed sonames are then
+//! mapped to the packages providing them using a `SonameMap`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use super::elf;
+use crate::core::{PkgDb, SetInfo};
+use crate::TypeErr;
+
+/// Shared libraries needed and provided by the files of a package.
+#[derive(Debug, Default)]
+pub struct SharedLibs {
+    /// Sonames needed by the files of the package, excluding the ones the package provides.
+    pub needed: BTreeSet<String>,
+    /// Sonames provided by the package.
+    pub provides: BTreeSet<String>,
+}
+
+/// Scans the given `paths` (relative to `root`) for ELF files and collects the shared libraries
+/// they need and provide. Directories, symlinks and non ELF files are skipped.
+pub fn scan_paths(root: &Path, paths: &[String]) -> Result<SharedLibs, TypeErr> {
+    let mut libs = SharedLibs::default();
+    let mut needed = BTreeSet::new();
+    for path in paths {
+        let path = root.join(path);
+        // symlinks are skipped, as they might point outside the package
+        if !fs::symlink_metadata(&path)?.file_type().is_file() {
+            continue;
+        }
+        if let Some(dynamic) = elf::read_dynamic(&path)? {
+            needed.extend(dynamic.needed);
+            libs.provides.extend(dynamic.soname);
+        }
+    }
+    libs.needed = needed.difference(&libs.provides).cloned().collect();
+    Ok(libs)
+}
+
+/// Map from sonames to the names of the packages providing them.
+#[derive(Debug, Default)]
+pub struct SonameMap {
+    map: HashMap<String, String>,
+}
+
+impl SonameMap {
+    pub fn new() -> SonameMap {
+        SonameMap::default()
+    }
+
+    /// Creates a `SonameMap` from the packages of a `PkgDb`. The sonames each package `provides`
+    /// are used and, for local packages, the file names of their paths too (so packages with no
+    /// `provides` are also found).
+    pub fn from_pkgdb(db: &PkgDb) -> SonameMap {
+        let mut map = SonameMap::new();
+        for (name, info) in db.iter() {
+            if let Some(SetInfo::Local(local)) = info.set_info() {
+                for path in local.paths() {
+                    if let Some(file_name) = Path::new(path).file_name() {
+                        let file_name = file_name.to_string_lossy();
+                        if file_name.contains(".so") {
+                            map.map.insert(file_name.to_string(), name.to_string());
+                        }
+                    }
+                }
+            }
+            for soname in info.provides() {
+                map.map.insert(soname.to_string(), name.to_string());
+            }
+        }
+        map
+    }
+
+    /// Loads a `SonameMap` from a toml file, where every key is a soname and its value the name
+    /// of the package providing it, for example: `"libz.so.1" = "zlib"`.
+    pub fn load(path: &Path) -> Result<SonameMap, TypeErr> {
+        let map_str = fs::read_to_string(path)?;
+        Ok(SonameMap {
+            map: toml::from_str::<HashMap<String, String>>(&map_str)?,
+        })
+    }
+
+    /// Adds all the entries of `other` to the map. The entries of `other` take precedence.
+    pub fn extend(&mut self, other: SonameMap) {
+        self.map.extend(other.map);
+    }
+
+    /// Returns the name of the package providing `soname`, if known.
+    pub fn get(&self, soname: &str) -> Option<&str> {
+        self.map.get(soname).map(|s| s.as_str())
+    }
+
+    /// Maps the `needed` sonames of the package `pkg_name` to the packages providing them.
+    /// Returns the providing packages (with the sonames each one provides) and the sonames with
+    /// no known provider. Sonames provided by `pkg_name` itself are ignored.
+    pub fn resolve(
+        &self,
+        pkg_name: &str,
+        needed: &BTreeSet<String>,
+    ) -> (BTreeMap<String, Vec<String>>, Vec<String>) {
+        let mut pkgs = BTreeMap::new();
+        let mut unresolved = vec![];
+        for soname in needed {
+            match self.get(soname) {
+                Some(pkg) if pkg == pkg_name => (),
+                Some(pkg) => pkgs
+                    .entry(pkg.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(soname.to_string()),
+                None => unresolved.push(soname.to_string()),
+            }
+        }
+        (pkgs, unresolved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sonames(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn test_map() -> SonameMap {
+        let db: PkgDb = toml::from_str(
+            "set = 'local'\n\
+             [zlib]\nversion = '1.2.11'\ndescription = 'zlib'\n\
+             [zlib.local]\npaths = ['usr/lib', 'usr/lib/libz.so.1', 'usr/lib/libz.so.1.2.11']\n\
+             [glibc]\nversion = '2.31.0'\ndescription = 'glibc'\nprovides = ['libc.so.6', 'libm.so.6']\n\
+             [glibc.local]\npaths = ['usr/lib']\n",
+        )
+        .unwrap();
+        SonameMap::from_pkgdb(&db)
+    }
+
+    #[test]
+    fn from_pkgdb_uses_provides_and_paths() {
+        let map = test_map();
+        assert_eq!(map.get("libz.so.1"), Some("zlib"));
+        assert_eq!(map.get("libc.so.6"), Some("glibc"));
+        assert_eq!(map.get("libm.so.6"), Some("glibc"));
+        assert_eq!(map.get("libfoo.so.1"), None);
+    }
+
+    #[test]
+    fn resolve_groups_sonames_by_pkg() {
+        let needed = sonames(&["libc.so.6", "libm.so.6", "libz.so.1", "libfoo.so.1"]);
+        let (pkgs, unresolved) = test_map().resolve("foo", &needed);
+
+        let mut expected = BTreeMap::new();
+        expected.insert(
+            "glibc".to_string(),
+            vec!["libc.so.6".to_string(), "libm.so.6".to_string()],
+        );
+        expected.insert("zlib".to_string(), vec!["libz.so.1".to_string()]);
+        assert_eq!(pkgs, expected);
+        assert_eq!(unresolved, ["libfoo.so.1"]);
+    }
+
+    #[test]
+    fn resolve_ignores_own_sonames() {
+        let (pkgs, unresolved) = test_map().resolve("zlib", &sonames(&["libz.so.1"]));
+        assert!(pkgs.is_empty());
+        assert!(unresolved.is_empty());
+    }
+
+    #[test]
+    fn extend_takes_precedence() {
+        let path = std::env::temp_dir().join(format!("nbkit-test-sonames-{}", std::process::id()));
+        fs::write(
+            &path,
+            "\"libz.so.1\" = \"zlib-ng\"\n\"libfoo.so.1\" = \"foo\"\n",
+        )
+        .unwrap();
+        let mut map = test_map();
+        map.extend(SonameMap::load(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(map.get("libz.so.1"), Some("zlib-ng"));
+        assert_eq!(map.get("libfoo.so.1"), Some("foo"));
+        assert_eq!(map.get("libc.so.6"), Some("glibc"));
+    }
+}
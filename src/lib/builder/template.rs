@@ -0,0 +1,153 @@
+use semver::Version;
+
+use std::path::Path;
+
+use super::BuildError;
+use crate::core::wrappers::DependencyWrap;
+use crate::{utils, Query, TypeErr, HOST_ARCH};
+
+/// Variables read from the templates, in the order they are printed by `TEMPLATE_VARS_SCRIPT`.
+const TEMPLATE_VARS: [&str; 5] = ["version", "description", "depends", "makedepends", "arch"];
+
+/// Shell script that sources the template given as first argument and prints the value of the
+/// `TEMPLATE_VARS`, separated by null characters.
+const TEMPLATE_VARS_SCRIPT: &str =
+    r#". "$1" && printf '%s\0' "$version" "$description" "$depends" "$makedepends" "$arch""#;
+
+/// Metadata of a package, read from the variables set by its `template` script.
+///
+/// Templates are shell scripts that set the following variables and define a `build` function,
+/// that installs the files of the package into `$DESTDIR`:
+///
+/// - `version` (required): the semver formatted version of the package.
+/// - `description` (required): brief description of the package.
+/// - `depends`: list of dependencies, one per line (a multi-line string), as version
+///   requirements may contain spaces. For example: `glibc` and `linux>=5.5, <6`.
+/// - `makedepends`: list of the packages needed to build the package, one per line.
+/// - `arch`: architecture of the package, `any` for architecture independent packages. By
+///   default, the architecture of the build host.
+#[derive(Debug, Clone)]
+pub struct Template {
+    version: Version,
+    description: String,
+    depends: Vec<Query>,
+    makedepends: Vec<Query>,
+    arch: String,
+}
+
+impl Template {
+    /// Reads the metadata of the template in `path`. The template is sourced by `sh`, so the
+    /// template must not run any command outside its `build` function. `location` is only used
+    /// for error reporting.
+    ///
+    /// # Errors
+    ///
+    /// If `version` or `description` are not set, a `BuildError::MissingMetadata` error is
+    /// returned. If any variable cannot be parsed, a `BuildError::InvalidMetadata` error is
+    /// returned.
+    pub fn load(path: &Path, location: &str) -> Result<Template, TypeErr> {
+        let path_str = path.to_string_lossy();
+        let output = utils::run_cmd_output("sh", &["-c", TEMPLATE_VARS_SCRIPT, "sh", &path_str])?;
+        let values: Vec<&str> = output.split('\0').collect();
+        // get the value of a variable by its name
+        let value = |var: &str| {
+            TEMPLATE_VARS
+                .iter()
+                .position(|v| *v == var)
+                .and_then(|i| values.get(i))
+                .map(|v| v.trim())
+                .unwrap_or("")
+        };
+
+        for var in &["version", "description"] {
+            if value(var).is_empty() {
+                return Err(Box::new(BuildError::MissingMetadata(
+                    location.to_string(),
+                    var.to_string(),
+                )));
+            }
+        }
+
+        let version = match Version::parse(value("version")) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(Box::new(BuildError::InvalidMetadata(
+                    location.to_string(),
+                    "version".to_string(),
+                    e.to_string(),
+                )))
+            }
+        };
+        Ok(Template {
+            version,
+            description: value("description").to_string(),
+            depends: parse_deps(value("depends"), "depends", location)?,
+            makedepends: parse_deps(value("makedepends"), "makedepends", location)?,
+            arch: match value("arch") {
+                "" => HOST_ARCH.to_string(),
+                a => a.to_string(),
+            },
+        })
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn depends(&self) -> &Vec<Query> {
+        &self.depends
+    }
+
+    pub fn makedepends(&self) -> &Vec<Query> {
+        &self.makedepends
+    }
+
+    pub fn arch(&self) -> &str {
+        &self.arch
+    }
+
+    /// Returns the dependencies of the package as they are stored in a `PkgInfo`.
+    pub fn depends_wrap(&self) -> Option<Vec<DependencyWrap>> {
+        if self.depends.is_empty() {
+            None
+        } else {
+            Some(
+                self.depends
+                    .iter()
+                    .map(|q| DependencyWrap::from(q.clone()))
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Parses a list of dependencies with one dependency per line (see `utils::parse_pkg_str_info`).
+/// Empty lines are ignored.
+fn parse_deps(list: &str, var: &str, location: &str) -> Result<Vec<Query>, TypeErr> {
+    let invalid = |e: String| {
+        Box::new(BuildError::InvalidMetadata(
+            location.to_string(),
+            var.to_string(),
+            e,
+        ))
+    };
+    let mut deps = vec![];
+    for dep in list.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        match utils::parse_pkg_str_info(dep) {
+            // names cannot contain spaces, the line probably has more than one dependency
+            Ok((name, _)) if name.contains(char::is_whitespace) => {
+                return Err(invalid(format!(
+                    "{:?} is not a single dependency, write one dependency per line",
+                    dep
+                )))
+            }
+            Ok(q) => deps.push(q),
+            Err(e) => return Err(invalid(e.to_string())),
+        }
+    }
+    Ok(deps)
+}
@@ -10,6 +10,8 @@ pub enum NbError {
     // ----------- IO ----------- //
     /// Contains the name of the missing file.
     MissingFile(String),
+    /// Contains the name of the file, the expected SHA256 hash and the actual hash of the file.
+    HashMismatch(String, String, String),
     // ----- Package related ---- //
     /// Contains the name of the missing dependecy and the name of package that requires the
     /// dependecy.
@@ -46,6 +48,11 @@ impl fmt::Display for NbError {
         match &self {
             // ----------- IO ----------- //
             NbError::MissingFile(file) => write!(f, "Missing file {}", file),
+            NbError::HashMismatch(file, expected, actual) => write!(
+                f,
+                "Hash mismatch for {}. Expected {}, got {}",
+                file, expected, actual
+            ),
             // ----- Package related ---- //
             NbError::MissingDependency(dep_name, pkg_name) => {
                 write!(f, "Missing dependecy {} required by {}", dep_name, pkg_name)
@@ -53,10 +60,7 @@ impl fmt::Display for NbError {
             NbError::BrokenDependency(dep_name, req, ver, pkg_name) => write!(
                 f,
                 "Broken dependency. Expected version ({}), got ({}): {} required by {}",
-                req.to_string(),
-                ver.to_string(),
-                dep_name,
-                pkg_name,
+                req, ver, dep_name, pkg_name,
             ),
             NbError::RemoveBreaksPkg(to_remove, breaks) => write!(
                 f,
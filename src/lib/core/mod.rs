@@ -1,8 +1,10 @@
 pub mod errors;
 pub mod pkgdb;
+pub mod search;
 pub mod set;
 pub mod wrappers;
 
 pub use errors::NbError;
-pub use pkgdb::{InfoLocal, InfoUniverse, PkgDb, SetInfo};
+pub use pkgdb::{InfoLocal, InfoUniverse, InstallReason, PkgDb, SetInfo};
+pub use search::{SearchMatch, SearchMode};
 pub use set::Set;
@@ -7,7 +7,7 @@ use std::fs;
 use std::path::Path;
 
 use super::{wrappers::*, NbError, Set};
-use crate::{TypeErr, DEFAULT_SET};
+use crate::{TypeErr, ARCH_ANY, DEFAULT_SET};
 
 /// Struct that contains all info about a package from a `PkgDb`.
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -18,6 +18,30 @@ pub struct PkgInfo {
     depends: Option<Vec<DependencyWrap>>,
     /// Brief description of the package.
     description: String,
+    /// Architecture the package is built for, `any` if the package is architecture independent.
+    /// It is optional, as packages with no architecture are assumed to be built for the
+    /// architecture of the repository they are in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arch: Option<String>,
+    /// Sonames of the shared libraries provided by the package, used to resolve the shared
+    /// library dependencies of other packages.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    provides: Vec<String>,
+    /// Why the package was installed, only set for packages of the local `PkgDb`.
+    #[serde(
+        default,
+        rename = "install-reason",
+        skip_serializing_if = "Option::is_none"
+    )]
+    install_reason: Option<InstallReason>,
+    /// When the package was installed (seconds since the Unix epoch), only set for packages of
+    /// the local `PkgDb`.
+    #[serde(
+        default,
+        rename = "install-date",
+        skip_serializing_if = "Option::is_none"
+    )]
+    install_date: Option<u64>,
     /// Set specific information. It is optional, as meta-packages
     /// have no set info.
     #[serde(flatten)]
@@ -26,7 +50,7 @@ pub struct PkgInfo {
 
 impl fmt::Display for PkgInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.version.inner().to_string())?;
+        write!(f, "{}", self.version.inner())?;
         write!(f, "   {}", self.description)
     }
 }
@@ -42,6 +66,10 @@ impl PkgInfo {
             version,
             depends,
             description,
+            arch: None,
+            provides: vec![],
+            install_reason: None,
+            install_date: None,
             set_info,
         }
     }
@@ -55,16 +83,55 @@ impl PkgInfo {
     }
 
     pub fn depends(&self) -> Option<Vec<(String, VersionReq)>> {
-        match &self.depends {
-            Some(list) => Some(
-                list.iter()
-                    .map(|x| {
-                        let a = x.inner();
-                        (a.0.clone(), a.1.clone())
-                    })
-                    .collect(),
-            ),
-            None => None,
+        self.depends.as_ref().map(|list| {
+            list.iter()
+                .map(|x| {
+                    let a = x.inner();
+                    (a.0.clone(), a.1.clone())
+                })
+                .collect()
+        })
+    }
+
+    pub fn arch(&self) -> Option<&str> {
+        self.arch.as_deref()
+    }
+
+    pub fn set_arch(&mut self, arch: Option<String>) {
+        self.arch = arch;
+    }
+
+    pub fn provides(&self) -> &[String] {
+        &self.provides
+    }
+
+    pub fn set_provides(&mut self, provides: Vec<String>) {
+        self.provides = provides;
+    }
+
+    pub fn install_reason(&self) -> Option<InstallReason> {
+        self.install_reason
+    }
+
+    pub fn set_install_reason(&mut self, reason: InstallReason) {
+        self.install_reason = Some(reason);
+    }
+
+    pub fn install_date(&self) -> Option<u64> {
+        self.install_date
+    }
+
+    pub fn set_install_date(&mut self, date: u64) {
+        self.install_date = Some(date);
+    }
+
+    /// Checks if the package can be installed in a system of the given architecture. Packages
+    /// with no architecture or with the `any` architecture are compatible with every
+    /// architecture.
+    pub fn is_compatible(&self, arch: &str) -> bool {
+        match &self.arch {
+            Some(a) => a == arch || a == ARCH_ANY,
+            None => true,
         }
     }
 
@@ -81,6 +148,26 @@ impl PkgInfo {
     }
 }
 
+/// Reason why a package of the local `PkgDb` was installed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum InstallReason {
+    /// The package was requested by the user.
+    #[serde(rename = "explicit")]
+    Explicit,
+    /// The package was installed as a dependency of another package.
+    #[serde(rename = "dependency")]
+    Dependency,
+}
+
+impl fmt::Display for InstallReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstallReason::Explicit => write!(f, "explicit"),
+            InstallReason::Dependency => write!(f, "dependency"),
+        }
+    }
+}
+
 /// This enum is used to contain the information struct
 /// of the set the package is from.
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -99,6 +186,12 @@ pub struct InfoUniverse {
 }
 
 impl InfoUniverse {
+    pub fn from(location: &str) -> InfoUniverse {
+        InfoUniverse {
+            location: location.to_string(),
+        }
+    }
+
     pub fn location(&self) -> &str {
         self.location.as_str()
     }
@@ -108,17 +201,55 @@ impl InfoUniverse {
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct InfoLocal {
     paths: Vec<String>,
+    /// Url of the repository mirror the package was downloaded from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+    /// Location of the package in the repository the package was downloaded from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    /// SHA256 hash of the archive the package was installed from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
 }
 
 impl InfoLocal {
     pub fn from(paths: Vec<String>) -> InfoLocal {
-        InfoLocal { paths }
+        InfoLocal {
+            paths,
+            origin: None,
+            location: None,
+            sha256: None,
+        }
     }
 
     pub fn paths(&self) -> &Vec<String> {
         &self.paths
     }
 
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    pub fn set_origin(&mut self, origin: &str) {
+        self.origin = Some(origin.to_string());
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    pub fn set_location(&mut self, location: &str) {
+        self.location = Some(location.to_string());
+    }
+
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    pub fn set_sha256(&mut self, sha256: &str) {
+        self.sha256 = Some(sha256.to_string());
+    }
+
     /// Sets a common prefix for all paths of the `InfoLocal`.
     ///
     /// # Panic
@@ -158,6 +289,14 @@ impl PkgDb {
         }
     }
 
+    /// Creates a new (empty) `PkgDb` for packages of the given `set`.
+    pub fn with_set(set: Set) -> PkgDb {
+        PkgDb {
+            set,
+            pkgdata: HashMap::new(),
+        }
+    }
+
     /// Loads a `PkgDb` from the given `toml` file.
     pub fn load(path: &Path) -> Result<PkgDb, TypeErr> {
         let file_str = match fs::read_to_string(path) {
@@ -170,6 +309,33 @@ impl PkgDb {
         }
     }
 
+    /// Saves the `PkgDb` in the given path as a `toml` file.
+    pub fn save(&self, path: &Path) -> Result<(), TypeErr> {
+        let db_str = toml::to_string_pretty(self)?;
+        fs::write(path, db_str.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns the set of the packages of the `PkgDb`.
+    pub fn set(&self) -> Set {
+        self.set
+    }
+
+    /// Returns an iterator over the names and `PkgInfo`s of all the packages in the `PkgDb`.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &PkgInfo)> {
+        self.pkgdata.iter()
+    }
+
+    /// Returns the number of packages in the `PkgDb`.
+    pub fn len(&self) -> usize {
+        self.pkgdata.len()
+    }
+
+    /// Checks if the `PkgDb` contains no packages.
+    pub fn is_empty(&self) -> bool {
+        self.pkgdata.is_empty()
+    }
+
     /// Checks if the `PkgDb` contains a package by the name of the package.
     pub fn contains_name(&self, name: &str) -> bool {
         self.pkgdata.contains_key(name)
@@ -195,6 +361,27 @@ impl PkgDb {
         self.pkgdata.get(name)
     }
 
+    /// Same as `get_pkg_info`, but returns a mutable reference to the `PkgInfo`.
+    pub fn get_pkg_info_mut(&mut self, name: &str) -> Option<&mut PkgInfo> {
+        self.pkgdata.get_mut(name)
+    }
+
+    /// Returns the names of the packages of the `PkgDb` that depend on the package `name`
+    /// (whatever the version requirement), sorted.
+    pub fn reverse_depends(&self, name: &str) -> Vec<String> {
+        let mut rdeps: Vec<String> = self
+            .pkgdata
+            .iter()
+            .filter(|(_, info)| match info.depends() {
+                Some(deps) => deps.iter().any(|(dep, _)| dep == name),
+                None => false,
+            })
+            .map(|(n, _)| n.to_string())
+            .collect();
+        rdeps.sort();
+        rdeps
+    }
+
     /// Removes a given package from the `PkgDb`. If `check_conflicts` is set to `true`, this
     /// function calls `check_remove` before removing the package.
     ///
@@ -316,9 +503,24 @@ impl PkgDb {
     /// when every dependency of every the node is inside the graph, and the dependencies met the
     /// version requirements the packages have.
     ///
+    /// If the integrity is not correct, the first problem found is returned, see
+    /// `subgraph_integrity_errors` to get all of them.
+    ///
     /// **Note**: The cost of this function is O(n^2).
     //NOTE: Parallelize?
     pub fn check_subgraph_integrity(subgraph: &HashMap<String, &PkgInfo>) -> Result<(), TypeErr> {
+        match Self::subgraph_integrity_errors(subgraph).into_iter().next() {
+            Some(e) => Err(Box::new(e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns all the problems that break the integrity of the graph (see
+    /// `check_subgraph_integrity`), this is, a `NbError::MissingDependency` error for every
+    /// missing dependency and a `NbError::BrokenDependency` for every dependency that does not
+    /// meet the version requirement.
+    pub fn subgraph_integrity_errors(subgraph: &HashMap<String, &PkgInfo>) -> Vec<NbError> {
+        let mut errors = vec![];
         // for every node (package) in the graph
         for (node_name, node) in subgraph.iter() {
             // for each dependency (if some) of the package
@@ -329,26 +531,24 @@ impl PkgDb {
                         // if the dependency exists, check if the version requirement is met
                         Some(dep) => {
                             if !version_req.matches(dep.version()) {
-                                return Err(Box::new(NbError::BrokenDependency(
+                                errors.push(NbError::BrokenDependency(
                                     dep_name.to_string(),
                                     version_req,
                                     dep.version().clone(),
                                     node_name.to_string(),
-                                )));
+                                ));
                             }
                         }
                         // the dependency is missing in the graph
-                        None => {
-                            return Err(Box::new(NbError::MissingDependency(
-                                dep_name.to_string(),
-                                node_name.to_string(),
-                            )))
-                        }
+                        None => errors.push(NbError::MissingDependency(
+                            dep_name.to_string(),
+                            node_name.to_string(),
+                        )),
                     }
                 }
             }
         }
-        Ok(())
+        errors
     }
 }
 
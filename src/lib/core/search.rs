@@ -0,0 +1,241 @@
+//! Search of packages in a `PkgDb` by name and description.
+
+use regex::RegexBuilder;
+use semver::Version;
+use serde_derive::Serialize;
+
+use super::{PkgDb, SetInfo};
+use crate::TypeErr;
+
+/// Minimum similarity (between 0 and 1) of a word to the pattern to be considered a match in
+/// `SearchMode::Fuzzy` searches.
+pub const FUZZY_THRESHOLD: f64 = 0.8;
+
+// scores of the different kinds of matches, results are ranked by score
+const SCORE_EXACT_NAME: u32 = 100;
+const SCORE_NAME_PREFIX: u32 = 80;
+const SCORE_NAME: u32 = 60;
+const SCORE_DESCRIPTION: u32 = 20;
+
+/// Scores the name and the description of a package, `None` if they do not match.
+type Scorer = Box<dyn Fn(&str, &str) -> Option<u32>>;
+
+/// How the pattern of a search is matched against the names and descriptions of the packages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    /// The pattern is a case insensitive substring.
+    Substring,
+    /// The pattern is a case insensitive regular expression.
+    Regex,
+    /// The pattern is compared with the names of the packages and the words of their
+    /// descriptions, allowing typos (see `FUZZY_THRESHOLD`).
+    Fuzzy,
+}
+
+/// A package found by `PkgDb::search`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchMatch {
+    pub name: String,
+    pub version: Version,
+    pub description: String,
+    /// Location of the package in the repository, for universe packages.
+    pub location: Option<String>,
+    /// Url of the repository the package comes from. It is not set by `PkgDb::search`, see
+    /// `SearchMatch::mark_repo`.
+    pub repo: Option<String>,
+    /// Relevance of the match, results with higher scores are better matches.
+    pub score: u32,
+    /// Version of the package installed in the system, if installed. It is not set by
+    /// `PkgDb::search`, see `SearchMatch::mark_installed`.
+    pub installed: Option<Version>,
+}
+
+impl SearchMatch {
+    /// Sets the `installed` version of every match to the version of the package in `local_db`.
+    pub fn mark_installed(matches: &mut [SearchMatch], local_db: &PkgDb) {
+        for m in matches {
+            m.installed = local_db.get_pkg_info(&m.name).map(|i| i.version().clone());
+        }
+    }
+
+    /// Sets the `repo` of every match with a location (universe packages) to `repo_url`.
+    pub fn mark_repo(matches: &mut [SearchMatch], repo_url: &str) {
+        for m in matches.iter_mut().filter(|m| m.location.is_some()) {
+            m.repo = Some(repo_url.to_string());
+        }
+    }
+}
+
+impl PkgDb {
+    /// Searches the packages whose name or description match the `pattern`, see `SearchMode`.
+    /// The results are ranked: exact name matches first, then matches at the beginning of the
+    /// name, matches anywhere in the name and finally matches in the description. Results with
+    /// the same score are sorted by name.
+    ///
+    /// # Errors
+    ///
+    /// If the `mode` is `SearchMode::Regex` and the `pattern` is not a valid regular expression,
+    /// an error is returned.
+    pub fn search(&self, pattern: &str, mode: SearchMode) -> Result<Vec<SearchMatch>, TypeErr> {
+        let score: Scorer = match mode {
+            SearchMode::Substring => {
+                let pattern = pattern.to_lowercase();
+                Box::new(move |name, description| {
+                    substring_score(&pattern, &name.to_lowercase(), &description.to_lowercase())
+                })
+            }
+            SearchMode::Regex => {
+                let re = RegexBuilder::new(pattern).case_insensitive(true).build()?;
+                Box::new(move |name, description| match re.find(name) {
+                    Some(m) if m.as_str() == name => Some(SCORE_EXACT_NAME),
+                    Some(m) if m.start() == 0 => Some(SCORE_NAME_PREFIX),
+                    Some(_) => Some(SCORE_NAME),
+                    None if re.is_match(description) => Some(SCORE_DESCRIPTION),
+                    None => None,
+                })
+            }
+            SearchMode::Fuzzy => {
+                let pattern = pattern.to_lowercase();
+                Box::new(move |name, description| fuzzy_score(&pattern, name, description))
+            }
+        };
+
+        let mut matches = vec![];
+        for (name, info) in self.iter() {
+            if let Some(score) = score(name, info.description()) {
+                matches.push(SearchMatch {
+                    name: name.to_string(),
+                    version: info.version().clone(),
+                    description: info.description().to_string(),
+                    location: match info.set_info() {
+                        Some(SetInfo::Universe(u)) => Some(u.location().to_string()),
+                        _ => None,
+                    },
+                    repo: None,
+                    score,
+                    installed: None,
+                });
+            }
+        }
+        matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+        Ok(matches)
+    }
+}
+
+/// Scores a substring match. All the arguments must be lowercase.
+fn substring_score(pattern: &str, name: &str, description: &str) -> Option<u32> {
+    if name == pattern {
+        Some(SCORE_EXACT_NAME)
+    } else if name.starts_with(pattern) {
+        Some(SCORE_NAME_PREFIX)
+    } else if name.contains(pattern) {
+        Some(SCORE_NAME)
+    } else if description.contains(pattern) {
+        Some(SCORE_DESCRIPTION)
+    } else {
+        None
+    }
+}
+
+/// Scores a fuzzy match, proportionally to the similarity of the pattern (lowercase) to the name
+/// or to the most similar word of the description.
+fn fuzzy_score(pattern: &str, name: &str, description: &str) -> Option<u32> {
+    let name_sim = strsim::jaro_winkler(pattern, &name.to_lowercase());
+    if name_sim >= FUZZY_THRESHOLD {
+        return Some((name_sim * f64::from(SCORE_EXACT_NAME)) as u32);
+    }
+    let desc_sim = description
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|w| strsim::jaro_winkler(pattern, &w.to_lowercase()))
+        .fold(0.0, f64::max);
+    if desc_sim >= FUZZY_THRESHOLD {
+        Some((desc_sim * f64::from(SCORE_DESCRIPTION)) as u32)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> PkgDb {
+        toml::from_str(
+            "set = 'universe'\n\
+             [git]\nversion = '2.28.0'\ndescription = 'Distributed version control system'\n\
+             [git.universe]\nlocation = 'core/git'\n\
+             [gitg]\nversion = '3.32.1'\ndescription = 'Graphical user interface'\n\
+             [gitg.universe]\nlocation = 'extra/gitg'\n\
+             [legit]\nversion = '1.2.0'\ndescription = 'Complementary command line interface'\n\
+             [legit.universe]\nlocation = 'extra/legit'\n\
+             [tig]\nversion = '2.5.1'\ndescription = 'Text-mode interface for git'\n\
+             [tig.universe]\nlocation = 'extra/tig'\n\
+             [neofetch]\nversion = '7.1.0'\ndescription = 'System information tool'\n\
+             [neofetch.universe]\nlocation = 'extra/neofetch'\n",
+        )
+        .unwrap()
+    }
+
+    fn names(matches: &[SearchMatch]) -> Vec<&str> {
+        matches.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn substring_ranks_exact_prefix_substring_description() {
+        let matches = test_db().search("GIT", SearchMode::Substring).unwrap();
+        assert_eq!(names(&matches), vec!["git", "gitg", "legit", "tig"]);
+        let scores: Vec<u32> = matches.iter().map(|m| m.score).collect();
+        assert_eq!(
+            scores,
+            vec![
+                SCORE_EXACT_NAME,
+                SCORE_NAME_PREFIX,
+                SCORE_NAME,
+                SCORE_DESCRIPTION
+            ]
+        );
+        assert_eq!(matches[0].location.as_deref(), Some("core/git"));
+    }
+
+    #[test]
+    fn regex_ranks_exact_prefix_substring_description() {
+        let matches = test_db().search("GI.", SearchMode::Regex).unwrap();
+        assert_eq!(names(&matches), vec!["git", "gitg", "legit", "tig"]);
+        assert!(test_db()
+            .search("nothing.*", SearchMode::Regex)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        assert!(test_db().search("git(", SearchMode::Regex).is_err());
+        assert!(test_db().search("[a-", SearchMode::Regex).is_err());
+    }
+
+    #[test]
+    fn fuzzy_finds_typos() {
+        let matches = test_db().search("neofecth", SearchMode::Fuzzy).unwrap();
+        assert_eq!(names(&matches), vec!["neofetch"]);
+        // typos in the words of the description
+        let matches = test_db().search("informaton", SearchMode::Fuzzy).unwrap();
+        assert_eq!(names(&matches), vec!["neofetch"]);
+        assert!(matches[0].score < SCORE_DESCRIPTION);
+    }
+
+    #[test]
+    fn mark_installed_and_repo() {
+        let mut matches = test_db().search("tig", SearchMode::Substring).unwrap();
+        let local_db: PkgDb = toml::from_str(
+            "set = 'local'\n\
+             [tig]\nversion = '2.4.0'\ndescription = 'tig'\n\
+             [tig.local]\npaths = []\n",
+        )
+        .unwrap();
+        SearchMatch::mark_installed(&mut matches, &local_db);
+        SearchMatch::mark_repo(&mut matches, "mock://repo");
+        assert_eq!(matches[0].installed, Some(Version::parse("2.4.0").unwrap()));
+        assert_eq!(matches[0].repo.as_deref(), Some("mock://repo"));
+    }
+}
@@ -73,7 +73,12 @@ impl serde::Serialize for DependencyWrap {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(format!("{}{}", self.0.to_string(), self.1.to_string()).as_str())
+        // dependencies on any version are written as the bare name of the package
+        if self.1 == VersionReq::any() {
+            serializer.serialize_str(&self.0)
+        } else {
+            serializer.serialize_str(format!("{}{}", self.0, self.1).as_str())
+        }
     }
 }
 
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use super::{clear_outfile, Fetcher, Progress, CHUNK_SIZE};
+use crate::core::NbError;
+use crate::{utils, TypeErr};
+
+/// Fetches files from the local filesystem. Accepts `file://` URLs and plain paths, so
+/// repositories in a NFS share, an USB stick or a local build directory can be used.
+#[derive(Default)]
+pub struct FileFetcher;
+
+impl FileFetcher {
+    pub fn new() -> FileFetcher {
+        FileFetcher
+    }
+}
+
+impl Fetcher for FileFetcher {
+    /// # Errors
+    ///
+    /// If the `url` does not point to an existing file in the local filesystem, a
+    /// `NbError::MissingFile` error is returned. `file://` URLs with a host other than `localhost`
+    /// are not supported.
+    fn fetch(&self, url: &str, outfile: &Path, progress: &dyn Progress) -> Result<(), TypeErr> {
+        let path = match utils::url_to_local_path(url) {
+            Some(p) if p.is_file() => p,
+            Some(_) => return Err(Box::new(NbError::MissingFile(url.to_string()))),
+            None => return Err(format!("Cannot fetch {}: not a local file URL", url).into()),
+        };
+        clear_outfile(outfile)?;
+
+        let mut src = File::open(path)?;
+        let total = src.metadata()?.len();
+        let mut dst = File::create(outfile)?;
+        // copy the file in chunks in order to report the progress
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut done = 0;
+        loop {
+            let n = src.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            dst.write_all(&buffer[..n])?;
+            done += n as u64;
+            progress.update(url, done, Some(total));
+        }
+        progress.finish(url);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::NoProgress;
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Creates an empty temporary directory for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("nbkit-test-file-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn fetches_local_urls() {
+        let dir = temp_dir("local");
+        let src = dir.join("src");
+        fs::write(&src, "data").unwrap();
+        let urls = [
+            format!("file://{}", src.display()),
+            format!("file://localhost{}", src.display()),
+            src.display().to_string(),
+        ];
+        for url in &urls {
+            let out = dir.join("out");
+            FileFetcher::new().fetch(url, &out, &NoProgress).unwrap();
+            assert_eq!(fs::read_to_string(&out).unwrap(), "data", "{}", url);
+            fs::remove_file(&out).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fetches_relative_paths() {
+        let dir = temp_dir("relative");
+        let out = dir.join("out");
+        // tests are run from the root of the crate
+        FileFetcher::new()
+            .fetch("Cargo.toml", &out, &NoProgress)
+            .unwrap();
+        assert_eq!(fs::read(&out).unwrap(), fs::read("Cargo.toml").unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_remote_hosts() {
+        let dir = temp_dir("remote");
+        let src = dir.join("src");
+        fs::write(&src, "data").unwrap();
+        let out = dir.join("out");
+        let url = format!("file://server{}", src.display());
+        assert!(FileFetcher::new().fetch(&url, &out, &NoProgress).is_err());
+        assert!(!out.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fails_on_missing_file() {
+        let dir = temp_dir("missing");
+        let url = format!("file://{}", dir.join("nothing").display());
+        let err = FileFetcher::new()
+            .fetch(&url, &dir.join("out"), &NoProgress)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NbError>(),
+            Some(NbError::MissingFile(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
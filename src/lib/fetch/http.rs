@@ -0,0 +1,480 @@
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use super::{clear_outfile, Fetcher, Progress, CHUNK_SIZE};
+use crate::core::NbError;
+use crate::TypeErr;
+
+/// Extension added to the files being downloaded. Once the download is completed, the file is
+/// renamed to its final name.
+pub const PARTIAL_EXT: &str = "part";
+
+/// Extension added to the partial file to name the file storing its validator: the `ETag` (or
+/// `Last-Modified` date) of the file being downloaded. It is sent as `If-Range` when resuming,
+/// so a file changed in the server is downloaded again instead of mixing both versions.
+pub const VALIDATOR_EXT: &str = "validator";
+
+/// Default number of times a failed download is retried.
+pub const DEF_RETRIES: u32 = 3;
+
+/// Default time to wait (in milliseconds) before retrying a failed download.
+pub const DEF_BACKOFF_MS: u64 = 500;
+
+/// Maximum time to wait (in milliseconds) between two retries, no matter the backoff.
+pub const MAX_BACKOFF_MS: u64 = 30_000;
+
+/// Fetches files using HTTP(S). Files are streamed to disk, and if the transfer is interrupted
+/// or the server fails, the download is retried (with an exponential backoff) resuming the
+/// transfer from the last received byte.
+pub struct HttpFetcher {
+    client: Client,
+    /// Number of times a failed download is retried.
+    retries: u32,
+    /// Time to wait before the first retry. It is doubled after every retry, up to
+    /// `MAX_BACKOFF_MS`.
+    backoff: Duration,
+}
+
+impl HttpFetcher {
+    pub fn new() -> HttpFetcher {
+        HttpFetcher::with_client(Client::new())
+    }
+
+    /// Creates a `HttpFetcher` that uses the given `reqwest` client. Useful to fetch files using
+    /// proxies, custom headers (for authentication) or timeouts.
+    pub fn with_client(client: Client) -> HttpFetcher {
+        HttpFetcher {
+            client,
+            retries: DEF_RETRIES,
+            backoff: Duration::from_millis(DEF_BACKOFF_MS),
+        }
+    }
+
+    /// Sets the number of times a failed download is retried and the time to wait before the
+    /// first retry.
+    pub fn with_retries(mut self, retries: u32, backoff: Duration) -> HttpFetcher {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Downloads the file in `url` to `partial`. If `partial` already contains the first bytes of
+    /// the file, only the remaining bytes are requested, as long as the file did not change in the
+    /// server (checked with `If-Range`). Else, the file is downloaded from the beginning.
+    fn fetch_partial(
+        &self,
+        url: &str,
+        partial: &Path,
+        progress: &dyn Progress,
+    ) -> Result<(), TypeErr> {
+        let validator_file = validator_path(partial);
+        let validator = fs::read_to_string(&validator_file).ok();
+        let offset = match (fs::metadata(partial), &validator) {
+            (Ok(m), Some(_)) => m.len(),
+            // without a validator, the partial file cannot be checked, so it is not resumed
+            _ => 0,
+        };
+
+        let mut request = self.client.get(url);
+        if let (true, Some(validator)) = (offset > 0, &validator) {
+            request = request
+                .header(RANGE, format!("bytes={}-", offset))
+                .header(IF_RANGE, validator.as_str());
+        }
+        let mut resp = request.send()?;
+
+        // check for errors
+        let status = resp.status();
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && offset > 0 {
+            // the partially downloaded file is not valid, download it from the beginning
+            fs::remove_file(partial)?;
+            let _ = fs::remove_file(&validator_file);
+            return self.fetch_partial(url, partial, progress);
+        } else if status.is_client_error() {
+            return Err(Box::new(NbError::ClientError(status.to_string())));
+        } else if status.is_server_error() {
+            return Err(Box::new(NbError::ServerError(status.to_string())));
+        }
+
+        // a range not starting where the partial file ends can't be appended to it
+        if status == StatusCode::PARTIAL_CONTENT
+            && offset > 0
+            && range_start(resp.headers()) != Some(offset)
+        {
+            fs::remove_file(partial)?;
+            let _ = fs::remove_file(&validator_file);
+            return self.fetch_partial(url, partial, progress);
+        }
+
+        // if the server does not support ranges or the file changed, the whole file is sent
+        let (mut file, mut done) = if status == StatusCode::PARTIAL_CONTENT && offset > 0 {
+            (OpenOptions::new().append(true).open(partial)?, offset)
+        } else {
+            match response_validator(resp.headers()) {
+                Some(v) => fs::write(&validator_file, v)?,
+                None => {
+                    let _ = fs::remove_file(&validator_file);
+                }
+            }
+            (File::create(partial)?, 0)
+        };
+        let total = resp.content_length().map(|len| len + done);
+
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let n = resp.read(&mut buffer).map_err(BodyError)?;
+            if n == 0 {
+                break;
+            }
+            file.write_all(&buffer[..n])?;
+            done += n as u64;
+            progress.update(url, done, total);
+        }
+        Ok(())
+    }
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    /// # Errors
+    ///
+    /// If the server responds with an error status code, a `NbError::ClientError` or
+    /// `NbError::ServerError` error is returned. Only connection errors, timeouts, interrupted
+    /// transfers and server errors are retried; client errors and IO errors saving the file are
+    /// returned right away.
+    fn fetch(&self, url: &str, outfile: &Path, progress: &dyn Progress) -> Result<(), TypeErr> {
+        let partial = partial_path(outfile);
+
+        let mut wait = self.backoff;
+        let mut retry = 0;
+        while let Err(e) = self.fetch_partial(url, &partial, progress) {
+            if retry >= self.retries || !is_transient(e.as_ref()) {
+                return Err(e);
+            }
+            retry += 1;
+            thread::sleep(wait);
+            wait = wait
+                .saturating_mul(2)
+                .min(Duration::from_millis(MAX_BACKOFF_MS));
+        }
+
+        // the file is fully downloaded, move it to its final path
+        clear_outfile(outfile)?;
+        fs::rename(&partial, outfile)?;
+        let _ = fs::remove_file(validator_path(&partial));
+        progress.finish(url);
+        Ok(())
+    }
+}
+
+/// Returns the path where the file to be saved as `outfile` is stored while it's being
+/// downloaded.
+pub fn partial_path(outfile: &Path) -> PathBuf {
+    let mut name = outfile.as_os_str().to_os_string();
+    name.push(format!(".{}", PARTIAL_EXT));
+    PathBuf::from(name)
+}
+
+/// Returns the path of the file storing the validator of a `partial` file, see `VALIDATOR_EXT`.
+fn validator_path(partial: &Path) -> PathBuf {
+    let mut name = partial.as_os_str().to_os_string();
+    name.push(format!(".{}", VALIDATOR_EXT));
+    PathBuf::from(name)
+}
+
+/// Returns the value to send as `If-Range` to resume the download of a response: its `ETag`, or
+/// its `Last-Modified` date if it has no `ETag`. Weak `ETag`s cannot be used with `If-Range`.
+fn response_validator(headers: &HeaderMap) -> Option<String> {
+    let etag = headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.starts_with("W/"));
+    etag.or_else(|| headers.get(LAST_MODIFIED).and_then(|v| v.to_str().ok()))
+        .map(|v| v.to_string())
+}
+
+/// Returns the first byte of the range sent in a `206 Partial Content` response, from its
+/// `Content-Range` header (`bytes <start>-<end>/<total>`).
+fn range_start(headers: &HeaderMap) -> Option<u64> {
+    let range = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = range.trim().strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+/// The transfer of the body of a response was interrupted.
+#[derive(Debug)]
+struct BodyError(io::Error);
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Transfer interrupted: {}", self.0)
+    }
+}
+
+impl Error for BodyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Checks if a failed download might succeed if retried: the connection failed or timed out,
+/// the transfer was interrupted or the server failed. Any other error, like a client error or
+/// an IO error writing the file (no space left, permission denied...), is permanent.
+fn is_transient(err: &(dyn Error + 'static)) -> bool {
+    if let Some(e) = err.downcast_ref::<NbError>() {
+        return matches!(e, NbError::ServerError(_));
+    }
+    if err.is::<BodyError>() {
+        return true;
+    }
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.is_connect() || e.is_timeout() || e.is_body(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::NoProgress;
+
+    use std::env;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// Starts a HTTP server that sends the given raw `responses`, one per connection and in order,
+    /// and closes every connection after the response. Returns the url of the server and a
+    /// handle returning the headers of every received request, in lowercase.
+    fn serve(responses: Vec<Vec<u8>>) -> (String, JoinHandle<Vec<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = vec![];
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim().to_lowercase());
+                }
+                requests.push(headers);
+                stream.write_all(&response).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    /// Builds a raw HTTP response. `len` is the announced length of the body, which might be
+    /// longer than the `body` actually sent.
+    fn response(status: &str, headers: &[&str], len: usize, body: &[u8]) -> Vec<u8> {
+        let mut resp = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status, len
+        );
+        for header in headers {
+            resp.push_str(&format!("{}\r\n", header));
+        }
+        resp.push_str("\r\n");
+        let mut resp = resp.into_bytes();
+        resp.extend_from_slice(body);
+        resp
+    }
+
+    fn fetcher() -> HttpFetcher {
+        HttpFetcher::new().with_retries(3, Duration::from_millis(1))
+    }
+
+    fn outfile(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("nbkit-test-http-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn has_header(request: &[String], header: &str) -> bool {
+        request.iter().any(|h| h == header)
+    }
+
+    #[test]
+    fn resumes_interrupted_download() {
+        let (url, server) = serve(vec![
+            // the connection is closed after sending the first 4 bytes
+            response("200 OK", &["ETag: \"v1\""], 10, b"0123"),
+            response(
+                "206 Partial Content",
+                &["ETag: \"v1\"", "Content-Range: bytes 4-9/10"],
+                6,
+                b"456789",
+            ),
+        ]);
+        let out = outfile("resume");
+        fetcher().fetch(&url, &out, &NoProgress).unwrap();
+
+        assert_eq!(fs::read_to_string(&out).unwrap(), "0123456789");
+        assert!(!partial_path(&out).exists());
+        assert!(!validator_path(&partial_path(&out)).exists());
+        let requests = server.join().unwrap();
+        assert!(!requests[0].iter().any(|h| h.starts_with("range:")));
+        assert!(has_header(&requests[1], "range: bytes=4-"));
+        assert!(has_header(&requests[1], "if-range: \"v1\""));
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn restarts_download_if_range_does_not_match() {
+        let (url, server) = serve(vec![
+            response("200 OK", &["ETag: \"v1\""], 10, b"0123"),
+            // the server sends a range starting at the wrong byte
+            response(
+                "206 Partial Content",
+                &["ETag: \"v1\"", "Content-Range: bytes 2-9/10"],
+                8,
+                b"23456789",
+            ),
+            response("200 OK", &["ETag: \"v1\""], 10, b"0123456789"),
+        ]);
+        let out = outfile("bad-range");
+        fetcher().fetch(&url, &out, &NoProgress).unwrap();
+
+        assert_eq!(fs::read_to_string(&out).unwrap(), "0123456789");
+        let requests = server.join().unwrap();
+        assert!(has_header(&requests[1], "range: bytes=4-"));
+        assert!(!requests[2].iter().any(|h| h.starts_with("range:")));
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn restarts_download_if_file_changed() {
+        let (url, server) = serve(vec![
+            response("200 OK", &["ETag: \"v1\""], 10, b"0123"),
+            // the file changed, so the server ignores the range and sends the new file
+            response("200 OK", &["ETag: \"v2\""], 10, b"abcdefghij"),
+        ]);
+        let out = outfile("changed");
+        fetcher().fetch(&url, &out, &NoProgress).unwrap();
+
+        assert_eq!(fs::read_to_string(&out).unwrap(), "abcdefghij");
+        let requests = server.join().unwrap();
+        assert!(has_header(&requests[1], "if-range: \"v1\""));
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn does_not_resume_without_validator() {
+        let (url, server) = serve(vec![
+            response("200 OK", &[], 10, b"0123"),
+            response("200 OK", &[], 10, b"0123456789"),
+        ]);
+        let out = outfile("no-validator");
+        fetcher().fetch(&url, &out, &NoProgress).unwrap();
+
+        assert_eq!(fs::read_to_string(&out).unwrap(), "0123456789");
+        let requests = server.join().unwrap();
+        assert!(!requests[1].iter().any(|h| h.starts_with("range:")));
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let (url, server) = serve(vec![
+            response("503 Service Unavailable", &[], 0, b""),
+            response("500 Internal Server Error", &[], 0, b""),
+            response("200 OK", &[], 4, b"data"),
+        ]);
+        let out = outfile("retry");
+        fetcher().fetch(&url, &out, &NoProgress).unwrap();
+
+        assert_eq!(fs::read_to_string(&out).unwrap(), "data");
+        assert_eq!(server.join().unwrap().len(), 3);
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn gives_up_after_retries() {
+        let responses = (0..2)
+            .map(|_| response("503 Service Unavailable", &[], 0, b""))
+            .collect();
+        let (url, server) = serve(responses);
+        let out = outfile("give-up");
+        let err = HttpFetcher::new()
+            .with_retries(1, Duration::from_millis(1))
+            .fetch(&url, &out, &NoProgress)
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<NbError>(),
+            Some(NbError::ServerError(_))
+        ));
+        assert_eq!(server.join().unwrap().len(), 2);
+        assert!(!out.exists());
+        let _ = fs::remove_file(partial_path(&out));
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let (url, server) = serve(vec![response("404 Not Found", &[], 0, b"")]);
+        let out = outfile("not-found");
+        let err = fetcher().fetch(&url, &out, &NoProgress).unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<NbError>(),
+            Some(NbError::ClientError(_))
+        ));
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn does_not_retry_io_errors() {
+        let (url, server) = serve(vec![response("200 OK", &[], 4, b"data")]);
+        // the partial file can't be created, as its parent does not exist
+        let out = outfile("io-error").join("file");
+        let err = fetcher().fetch(&url, &out, &NoProgress).unwrap_err();
+
+        assert!(err.downcast_ref::<io::Error>().is_some());
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn transient_errors() {
+        let body = BodyError(io::Error::new(io::ErrorKind::UnexpectedEof, "eof"));
+        assert!(is_transient(&body));
+        assert!(is_transient(&NbError::ServerError("503".to_string())));
+        assert!(!is_transient(&NbError::ClientError("404".to_string())));
+        assert!(!is_transient(&io::Error::from_raw_os_error(28))); // ENOSPC
+        assert!(!is_transient(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+    }
+
+    #[test]
+    fn parses_range_start() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(range_start(&headers), None);
+        headers.insert(CONTENT_RANGE, "bytes 4-9/10".parse().unwrap());
+        assert_eq!(range_start(&headers), Some(4));
+        headers.insert(CONTENT_RANGE, "bytes 0-9/*".parse().unwrap());
+        assert_eq!(range_start(&headers), Some(0));
+        headers.insert(CONTENT_RANGE, "bytes */10".parse().unwrap());
+        assert_eq!(range_start(&headers), None);
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::{clear_outfile, Fetcher, Progress};
+use crate::core::NbError;
+use crate::TypeErr;
+
+/// An in-memory `Fetcher`, mainly intended for testing. Serves the files previously inserted with
+/// `MockFetcher::insert` and records every fetched URL, so no network access is needed.
+#[derive(Default)]
+pub struct MockFetcher {
+    /// Contents of the served files, indexed by their URL.
+    files: HashMap<String, Vec<u8>>,
+    /// URLs requested to the fetcher, in order.
+    requests: Mutex<Vec<String>>,
+}
+
+impl MockFetcher {
+    pub fn new() -> MockFetcher {
+        MockFetcher {
+            files: HashMap::new(),
+            requests: Mutex::new(vec![]),
+        }
+    }
+
+    /// Serves `contents` as the file located in `url`. If the `url` was already served, its
+    /// previous contents are returned.
+    pub fn insert(&mut self, url: &str, contents: &[u8]) -> Option<Vec<u8>> {
+        self.files.insert(url.to_string(), contents.to_vec())
+    }
+
+    /// Returns the list of all the URLs requested to the fetcher (including the ones that failed),
+    /// in the order they were requested.
+    pub fn requests(&self) -> Vec<String> {
+        match self.requests.lock() {
+            Ok(r) => r.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+}
+
+impl Fetcher for MockFetcher {
+    /// # Errors
+    ///
+    /// If the `url` was not inserted in the fetcher, a `NbError::ClientError` error is returned,
+    /// just like a HTTP server responding with a `404 Not Found`.
+    fn fetch(&self, url: &str, outfile: &Path, progress: &dyn Progress) -> Result<(), TypeErr> {
+        match self.requests.lock() {
+            Ok(mut r) => r.push(url.to_string()),
+            Err(poisoned) => poisoned.into_inner().push(url.to_string()),
+        }
+
+        let contents = match self.files.get(url) {
+            Some(c) => c,
+            None => {
+                return Err(Box::new(NbError::ClientError(format!(
+                    "404 Not Found: {}",
+                    url
+                ))))
+            }
+        };
+        clear_outfile(outfile)?;
+        fs::write(outfile, contents)?;
+        let size = contents.len() as u64;
+        progress.update(url, size, Some(size));
+        progress.finish(url);
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! This module contains the transports used to fetch files (repository indexes, packages...) from
+//! nebula repositories.
+//!
+//! All transports implement the `Fetcher` trait, so applications embedding nbkit can provide
+//! their own transport (with authentication, proxies, caching...) by implementing it.
+
+use std::fs;
+use std::path::Path;
+
+use crate::{utils, TypeErr};
+
+pub mod file;
+pub mod http;
+pub mod mock;
+
+pub use file::FileFetcher;
+pub use http::HttpFetcher;
+pub use mock::MockFetcher;
+
+/// Size of the chunks fetchers read and write files with.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A transport able to fetch the file located in an URL.
+pub trait Fetcher: Send + Sync {
+    /// Fetches the file located in `url` and saves it as `outfile`. If `outfile` already exists,
+    /// it is replaced. The progress of the transfer is reported to `progress`.
+    fn fetch(&self, url: &str, outfile: &Path, progress: &dyn Progress) -> Result<(), TypeErr>;
+}
+
+/// Receives progress reports of the files being fetched.
+pub trait Progress: Sync {
+    /// Called every time a chunk of the file in `url` is fetched. `done` is the number of bytes
+    /// already fetched and `total` the size of the file, if known.
+    fn update(&self, url: &str, done: u64, total: Option<u64>);
+
+    /// Called once the file in `url` has been completely fetched.
+    fn finish(&self, _url: &str) {}
+}
+
+/// A `Progress` that ignores all progress reports.
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn update(&self, _url: &str, _done: u64, _total: Option<u64>) {}
+}
+
+/// The `Fetcher` used by default. `file://` URLs and plain paths (see `utils::url_to_local_path`)
+/// are fetched using a `FileFetcher`, and the rest of the URLs using a `HttpFetcher`.
+#[derive(Default)]
+pub struct DefaultFetcher {
+    file: FileFetcher,
+    http: HttpFetcher,
+}
+
+impl DefaultFetcher {
+    pub fn new() -> DefaultFetcher {
+        DefaultFetcher {
+            file: FileFetcher::new(),
+            http: HttpFetcher::new(),
+        }
+    }
+
+    /// Sets the `HttpFetcher` used to fetch remote files.
+    pub fn with_http(mut self, http: HttpFetcher) -> DefaultFetcher {
+        self.http = http;
+        self
+    }
+}
+
+impl Fetcher for DefaultFetcher {
+    fn fetch(&self, url: &str, outfile: &Path, progress: &dyn Progress) -> Result<(), TypeErr> {
+        // `file://` URLs with a remote host are rejected by the `FileFetcher`
+        if url.starts_with(utils::FILE_URL_SCHEME) || utils::url_to_local_path(url).is_some() {
+            self.file.fetch(url, outfile, progress)
+        } else {
+            self.http.fetch(url, outfile, progress)
+        }
+    }
+}
+
+/// Deletes the file or directory in `path` if it exists. Used by the fetchers before writing the
+/// fetched file.
+pub(crate) fn clear_outfile(path: &Path) -> Result<(), TypeErr> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
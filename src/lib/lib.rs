@@ -5,7 +5,9 @@ use semver::VersionReq;
 
 use std::error::Error;
 
+pub mod builder;
 pub mod core;
+pub mod fetch;
 pub mod nbpm;
 pub mod repo;
 pub mod utils;
@@ -17,3 +19,10 @@ pub type Dependencies = Option<Vec<Query>>;
 
 // declare constants
 pub const DEFAULT_SET: core::Set = core::Set::Universe;
+
+/// Architecture of the packages that can be installed in any architecture, for example, packages
+/// only containing data files.
+pub const ARCH_ANY: &str = "any";
+
+/// Architecture of the host nbkit is running on, for example `x86_64` or `aarch64`.
+pub const HOST_ARCH: &str = std::env::consts::ARCH;
@@ -0,0 +1,48 @@
+//! The packages downloaded by nbpm, kept in its working directory (`Config::work_dir`) until the
+//! next operation.
+
+use serde_derive::Serialize;
+use walkdir::WalkDir;
+
+use std::fs;
+use std::path::Path;
+
+use super::Config;
+use crate::TypeErr;
+
+/// A file of the cache.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheEntry {
+    pub path: String,
+    /// Size in bytes of the file.
+    pub size: u64,
+}
+
+/// Returns the files in the cache, sorted by path. If the cache does not exist, the list is
+/// empty.
+pub fn cache_entries(config: &Config) -> Result<Vec<CacheEntry>, TypeErr> {
+    if !Path::new(config.work_dir()).is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for entry in WalkDir::new(config.work_dir()).sort_by(|a, b| a.path().cmp(b.path())) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            entries.push(CacheEntry {
+                path: entry.path().to_string_lossy().to_string(),
+                size: entry.metadata()?.len(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Removes the cache, returning the number of bytes freed.
+pub fn clean_cache(config: &Config) -> Result<u64, TypeErr> {
+    let freed = cache_entries(config)?.iter().map(|e| e.size).sum();
+    if Path::new(config.work_dir()).is_dir() {
+        fs::remove_dir_all(config.work_dir())?;
+    }
+    Ok(freed)
+}
@@ -1,60 +1,321 @@
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, Shell, SubCommand};
 
 pub fn init_cli_args() -> App<'static, 'static> {
     App::new("nbpm")
         .author(crate_authors!())
         .about("Nebula package manager")
         .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
         .arg(
             Arg::with_name("config")
                 .short("c")
                 .long("config")
                 .takes_value(true)
                 .value_name("path")
+                .global(true)
                 .help("read the configuration file from a custom path"),
         )
         .arg(
-            Arg::with_name("update-repos")
-                .short("u")
-                .long("update")
-                .conflicts_with_all(&["search", "install", "PKG", "remove"])
-                .takes_value(false)
-                .help("Update repostories"),
+            Arg::with_name("root")
+                .short("r")
+                .long("root")
+                .takes_value(true)
+                .value_name("path")
+                .global(true)
+                .help("root directory of the system to manage packages in"),
         )
         .arg(
-            Arg::with_name("search")
-                .short("s")
-                .long("search")
+            Arg::with_name("arch")
+                .long("arch")
                 .takes_value(true)
-                .value_name("package")
-                .conflicts_with_all(&["update-repos", "install", "remove"])
-                .help("Search for a package matching PKG"),
+                .value_name("arch")
+                .global(true)
+                .help("architecture of the system to install packages for"),
         )
         .arg(
-            Arg::with_name("install")
-                .short("i")
-                .long("install")
-                .takes_value(true)
-                .multiple(true)
-                .value_name("packages")
-                .conflicts_with_all(&["update-repos", "search", "remove"])
-                .help("Install a package or list of packages"),
+            Arg::with_name("allow-foreign-arch")
+                .long("allow-foreign-arch")
+                .takes_value(false)
+                .global(true)
+                .help("allow installing packages built for other architectures"),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .short("y")
+                .long("yes")
+                .visible_alias("noconfirm")
+                .takes_value(false)
+                .global(true)
+                .help("do not ask for confirmation before making changes"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .takes_value(false)
+                .global(true)
+                .help("print the output and the errors as JSON"),
+        )
+        .subcommand(SubCommand::with_name("update").about("Update the repository index"))
+        .subcommand(
+            SubCommand::with_name("search")
+                .about("Search for packages whose name or description contains the pattern")
+                .arg(
+                    Arg::with_name("regex")
+                        .long("regex")
+                        .takes_value(false)
+                        .conflicts_with("fuzzy")
+                        .help("use the pattern as a regular expression"),
+                )
+                .arg(
+                    Arg::with_name("fuzzy")
+                        .long("fuzzy")
+                        .takes_value(false)
+                        .help("search for names and words similar to the pattern"),
+                )
+                .arg(
+                    Arg::with_name("pattern")
+                        .help("pattern to search for")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("install")
+                .about("Install packages and their dependencies")
+                .arg(dry_run_arg())
+                .arg(
+                    Arg::with_name("packages")
+                        .help("package or packages to install")
+                        .required(true)
+                        .multiple(true),
+                ),
         )
         .subcommand(
             SubCommand::with_name("remove")
                 .about("Remove installed packages")
+                .arg(dry_run_arg())
                 .arg(
                     Arg::with_name("recursive")
                         .long("recursive")
                         .short("R")
-                        .help("Package or packages to remove")
+                        .help("also remove the dependencies of the packages")
                         .takes_value(false),
                 )
                 .arg(
                     Arg::with_name("packages")
-                        .help("Package or packages to remove")
+                        .help("package or packages to remove")
                         .required(true)
                         .multiple(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("upgrade")
+                .about("Upgrade the installed packages with a newer version in the repository")
+                .arg(dry_run_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Install, upgrade and remove packages to match a manifest")
+                .after_help(
+                    "Installed packages whose version matches the manifest are kept, even if the \
+                     repository has a newer matching version. Use `upgrade` to upgrade them.",
+                )
+                .arg(dry_run_arg())
+                .arg(
+                    Arg::with_name("prune")
+                        .long("prune")
+                        .takes_value(false)
+                        .help("remove the packages not in the manifest nor needed by it"),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .help("path to the manifest file")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("lock")
+                .about("Export the installed packages as a lockfile, or install a lockfile")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Export the exact installed packages as a lockfile")
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .takes_value(true)
+                                .value_name("path")
+                                .help("write the lockfile to a file instead of printing it"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("install")
+                        .about("Install exactly the packages of a lockfile")
+                        .arg(dry_run_arg())
+                        .arg(
+                            Arg::with_name("prune")
+                                .long("prune")
+                                .takes_value(false)
+                                .help("remove the packages not in the lockfile"),
+                        )
+                        .arg(
+                            Arg::with_name("allow-unhashed")
+                                .long("allow-unhashed")
+                                .takes_value(false)
+                                .help(
+                                    "accept packages whose hash is unknown, without checking them",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("lockfile")
+                                .help("path to the lockfile")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List the installed packages")
+                .arg(
+                    Arg::with_name("explicit")
+                        .short("e")
+                        .long("explicit")
+                        .takes_value(false)
+                        .conflicts_with_all(&["deps", "orphans"])
+                        .help("only packages installed explicitly"),
+                )
+                .arg(
+                    Arg::with_name("deps")
+                        .short("d")
+                        .long("deps")
+                        .takes_value(false)
+                        .help("only packages installed as dependencies"),
+                )
+                .arg(
+                    Arg::with_name("orphans")
+                        .short("o")
+                        .long("orphans")
+                        .takes_value(false)
+                        .help("only dependencies no installed package depends on"),
+                )
+                .arg(
+                    Arg::with_name("upgradable")
+                        .short("u")
+                        .long("upgradable")
+                        .takes_value(false)
+                        .help("only packages with a newer version in the repository"),
+                )
+                .arg(
+                    Arg::with_name("set")
+                        .short("s")
+                        .long("set")
+                        .takes_value(true)
+                        .value_name("set")
+                        .help("only packages of a set of the repository, for example: core"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .takes_value(true)
+                        .value_name("regex")
+                        .help("only packages whose name matches the regular expression"),
+                )
+                .arg(
+                    Arg::with_name("min-size")
+                        .long("min-size")
+                        .takes_value(true)
+                        .value_name("size")
+                        .help("only packages taking at least this size, for example: 10M"),
+                )
+                .arg(
+                    Arg::with_name("max-size")
+                        .long("max-size")
+                        .takes_value(true)
+                        .value_name("size")
+                        .help("only packages taking at most this size, for example: 512K"),
+                )
+                .arg(
+                    Arg::with_name("export")
+                        .long("export")
+                        .takes_value(false)
+                        .help("only print the names, one per line, to install them elsewhere"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show all the information about a package, available and installed")
+                .arg(
+                    Arg::with_name("package")
+                        .help("name of the package")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("files")
+                .about("List the files of an installed package")
+                .arg(
+                    Arg::with_name("package")
+                        .help("name of the package")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("owns")
+                .about("Show the installed packages a file belongs to")
+                .arg(
+                    Arg::with_name("path")
+                        .help("path of the file, relative to the root of the system")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check that the files of installed packages are in the system")
+                .arg(
+                    Arg::with_name("packages")
+                        .help("packages to check [default: all the installed packages]")
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Show the downloaded packages")
+                .arg(
+                    Arg::with_name("clean")
+                        .long("clean")
+                        .takes_value(false)
+                        .help("remove the downloaded packages"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show the changes made in the system")
+                .arg(
+                    Arg::with_name("last")
+                        .short("n")
+                        .long("last")
+                        .takes_value(true)
+                        .value_name("count")
+                        .help("only show the last entries"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Generate the completion script of a shell")
+                .arg(
+                    Arg::with_name("shell")
+                        .possible_values(&Shell::variants())
+                        .required(true),
+                ),
+        )
+}
+
+/// The `--dry-run` argument of the commands that make changes in the system.
+fn dry_run_arg() -> Arg<'static, 'static> {
+    Arg::with_name("dry-run")
+        .long("dry-run")
+        .takes_value(false)
+        .help("show what the command would do, without doing it")
 }
@@ -3,17 +3,48 @@ use serde_derive::{Deserialize, Serialize};
 use std::fs::read_to_string;
 use std::path::Path;
 
-use super::{NbpmError, DEF_NBPM_PATH, DEF_NBPM_REPO, DEF_NBPM_ROOT};
-use crate::TypeErr;
+use super::{
+    NbpmError, ARCH_PLACEHOLDER, DEF_NBPM_PARALLEL_DOWNLOADS, DEF_NBPM_PATH, DEF_NBPM_REPO,
+    DEF_NBPM_ROOT, NBPM_WORK_CURR, NBPM_WORK_DIR,
+};
+use crate::{TypeErr, HOST_ARCH};
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
     #[serde(rename = "nbpm-home", default = "get_default_nbpm_home")]
     home: String,
     /// Root directory of the system. In most of the cases you want this variable to be `/`.
     #[serde(rename = "root-dir", default = "get_default_nbpm_root")]
     root: String,
+    /// Architecture of the system, the host architecture by default.
+    #[serde(default = "get_default_arch")]
+    arch: String,
+    /// Allow installing packages built for an architecture other than `arch`.
+    #[serde(rename = "allow-foreign-arch", default)]
+    allow_foreign_arch: bool,
+    /// URL of the repository. It can be an HTTP(S) URL, a `file://` URL or a plain path to a
+    /// local directory. Any `{arch}` in the URL is replaced by `arch`.
     repo_url: String,
+    /// URLs of the mirrors of the repository. They are tried in order if `repo_url` fails.
+    #[serde(default)]
+    mirrors: Vec<String>,
+    /// Maximum number of packages to download at the same time.
+    #[serde(
+        rename = "parallel-downloads",
+        default = "get_default_parallel_downloads"
+    )]
+    parallel_downloads: usize,
+    /// Do not ask for confirmation before making changes in the system, for unattended runs.
+    #[serde(rename = "no-confirm", default)]
+    noconfirm: bool,
+    /// Working directory where packages are downloaded and extracted. It is wiped by every
+    /// operation, so processes sharing it must not run at the same time.
+    #[serde(rename = "work-dir", default = "get_default_work_dir")]
+    work_dir: String,
+    /// Install lockfiles even if the hash of a package is unknown, in the lockfile or in the
+    /// installed package, so the package cannot be checked.
+    #[serde(rename = "allow-unhashed", default)]
+    allow_unhashed: bool,
 }
 
 impl Config {
@@ -22,7 +53,14 @@ impl Config {
         Config {
             home: DEF_NBPM_PATH.to_string(),
             root: DEF_NBPM_ROOT.to_string(),
+            arch: HOST_ARCH.to_string(),
+            allow_foreign_arch: false,
             repo_url: DEF_NBPM_REPO.to_string(),
+            mirrors: vec![],
+            parallel_downloads: DEF_NBPM_PARALLEL_DOWNLOADS,
+            noconfirm: false,
+            work_dir: NBPM_WORK_DIR.to_string(),
+            allow_unhashed: false,
         }
     }
 
@@ -47,9 +85,87 @@ impl Config {
         &self.root
     }
 
+    /// Sets the root directory of the system, where packages are installed to.
+    pub fn set_root(&mut self, root: &str) {
+        self.root = root.to_string();
+    }
+
+    /// Sets the URL of the repository, removing all other mirrors.
+    pub fn set_repo_url(&mut self, repo_url: &str) {
+        self.repo_url = repo_url.to_string();
+        self.mirrors.clear();
+    }
+
     pub fn repo_url(&self) -> &str {
         &self.repo_url
     }
+
+    pub fn arch(&self) -> &str {
+        &self.arch
+    }
+
+    /// Sets the architecture of the system, for example, to provision a system of other
+    /// architecture in `root`.
+    pub fn set_arch(&mut self, arch: &str) {
+        self.arch = arch.to_string();
+    }
+
+    pub fn allow_foreign_arch(&self) -> bool {
+        self.allow_foreign_arch
+    }
+
+    pub fn set_allow_foreign_arch(&mut self, allow: bool) {
+        self.allow_foreign_arch = allow;
+    }
+
+    pub fn noconfirm(&self) -> bool {
+        self.noconfirm
+    }
+
+    pub fn set_noconfirm(&mut self, noconfirm: bool) {
+        self.noconfirm = noconfirm;
+    }
+
+    pub fn work_dir(&self) -> &str {
+        &self.work_dir
+    }
+
+    pub fn set_work_dir(&mut self, work_dir: &str) {
+        self.work_dir = work_dir.to_string();
+    }
+
+    /// Returns the directory inside `work_dir` where packages are extracted, see
+    /// `NBPM_WORK_CURR`.
+    pub fn work_curr(&self) -> String {
+        format!("{}/{}", self.work_dir, NBPM_WORK_CURR)
+    }
+
+    pub fn allow_unhashed(&self) -> bool {
+        self.allow_unhashed
+    }
+
+    pub fn set_allow_unhashed(&mut self, allow: bool) {
+        self.allow_unhashed = allow;
+    }
+
+    /// Returns the ordered list of urls the repository can be fetched from: `repo_url` followed by
+    /// the rest of the mirrors. Any `{arch}` in the urls is replaced by the architecture of the
+    /// system.
+    pub fn mirrors(&self) -> Vec<String> {
+        let mut mirrors = vec![self.repo_url.replace(ARCH_PLACEHOLDER, &self.arch)];
+        mirrors.extend(
+            self.mirrors
+                .iter()
+                .map(|m| m.replace(ARCH_PLACEHOLDER, &self.arch)),
+        );
+        mirrors
+    }
+
+    /// Returns the maximum number of packages to download at the same time, which is always at
+    /// least one.
+    pub fn parallel_downloads(&self) -> usize {
+        self.parallel_downloads.max(1)
+    }
 }
 
 fn get_default_nbpm_home() -> String {
@@ -60,6 +176,18 @@ fn get_default_nbpm_root() -> String {
     DEF_NBPM_ROOT.to_string()
 }
 
+fn get_default_arch() -> String {
+    HOST_ARCH.to_string()
+}
+
+fn get_default_work_dir() -> String {
+    NBPM_WORK_DIR.to_string()
+}
+
+fn get_default_parallel_downloads() -> usize {
+    DEF_NBPM_PARALLEL_DOWNLOADS
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self::new()
@@ -0,0 +1,369 @@
+//! Dry runs of the operations of nbpm: everything an operation would do in the system, computed
+//! without doing it.
+//!
+//! To know the files of the packages to install, packages are downloaded and decompressed in a
+//! private temporary directory, which is removed when the dry run ends. Nothing is written to
+//! the root of the system, to the local `PkgDb` nor to nbpm's working directory.
+
+use serde_derive::Serialize;
+use walkdir::WalkDir;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::files::local_paths;
+use super::install::{resolve_install, upgradable};
+use super::lock::{plan_locked, Lockfile};
+use super::manifest::{plan_apply, Manifest};
+use super::plan::sort_plan;
+use super::remove::plan_remove;
+use super::utils::{clean_work_curr, download_pkgs_to_workdir};
+use super::{Config, PlanItem, Ui};
+use crate::core::{pkgdb::PkgInfo, PkgDb};
+use crate::fetch::Fetcher;
+use crate::repo::REPO_PKG_INFO;
+use crate::{utils, TypeErr};
+
+/// Everything an operation would do in the system.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct DryRun {
+    /// The planned changes, see `plan_install` and `plan_remove`.
+    pub plan: Vec<PlanItem>,
+    /// Packages that would be downloaded.
+    pub downloads: Vec<Download>,
+    /// Files and directories that would be written, sorted by path.
+    pub writes: Vec<FileChange>,
+    /// Files and directories that would be deleted, sorted by path.
+    pub deletes: Vec<FileChange>,
+    /// Files that would be overwritten and do not belong to the package writing them.
+    pub conflicts: Vec<FileConflict>,
+    /// Total size in bytes of the downloaded packages.
+    pub download_size: u64,
+    /// Change of the disk usage in bytes of the system (negative if space is freed).
+    pub disk_usage: i64,
+}
+
+/// A package to download.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Download {
+    pub name: String,
+    /// Url of the mirror serving the package.
+    pub mirror: String,
+    /// Size in bytes of the compressed package.
+    pub size: u64,
+}
+
+/// A file (or directory) written or deleted by a package.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileChange {
+    pub path: String,
+    pub package: String,
+    /// Size in bytes of the file, 0 for directories.
+    pub size: u64,
+}
+
+/// A file that a package would overwrite.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileConflict {
+    pub path: String,
+    /// Package that would overwrite the file.
+    pub package: String,
+    /// Package the file belongs to: an installed package or another package of the same
+    /// operation. `None` if the file does not belong to any package.
+    pub owner: Option<String>,
+}
+
+/// Computes everything `install_handler` would do to install the packages in `names`. The
+/// packages are downloaded (reporting the progress to the `ui`) to read their files.
+///
+/// # Errors
+///
+/// See `plan_install` and `download_pkgs_to_workdir`. If a downloaded package cannot be
+/// decompressed or read, the error is returned.
+pub fn dry_run_install(
+    names: &[&str],
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<DryRun, TypeErr> {
+    let (graph, plan) = resolve_install(names, config, local_db, index_db)?;
+    dry_run_graph(&graph, plan, config, local_db, fetcher, ui)
+}
+
+/// Counter of the dry runs of this process, to give each one its own working directory.
+static DRY_RUN_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Computes everything `install_graph` would do to install the packages of a `graph`, planned as
+/// `plan`. See `dry_run_install`.
+///
+/// The packages are downloaded to a private working directory, so the cache of nbpm and other
+/// running operations are not touched. The directory is removed even if the dry run fails.
+fn dry_run_graph(
+    graph: &HashMap<String, &PkgInfo>,
+    plan: Vec<PlanItem>,
+    config: &Config,
+    local_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<DryRun, TypeErr> {
+    let work_dir = env::temp_dir().join(format!(
+        "nbpm-dry-run-{}-{}",
+        process::id(),
+        DRY_RUN_COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    let mut config = config.clone();
+    config.set_work_dir(&work_dir.to_string_lossy());
+
+    let res = dry_run_in_workdir(graph, plan, &config, local_db, fetcher, ui);
+    if work_dir.is_dir() {
+        fs::remove_dir_all(&work_dir)?;
+    }
+    res
+}
+
+/// Computes the dry run of `dry_run_graph`, using the working directory of the `config`.
+fn dry_run_in_workdir(
+    graph: &HashMap<String, &PkgInfo>,
+    plan: Vec<PlanItem>,
+    config: &Config,
+    local_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<DryRun, TypeErr> {
+    let mut dry_run = DryRun {
+        plan,
+        ..Default::default()
+    };
+    if graph.is_empty() {
+        return Ok(dry_run);
+    }
+
+    // owner of every file of the installed packages
+    let mut owners: HashMap<String, String> = HashMap::new();
+    for (name, info) in local_db.iter() {
+        for path in local_paths(info) {
+            owners.insert(path.to_string(), name.to_string());
+        }
+    }
+    // files written by the packages of the operation, and the package writing them
+    let mut written: HashMap<String, String> = HashMap::new();
+
+    let downl_files = download_pkgs_to_workdir(graph, config, fetcher, ui)?;
+    let work_curr = config.work_curr();
+    for (pkg_name, path, mirror) in downl_files {
+        let size = fs::metadata(&path)?.len();
+        dry_run.download_size += size;
+        dry_run.downloads.push(Download {
+            name: pkg_name.to_string(),
+            mirror,
+            size,
+        });
+
+        utils::run_cmd("tar", &["xf", path.as_str(), "-C", &work_curr])?;
+        // paths of the new version of the package
+        let mut pkg_paths = HashSet::new();
+        for entry in WalkDir::new(&work_curr).min_depth(1) {
+            let entry = entry?;
+            let real_path = entry.path();
+            // the nbinfo.toml file is not installed
+            if real_path.file_name().unwrap() == REPO_PKG_INFO {
+                continue;
+            }
+            let new_path = Path::new(config.root()).join(real_path.strip_prefix(&work_curr)?);
+            let new_path_str = new_path.to_string_lossy().to_string();
+            pkg_paths.insert(new_path_str.clone());
+
+            // same checks as `install_pkg_files`
+            if real_path.is_dir() && !new_path.exists() {
+                dry_run.writes.push(FileChange {
+                    path: new_path_str,
+                    package: pkg_name.to_string(),
+                    size: 0,
+                });
+            } else if real_path.is_file() {
+                let size = entry.metadata()?.len();
+                dry_run.disk_usage += size as i64;
+                if new_path.is_file() {
+                    dry_run.disk_usage -= fs::metadata(&new_path)?.len() as i64;
+                }
+                // the file conflicts if another package of the operation writes it, or if it
+                // exists and does not belong to the package (the installed version of it)
+                let owner = match written.get(&new_path_str) {
+                    Some(other) => Some(Some(other.to_string())),
+                    None if new_path.exists() => match owners.get(&new_path_str) {
+                        Some(o) if *o == pkg_name => None,
+                        o => Some(o.cloned()),
+                    },
+                    None => None,
+                };
+                if let Some(owner) = owner {
+                    dry_run.conflicts.push(FileConflict {
+                        path: new_path_str.clone(),
+                        package: pkg_name.to_string(),
+                        owner,
+                    });
+                }
+                written.insert(new_path_str.clone(), pkg_name.to_string());
+                dry_run.writes.push(FileChange {
+                    path: new_path_str,
+                    package: pkg_name.to_string(),
+                    size,
+                });
+            }
+        }
+        clean_work_curr(config)?;
+
+        // files of the installed version that the new version does not have anymore, as
+        // `install_graph` removes them
+        let old_paths = local_db.get_pkg_info(&pkg_name).map(local_paths);
+        for path in old_paths.unwrap_or(&[]) {
+            let owner = owners.get(path).map(|o| o.as_str());
+            if pkg_paths.contains(path) || owner != Some(pkg_name.as_str()) {
+                continue;
+            }
+            if let Some(meta) = fs::symlink_metadata(path).ok().filter(|m| m.is_file()) {
+                dry_run.disk_usage -= meta.len() as i64;
+                dry_run.deletes.push(FileChange {
+                    path: path.to_string(),
+                    package: pkg_name.to_string(),
+                    size: meta.len(),
+                });
+            }
+        }
+    }
+    dry_run.writes.sort_by(|a, b| a.path.cmp(&b.path));
+    dry_run.deletes.sort_by(|a, b| a.path.cmp(&b.path));
+    dry_run.conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(dry_run)
+}
+
+/// Computes everything `upgrade_handler` would do. See `dry_run_install`.
+pub fn dry_run_upgrade(
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<DryRun, TypeErr> {
+    let names = upgradable(local_db, index_db);
+    let names: Vec<&str> = names.iter().map(|s| s.as_str()).collect();
+    dry_run_install(&names, config, local_db, index_db, fetcher, ui)
+}
+
+/// Computes everything `remove_handler` would do to remove the packages in `to_remove`.
+/// Directories are only deleted if all their contents are deleted too, as `remove_handler` only
+/// removes empty directories.
+///
+/// # Errors
+///
+/// See `plan_remove`.
+pub fn dry_run_remove(
+    to_remove: &[&str],
+    recursive: bool,
+    check_conflicts: bool,
+    local_db: &PkgDb,
+) -> Result<DryRun, TypeErr> {
+    let plan = plan_remove(to_remove, recursive, check_conflicts, local_db)?;
+    Ok(dry_run_planned_remove(plan, local_db))
+}
+
+/// Computes everything `remove_planned` would do to remove the packages of the `plan`. See
+/// `dry_run_remove`.
+fn dry_run_planned_remove(plan: Vec<PlanItem>, local_db: &PkgDb) -> DryRun {
+    let mut dry_run = DryRun::default();
+    let mut dirs = vec![];
+    let mut deleted = BTreeSet::new();
+    for item in &plan {
+        // it's safe to call unwrap here, as all the packages of the plan are in the local db
+        let info = local_db.get_pkg_info(&item.name).unwrap();
+        for path in local_paths(info).iter().map(Path::new) {
+            if path.is_dir() {
+                dirs.push((path, &item.name));
+            } else if let Ok(meta) = fs::symlink_metadata(path) {
+                dry_run.disk_usage -= meta.len() as i64;
+                deleted.insert(path.to_path_buf());
+                dry_run.deletes.push(FileChange {
+                    path: path.to_string_lossy().to_string(),
+                    package: item.name.to_string(),
+                    size: meta.len(),
+                });
+            }
+        }
+    }
+
+    // nested directories are checked before their parents, as in `remove_local_pkg_files`
+    dirs.sort_by(|a, b| b.0.cmp(a.0));
+    for (dir, pkg_name) in dirs {
+        let empty = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .all(|e| deleted.contains(&e.path())),
+            Err(_) => false,
+        };
+        if empty {
+            deleted.insert(dir.to_path_buf());
+            dry_run.deletes.push(FileChange {
+                path: dir.to_string_lossy().to_string(),
+                package: pkg_name.to_string(),
+                size: 0,
+            });
+        }
+    }
+    dry_run.deletes.sort_by(|a, b| a.path.cmp(&b.path));
+    dry_run.plan = plan;
+    dry_run
+}
+
+/// Computes everything `apply_handler` would do to apply the `manifest`. See `dry_run_install`
+/// and `dry_run_remove`.
+pub fn dry_run_apply(
+    manifest: &Manifest,
+    prune: bool,
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<DryRun, TypeErr> {
+    let apply = plan_apply(manifest, prune, config, local_db, index_db)?;
+    let mut dry_run = dry_run_graph(&apply.graph, apply.install, config, local_db, fetcher, ui)?;
+    let removal = dry_run_planned_remove(apply.remove, local_db);
+    dry_run.plan.extend(removal.plan);
+    sort_plan(&mut dry_run.plan);
+    dry_run.deletes.extend(removal.deletes);
+    dry_run.deletes.sort_by(|a, b| a.path.cmp(&b.path));
+    dry_run.disk_usage += removal.disk_usage;
+    Ok(dry_run)
+}
+
+/// Computes everything `lock_install_handler` would do to install the `lock`. The hashes of the
+/// lock are not checked. See `dry_run_install` and `dry_run_remove`.
+pub fn dry_run_lock_install(
+    lock: &Lockfile,
+    prune: bool,
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<DryRun, TypeErr> {
+    let locked = plan_locked(lock, prune, config, local_db, index_db)?;
+    let mut dry_run = dry_run_graph(&locked.graph, locked.install, config, local_db, fetcher, ui)?;
+    let removal = dry_run_planned_remove(locked.remove, local_db);
+    dry_run.plan.extend(removal.plan);
+    sort_plan(&mut dry_run.plan);
+    dry_run.deletes.extend(removal.deletes);
+    dry_run.deletes.sort_by(|a, b| a.path.cmp(&b.path));
+    dry_run.disk_usage += removal.disk_usage;
+    Ok(dry_run)
+}
@@ -1,4 +1,4 @@
-use semver::Version;
+use semver::{Version, VersionReq};
 
 use std::error::Error;
 use std::fmt;
@@ -19,6 +19,24 @@ pub enum NbpmError {
     CannotRemove(Vec<(PathBuf, Box<dyn Error>)>),
     /// Contains name and errors of the packages that couldn't be removed
     CannotRemovePkgs(Vec<(String, Box<dyn Error>)>),
+    /// Contains name and errors of the packages that couldn't be downloaded
+    CannotDownloadPkgs(Vec<(String, Box<dyn Error>)>),
+    /// Contains the url and error of every mirror that failed to serve a file
+    MirrorsFailed(Vec<(String, Box<dyn Error>)>),
+    /// Contains the architecture of the system and the name and architecture of every package
+    /// built for another architecture
+    ForeignArchPkgs(String, Vec<(String, String)>),
+    /// A package of a manifest does not match the version requirement of the manifest. Contains
+    /// the name of the package, the requirement and the version available in the repository
+    ManifestVersion(String, VersionReq, Version),
+    /// A package cannot be installed as recorded in a lockfile. Contains the name of the package
+    /// and the reason
+    LockMismatch(String, String),
+    /// Confirmation was required, but the standard input is not a terminal
+    NonInteractive,
+    /// Pruning with an empty manifest or lockfile, that would remove every installed package.
+    /// Contains the kind of file ("manifest" or "lockfile")
+    PruneEverything(String),
 }
 
 impl fmt::Display for NbpmError {
@@ -60,6 +78,49 @@ impl fmt::Display for NbpmError {
                 }
                 Ok(())
             }
+            NbpmError::CannotDownloadPkgs(pkgs) => {
+                writeln!(f, "The following packages could not be downloaded:")?;
+                for (p, e) in pkgs {
+                    writeln!(f, "  - {}: {}", p, e)?;
+                }
+                Ok(())
+            }
+            NbpmError::ForeignArchPkgs(arch, pkgs) => {
+                writeln!(
+                    f,
+                    "The following packages are not built for the {} architecture:",
+                    arch
+                )?;
+                for (p, a) in pkgs {
+                    writeln!(f, "  - {} ({})", p, a)?;
+                }
+                Ok(())
+            }
+            NbpmError::MirrorsFailed(mirrors) => {
+                writeln!(f, "All mirrors failed:")?;
+                for (m, e) in mirrors {
+                    writeln!(f, "    * {}: {}", m, e)?;
+                }
+                Ok(())
+            }
+            NbpmError::ManifestVersion(name, req, ver) => write!(
+                f,
+                "The manifest requires {} {}, but the repository has version {}",
+                name, req, ver
+            ),
+            NbpmError::LockMismatch(name, detail) => {
+                write!(f, "Cannot install {} as locked: {}", name, detail)
+            }
+            NbpmError::NonInteractive => write!(
+                f,
+                "Cannot ask for confirmation, the standard input is not a terminal \
+                (use --yes to proceed without confirmation)"
+            ),
+            NbpmError::PruneEverything(kind) => write!(
+                f,
+                "The {} has no packages, pruning would remove every installed package",
+                kind
+            ),
         }
     }
 }
@@ -0,0 +1,89 @@
+//! Files of the installed packages: listing them, finding the package a file belongs to and
+//! checking that they are still in the system.
+
+use serde_derive::Serialize;
+
+use std::path::Path;
+
+use super::Config;
+use crate::core::{pkgdb::PkgInfo, NbError, PkgDb, SetInfo};
+use crate::TypeErr;
+
+/// Result of checking the files of an installed package.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct FileCheck {
+    pub name: String,
+    /// Number of files and directories of the package.
+    pub files: usize,
+    /// Files and directories of the package that are not in the system anymore.
+    pub missing: Vec<String>,
+}
+
+/// Returns the paths of the files and directories of the installed package `name`, sorted.
+/// Meta-packages have no files.
+///
+/// # Errors
+///
+/// If the package is not installed, a `NbError::PkgNotFound` error is returned.
+pub fn pkg_files(name: &str, local_db: &PkgDb) -> Result<Vec<String>, TypeErr> {
+    match local_db.get_pkg_info(name) {
+        Some(info) => {
+            let mut paths = local_paths(info).to_vec();
+            paths.sort();
+            Ok(paths)
+        }
+        None => Err(Box::new(NbError::PkgNotFound(name.to_string()))),
+    }
+}
+
+/// Returns the names of the installed packages that contain the file (or directory) in `path`,
+/// sorted. `path` is relative to the root of the system in `config`, even if absolute.
+pub fn owners(path: &str, config: &Config, local_db: &PkgDb) -> Vec<String> {
+    let path = Path::new(config.root()).join(path.trim_start_matches('/'));
+    let mut names: Vec<String> = local_db
+        .iter()
+        .filter(|(_, info)| local_paths(info).iter().any(|p| Path::new(p) == path))
+        .map(|(name, _)| name.to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Checks that all the files of the installed packages in `names` (all the installed packages if
+/// `None`) are still in the system. Results are sorted by package name.
+///
+/// # Errors
+///
+/// If a package is not installed, a `NbError::PkgNotFound` error is returned.
+pub fn verify_files(names: Option<&[&str]>, local_db: &PkgDb) -> Result<Vec<FileCheck>, TypeErr> {
+    let mut names: Vec<String> = match names {
+        Some(n) => n.iter().map(|s| s.to_string()).collect(),
+        None => local_db.iter().map(|(name, _)| name.to_string()).collect(),
+    };
+    names.sort();
+
+    let mut checks = vec![];
+    for name in names {
+        let paths = pkg_files(&name, local_db)?;
+        let missing = paths
+            .iter()
+            .filter(|p| Path::new(p).symlink_metadata().is_err())
+            .cloned()
+            .collect();
+        checks.push(FileCheck {
+            name,
+            files: paths.len(),
+            missing,
+        });
+    }
+    Ok(checks)
+}
+
+/// Returns the paths of the files of an installed package, empty for meta-packages.
+pub(crate) fn local_paths(info: &PkgInfo) -> &[String] {
+    match info.set_info() {
+        Some(SetInfo::Local(l)) => l.paths(),
+        _ => &[],
+    }
+}
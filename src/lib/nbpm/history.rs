@@ -0,0 +1,50 @@
+//! History of the changes made by nbpm in the system.
+//!
+//! Every entry is appended to `HISTORY_PATH` as a `[[entry]]` table, so the history file is
+//! never rewritten.
+
+use serde_derive::{Deserialize, Serialize};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use super::{Config, PlanItem, HISTORY_PATH};
+use crate::TypeErr;
+
+/// A command that made changes in the system.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct HistoryEntry {
+    /// Date of the changes, in seconds since the Unix epoch.
+    pub date: u64,
+    /// The command that made the changes, for example: `install foo bar`.
+    pub command: String,
+    pub changes: Vec<PlanItem>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct History {
+    #[serde(default)]
+    entry: Vec<HistoryEntry>,
+}
+
+/// Appends an entry to the history of the system in `config`.
+pub fn record(config: &Config, entry: HistoryEntry) -> Result<(), TypeErr> {
+    let history = toml::to_string(&History { entry: vec![entry] })?;
+    let path = Path::new(config.home()).join(HISTORY_PATH);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(history.as_bytes())?;
+    Ok(())
+}
+
+/// Reads the history of the system in `config`, oldest entries first. If there is no history
+/// file, the history is empty.
+pub fn read_history(config: &Config) -> Result<Vec<HistoryEntry>, TypeErr> {
+    let path = Path::new(config.home()).join(HISTORY_PATH);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let history: History = toml::from_str(&fs::read_to_string(path)?)?;
+    Ok(history.entry)
+}
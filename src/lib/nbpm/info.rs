@@ -0,0 +1,95 @@
+//! Detailed information about a package, from both the repository index and the local `PkgDb`.
+
+use semver::Version;
+use serde_derive::Serialize;
+
+use std::fs;
+use std::path::Path;
+
+use crate::core::{pkgdb::PkgInfo, NbError, PkgDb, SetInfo};
+use crate::TypeErr;
+
+/// All the information about a package available in the index and the local `PkgDb`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct PkgDetails {
+    pub name: String,
+    /// The package as found in the repository index, if available in the repository.
+    pub index: Option<PkgInfo>,
+    /// Names of the packages of the index that depend on this package.
+    pub index_required_by: Vec<String>,
+    /// The installed package, if installed.
+    pub local: Option<PkgInfo>,
+    /// Names of the installed packages that depend on this package.
+    pub local_required_by: Vec<String>,
+    /// Size in bytes of the installed files of the package, if installed.
+    pub installed_size: Option<u64>,
+    /// Number of installed files (directories not included) of the package, if installed.
+    pub file_count: Option<usize>,
+    /// Version of the package in the index, if newer than the installed version.
+    pub upgrade: Option<Version>,
+}
+
+/// Collects all the information about the package `name` from the `index_db` and the
+/// `local_db`. The size and the files of installed packages are read from the filesystem, files
+/// that do not exist anymore are ignored.
+///
+/// # Errors
+///
+/// If the package is neither in the index nor installed, a `NbError::PkgNotFound` error is
+/// returned.
+pub fn pkg_details(name: &str, index_db: &PkgDb, local_db: &PkgDb) -> Result<PkgDetails, TypeErr> {
+    let index = index_db.get_pkg_info(name).cloned();
+    let local = local_db.get_pkg_info(name).cloned();
+    if index.is_none() && local.is_none() {
+        return Err(Box::new(NbError::PkgNotFound(name.to_string())));
+    }
+
+    let (installed_size, file_count) = match local.as_ref().map(|i| i.set_info()) {
+        Some(Some(SetInfo::Local(set))) => {
+            let (size, count) = files_size(set.paths());
+            (Some(size), Some(count))
+        }
+        // installed meta-packages have no files
+        Some(_) => (Some(0), Some(0)),
+        None => (None, None),
+    };
+
+    let upgrade = match (&index, &local) {
+        (Some(i), Some(l)) if i.version() > l.version() => Some(i.version().clone()),
+        _ => None,
+    };
+
+    Ok(PkgDetails {
+        name: name.to_string(),
+        index_required_by: match index {
+            Some(_) => index_db.reverse_depends(name),
+            None => vec![],
+        },
+        local_required_by: match local {
+            Some(_) => local_db.reverse_depends(name),
+            None => vec![],
+        },
+        index,
+        local,
+        installed_size,
+        file_count,
+        upgrade,
+    })
+}
+
+/// Returns the total size in bytes and the number of the files (directories not included) in
+/// `paths`. Missing paths are ignored.
+pub(crate) fn files_size(paths: &[String]) -> (u64, usize) {
+    let mut size = 0;
+    let mut count = 0;
+    for path in paths {
+        if let Ok(meta) = fs::symlink_metadata(Path::new(path)) {
+            if !meta.is_dir() {
+                size += meta.len();
+                count += 1;
+            }
+        }
+    }
+    (size, count)
+}
@@ -1,25 +1,95 @@
 use walkdir::WalkDir;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::Path;
 
+use super::files::local_paths;
+use super::ui::{Event, Prompt, Ui};
 use super::utils::{clean_work_curr, download_pkgs_to_workdir};
-use super::NBPM_WORK_CURR;
-use super::{remove::*, Config, NbpmError};
-use crate::core::{pkgdb::PkgInfo, PkgDb, SetInfo};
+use super::{remove::*, Config, NbpmError, PlanItem, Transaction, NBPM_WORK_BACKUP};
+use crate::core::{pkgdb::PkgInfo, InstallReason, NbError, PkgDb, SetInfo};
+use crate::fetch::Fetcher;
 use crate::repo::REPO_PKG_INFO;
 use crate::{utils, TypeErr};
 
-/// Given a vector of tuples containing package names and paths to the compressed packages, the
-/// function installs this compressed packages on the system and updates the local `PkgDb`.
+/// Computes the changes needed to install the packages in `names` and all their dependencies
+/// from `index_db`: packages not installed yet are installed, and packages installed with an
+/// older version are updated. The returned plan is sorted by name, and it is empty if all the
+/// packages are already installed.
 ///
-/// **NOTE**: You might want to call `nbpm::utils::purge_already_installed` before this function.
-/// In order to avoid installing already installed packages.
+/// # Errors
+///
+/// The function returns an error in the following cases:
+///
+/// - A package (or a dependency) is not in the index, or the dependencies are broken.
+/// - A package is built for another architecture (see `nbpm::utils::check_arch`).
+/// - A package requires downgrading an installed package.
+pub fn plan_install(
+    names: &[&str],
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+) -> Result<Vec<PlanItem>, TypeErr> {
+    resolve_install(names, config, local_db, index_db).map(|(_, plan)| plan)
+}
+
+/// Returns the graph of the packages to install (or update) in order to install the packages in
+/// `names`, and the plan of the installation. See `plan_install`.
+pub(crate) fn resolve_install<'a>(
+    names: &[&str],
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &'a PkgDb,
+) -> Result<(HashMap<String, &'a PkgInfo>, Vec<PlanItem>), TypeErr> {
+    let mut graph = index_db.get_subgraph(Some(names), true)?;
+    super::utils::check_arch(&graph, config)?;
+    // remove the already installed packages from the graph
+    let plan = super::utils::purge_already_installed(&mut graph, local_db)?;
+    Ok((graph, plan))
+}
+
+/// Returns the names of the installed packages that have a newer version in `index_db`, sorted.
+pub fn upgradable(local_db: &PkgDb, index_db: &PkgDb) -> Vec<String> {
+    let mut names: Vec<String> = local_db
+        .iter()
+        .filter(|(name, info)| match index_db.get_pkg_info(name) {
+            Some(new) => new.version() > info.version(),
+            None => false,
+        })
+        .map(|(name, _)| name.to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Computes the changes needed to upgrade all the installed packages with a newer version in
+/// `index_db` (see `upgradable`). New dependencies of the upgraded packages are installed too.
+///
+/// # Errors
+///
+/// See `plan_install`.
+pub fn plan_upgrade(
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+) -> Result<Vec<PlanItem>, TypeErr> {
+    let names = upgradable(local_db, index_db);
+    let names: Vec<&str> = names.iter().map(|s| s.as_str()).collect();
+    plan_install(&names, config, local_db, index_db)
+}
+
+/// Installs the packages in `names` and all their dependencies, as planned by `plan_install`,
+/// and updates the local `PkgDb`. The plan is reported to the `ui`, that is asked for
+/// confirmation before making any change. Packages are downloaded from the repository using the
+/// given `fetcher`, and the progress of the installation is reported to the `ui` too.
+///
+/// If the installation fails, the already installed packages are removed.
 ///
 /// # Errors
 /// The function returns an error in the following cases:
 ///
+/// - The packages cannot be installed, see `plan_install`.
 /// - The path to the compressed package is invalid.
 /// - Cannot decompress the package.
 /// - Cannot read or deserialize the `pkginfo` file of the decompressed package.
@@ -30,45 +100,142 @@ pub fn install_handler(
     config: &Config,
     local_db: &mut PkgDb,
     index_db: &PkgDb,
-) -> Result<(), TypeErr> {
-    let mut graph = index_db.get_subgraph(Some(&names), true)?;
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<Transaction, TypeErr> {
+    let (graph, plan) = resolve_install(names, config, local_db, index_db)?;
+    install_resolved(
+        graph,
+        plan,
+        names,
+        Prompt::Install,
+        config,
+        local_db,
+        fetcher,
+        ui,
+    )
+}
+
+/// Upgrades all the installed packages with a newer version in `index_db`, as planned by
+/// `plan_upgrade`. The install reason of the upgraded packages is kept. See `install_handler`.
+pub fn upgrade_handler(
+    config: &Config,
+    local_db: &mut PkgDb,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<Transaction, TypeErr> {
+    let names = upgradable(local_db, index_db);
+    let names: Vec<&str> = names.iter().map(|s| s.as_str()).collect();
+    let (graph, plan) = resolve_install(&names, config, local_db, index_db)?;
+    install_resolved(
+        graph,
+        plan,
+        &[],
+        Prompt::Upgrade,
+        config,
+        local_db,
+        fetcher,
+        ui,
+    )
+}
 
-    // remove the already installed packages from the graph, this function will also show the
-    // action nbpm will take for every package (install/update...)
-    super::utils::purge_already_installed(&mut graph, &local_db)?;
+/// Installs the packages of a `graph` computed by `resolve_install`, asking the `ui` the given
+/// `prompt` first. The packages in `requested` are marked as explicitly installed.
+#[allow(clippy::too_many_arguments)]
+fn install_resolved(
+    graph: HashMap<String, &PkgInfo>,
+    plan: Vec<PlanItem>,
+    names: &[&str],
+    prompt: Prompt,
+    config: &Config,
+    local_db: &mut PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<Transaction, TypeErr> {
+    ui.event(Event::Plan(&plan));
 
     // after pkg graph purge, check if there is any package to be installed
     if graph.is_empty() {
-        println!("Packages already installed. Skipping the installation...");
-        return Ok(());
+        mark_explicit(names, local_db);
+        return Ok(Transaction { plan, done: true });
     }
-
-    // show the packages to be installed and ask for user confirmation
-    println!("Packages to be installed ({}):", graph.len());
-    match super::utils::read_line("\nAre you sure you want to install this packages? [Y/n] ") {
-        Ok(line) => {
-            if !line.is_empty() && line != "y" && line != "Y" {
-                println!("Operation cancelled");
-                return Ok(());
-            }
-        }
-        Err(e) => return Err(e),
+    if !ui.confirm(prompt) {
+        return Ok(Transaction { plan, done: false });
     }
+    install_graph(
+        &graph,
+        &plan,
+        names,
+        &HashMap::new(),
+        config,
+        local_db,
+        fetcher,
+        ui,
+    )?;
+    Ok(Transaction { plan, done: true })
+}
 
-    let downl_files = download_pkgs_to_workdir(&graph, &config)?;
+/// Installs the packages of a `graph` computed by `resolve_install`, without asking for
+/// confirmation, and marks the packages in `names` as explicitly installed. The installed
+/// packages of the `plan` are reported to the `ui`.
+///
+/// The SHA256 hash of the archive of every package is recorded in the local `PkgDb`. If a
+/// package is in `hashes`, the hash of its archive must match, else the installation fails with
+/// a `NbError::HashMismatch` error.
+///
+/// Files of the installed versions of updated packages are replaced by the ones of the new
+/// versions, and the files the new versions do not have anymore are removed. If the
+/// installation fails, the newly installed packages are removed and the updated packages are
+/// restored to their installed versions (see `undo_install`).
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn install_graph(
+    graph: &HashMap<String, &PkgInfo>,
+    plan: &[PlanItem],
+    names: &[&str],
+    hashes: &HashMap<String, String>,
+    config: &Config,
+    local_db: &mut PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<(), TypeErr> {
+    if graph.is_empty() {
+        mark_explicit(names, local_db);
+        return Ok(());
+    }
+    let downl_files = download_pkgs_to_workdir(graph, config, fetcher, ui)?;
+    let work_curr = config.work_curr();
 
     let mut installed_pkgs = vec![]; // names of the installed packages
+                                     // installed versions of the updated packages, their files are kept in `backup_dir`
+    let mut backups: HashMap<String, PkgInfo> = HashMap::new();
+    let backup_dir = Path::new(config.work_dir()).join(NBPM_WORK_BACKUP);
     let mut status: Result<(), TypeErr> = Ok(());
-    for (pkg_name, path) in downl_files {
-        println!("\n[*] Decompressing {}...", path);
+    for (pkg_name, path, mirror) in downl_files {
+        let hash = match utils::file2hash(Path::new(&path)) {
+            Ok(h) => h,
+            Err(e) => {
+                status = Err(e);
+                break;
+            }
+        };
+        if let Some(expected) = hashes.get(&pkg_name).filter(|h| **h != hash) {
+            status = Err(Box::new(NbError::HashMismatch(
+                path,
+                expected.to_string(),
+                hash,
+            )));
+            break;
+        }
+
         // decompress the downloaded package in nbpm's current working dir
-        if let Err(e) = utils::run_cmd("tar", &["xvf", path.as_str(), "-C", NBPM_WORK_CURR]) {
+        if let Err(e) = utils::run_cmd("tar", &["xvf", path.as_str(), "-C", &work_curr]) {
             status = Err(e);
             break;
         }
 
         // read and deserialize the info file of the package
-        let info_str = match fs::read_to_string(format!("{}/{}", NBPM_WORK_CURR, REPO_PKG_INFO)) {
+        let info_str = match fs::read_to_string(format!("{}/{}", work_curr, REPO_PKG_INFO)) {
             Ok(v) => v,
             Err(e) => {
                 status = Err(Box::new(e));
@@ -87,57 +254,205 @@ pub fn install_handler(
         let mut info = pkg_info.remove(&pkg_name).unwrap(); // get the `PkgInfo` object
 
         // set the prefix of the package's file paths to the root path specified in the
-        // config file
+        // config file, and record where the package was downloaded from
         match info.mut_set_info() {
-            Some(SetInfo::Local(set)) => set.set_path_prefix(Path::new(config.root())),
+            Some(SetInfo::Local(set)) => {
+                set.set_path_prefix(Path::new(config.root()));
+                set.set_origin(&mirror);
+                if let Some(Some(SetInfo::Universe(u))) = graph.get(&pkg_name).map(|i| i.set_info())
+                {
+                    set.set_location(u.location());
+                }
+                set.set_sha256(&hash);
+            }
             Some(SetInfo::Universe(_)) => unreachable!(),
             None => (), // the package is a meta-package, it does not contain any Local set info to modify
         }
+        // record why and when the package was installed
+        info.set_install_reason(install_reason(&pkg_name, names, local_db));
+        info.set_install_date(utils::unix_time());
+        // keep the files of the installed version, to restore them if the installation fails
+        if let Some(old) = local_db.get_pkg_info(&pkg_name) {
+            if let Err(e) = backup_pkg_files(old, &backup_dir.join(&pkg_name)) {
+                status = Err(e);
+                break;
+            }
+            backups.insert(pkg_name.clone(), old.clone());
+        }
         // push the package data to the local db
         let _ = local_db.insert(&pkg_name, info);
-        println!("[*] Installing {}...", pkg_name);
         installed_pkgs.push(pkg_name);
 
         // installl all the files of the package
-        if let Err(e) = install_pkg_files(NBPM_WORK_CURR, config.root()) {
+        if let Err(e) = install_pkg_files(&work_curr, config.root(), ui) {
             status = Err(e);
             break;
         }
+        if let Some(item) = plan.iter().find(|p| Some(&p.name) == installed_pkgs.last()) {
+            ui.event(Event::PkgInstalled(item));
+        }
 
         // clean the installation working directory to be used with other package
-        if let Err(e) = clean_work_curr() {
+        if let Err(e) = clean_work_curr(config) {
             status = Err(e);
             break;
         }
     }
 
+    if status.is_err() {
+        // something went wrong, try to undo changes
+        undo_install(&installed_pkgs, &backups, &backup_dir, plan, local_db, ui);
+        let _ = fs::remove_dir_all(&backup_dir);
+        return status;
+    }
+
+    // remove the files the updated packages do not have anymore, unless another package owns
+    // them now
+    let kept: HashSet<&String> = local_db
+        .iter()
+        .flat_map(|(_, info)| local_paths(info))
+        .collect();
+    for old in backups.values() {
+        let dropped: Vec<&Path> = local_paths(old)
+            .iter()
+            .filter(|p| !kept.contains(p))
+            .map(Path::new)
+            .collect();
+        if let Err(e) = remove_paths(&dropped) {
+            ui.event(Event::Error(e.as_ref()));
+        }
+    }
+    let _ = fs::remove_dir_all(&backup_dir);
+
     // get metapackages of the graph and insert them into the local db as they are considered
     // installed on the system
     graph
         .iter()
         .filter(|(_, &info)| info.is_meta())
         .for_each(|(name, &info)| {
-            let _ = local_db.insert(name, info.clone());
+            let mut info = info.clone();
+            info.set_install_reason(install_reason(name, names, local_db));
+            info.set_install_date(utils::unix_time());
+            let _ = local_db.insert(name, info);
+            if let Some(item) = plan.iter().find(|p| p.name == *name) {
+                ui.event(Event::PkgInstalled(item));
+            }
         });
+    mark_explicit(names, local_db);
+    Ok(())
+}
 
-    if status.is_err() {
-        // something went wrong, try to undo changes
-        println!("\n[!] Trying to undo the installation...");
-        let names_list: Vec<&str> = installed_pkgs.iter().map(|s| s.as_str()).collect();
-        // let installed_graph = local_db.get_subgraph(Some(&names_list), false)?;
-        remove_handler(&names_list, false, false, false, local_db)?;
+/// Undoes the installation of the packages in `installed` after a failed installation. Packages
+/// that were not installed before are removed, and updated packages are restored to their
+/// installed version in `backups`: the files added by the new version are removed, the files
+/// in `backup_dir` are copied back and the previous `PkgInfo` is put back in the local `PkgDb`.
+/// Every restored package is reported to the `ui`, as well as the errors undoing the changes.
+fn undo_install(
+    installed: &[String],
+    backups: &HashMap<String, PkgInfo>,
+    backup_dir: &Path,
+    plan: &[PlanItem],
+    local_db: &mut PkgDb,
+    ui: &dyn Ui,
+) {
+    let fresh: Vec<&str> = installed
+        .iter()
+        .filter(|name| !backups.contains_key(*name))
+        .map(|s| s.as_str())
+        .collect();
+    if !fresh.is_empty() {
+        let res = plan_remove(&fresh, false, false, local_db)
+            .and_then(|undo_plan| remove_planned(&undo_plan, local_db, ui));
+        if let Err(e) = res {
+            ui.event(Event::Error(e.as_ref()));
+        }
+    }
+
+    for name in installed.iter().filter(|name| backups.contains_key(*name)) {
+        let old = &backups[name];
+        let new_paths = local_db.get_pkg_info(name).map(local_paths).unwrap_or(&[]);
+        let added: Vec<&Path> = new_paths
+            .iter()
+            .filter(|p| !local_paths(old).contains(p))
+            .map(Path::new)
+            .collect();
+        let res = remove_paths(&added).and_then(|_| restore_pkg_files(old, &backup_dir.join(name)));
+        if let Err(e) = res {
+            ui.event(Event::Error(e.as_ref()));
+        }
+        let _ = local_db.insert(name, old.clone());
+        if let Some(item) = plan.iter().find(|p| p.name == *name) {
+            ui.event(Event::PkgRestored(item));
+        }
+    }
+}
+
+/// Copies the files of the installed package `info` into `backup`, keeping their paths.
+fn backup_pkg_files(info: &PkgInfo, backup: &Path) -> Result<(), TypeErr> {
+    for path in local_paths(info).iter().map(Path::new) {
+        if path.is_file() {
+            let dest = backup.join(path.strip_prefix("/").unwrap_or(path));
+            // it's safe to call unwrap here, as `dest` is inside `backup`
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::copy(path, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies back the files of the package `info` saved in `backup` by `backup_pkg_files`.
+fn restore_pkg_files(info: &PkgInfo, backup: &Path) -> Result<(), TypeErr> {
+    for path in local_paths(info).iter().map(Path::new) {
+        let saved = backup.join(path.strip_prefix("/").unwrap_or(path));
+        if saved.is_file() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&saved, path)?;
+        }
     }
-    status
+    Ok(())
 }
 
-pub fn install_pkg_files(from: &str, to: &str) -> Result<(), TypeErr> {
+/// Returns the reason to record for the package `name` when installed: `Explicit` if the user
+/// requested it, else the reason of the installed version (for updated packages) or
+/// `Dependency`.
+fn install_reason(name: &str, requested: &[&str], local_db: &PkgDb) -> InstallReason {
+    if requested.contains(&name) {
+        return InstallReason::Explicit;
+    }
+    local_db
+        .get_pkg_info(name)
+        .and_then(|info| info.install_reason())
+        .unwrap_or(InstallReason::Dependency)
+}
+
+/// Marks the `requested` packages of the local `PkgDb` as explicitly installed, as packages
+/// previously installed as dependencies might be requested by the user later.
+fn mark_explicit(requested: &[&str], local_db: &mut PkgDb) {
+    for name in requested {
+        if let Some(info) = local_db.get_pkg_info_mut(name) {
+            info.set_install_reason(InstallReason::Explicit);
+        }
+    }
+}
+
+/// Copies all the files and directories in `from` (the contents of a decompressed package,
+/// except its `nbinfo.toml`) into `to`. Every installed file is reported to the `ui`.
+///
+/// # Errors
+///
+/// If a file cannot be installed, the error is reported to the `ui` and the already installed
+/// files are removed. If all of them are removed a `NbpmError::CleanUnSuccessfulInstallation`
+/// error is returned, else a `NbpmError::DirtyUnSuccessfulInstallation` error.
+pub fn install_pkg_files(from: &str, to: &str, ui: &dyn Ui) -> Result<(), TypeErr> {
     let mut installed_files = vec![];
     let mut success = true;
     for entry in WalkDir::new(from) {
         let real_path = match &entry {
             Ok(v) => v.path(),
             Err(e) => {
-                eprintln!("Error: {}", e);
+                ui.event(Event::Error(e));
                 success = false;
                 break;
             }
@@ -150,7 +465,7 @@ pub fn install_pkg_files(from: &str, to: &str) -> Result<(), TypeErr> {
         let virt_path = match real_path.strip_prefix(from) {
             Ok(p) => p,
             Err(e) => {
-                eprintln!("Error: {}", e);
+                ui.event(Event::Error(&e));
                 success = false;
                 break;
             }
@@ -164,16 +479,17 @@ pub fn install_pkg_files(from: &str, to: &str) -> Result<(), TypeErr> {
 
         if real_path.is_dir() && !new_path.exists() {
             if let Err(e) = fs::create_dir(&new_path) {
-                eprintln!("Error: {}", e);
+                ui.event(Event::Error(&e));
                 success = false;
                 break;
             }
         } else if real_path.is_file() {
             if let Err(e) = fs::copy(real_path, &new_path) {
-                eprintln!("Error: {}", e);
+                ui.event(Event::Error(&e));
                 success = false;
                 break;
             } else {
+                ui.event(Event::FileInstalled(&new_path));
                 installed_files.push(new_path);
             }
         }
@@ -198,3 +514,226 @@ pub fn install_pkg_files(from: &str, to: &str) -> Result<(), TypeErr> {
         )))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Set;
+    use crate::nbpm::test_utils::TestEnv;
+    use crate::nbpm::ui::{ScriptedUi, SilentUi};
+
+    #[test]
+    fn install_handler_installs_pkgs_and_deps() {
+        let mut env = TestEnv::new("install");
+        env.add_pkg("bar", "1.0.0", &[], &["usr/lib/bar"]);
+        env.add_pkg("foo", "1.0.0", &["bar>=1.0.0"], &["usr/bin/foo"]);
+        let index_db = env.index_db();
+        let mut local_db = PkgDb::with_set(Set::Local);
+
+        let trans = install_handler(
+            &["foo"],
+            &env.config,
+            &mut local_db,
+            &index_db,
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap();
+        assert!(trans.done);
+        assert_eq!(trans.plan.len(), 2);
+        assert_eq!(
+            fs::read_to_string(env.root_path("usr/bin/foo")).unwrap(),
+            "usr/bin/foo 1.0.0"
+        );
+        assert!(env.root_path("usr/lib/bar").is_file());
+
+        let foo = local_db.get_pkg_info("foo").unwrap();
+        assert_eq!(foo.install_reason(), Some(InstallReason::Explicit));
+        match foo.set_info() {
+            Some(SetInfo::Local(local)) => {
+                assert_eq!(local.origin(), Some("mock://repo"));
+                assert_eq!(local.location(), Some("core/foo"));
+                assert!(local.sha256().is_some());
+            }
+            _ => panic!("foo has no local set info"),
+        }
+        let bar = local_db.get_pkg_info("bar").unwrap();
+        assert_eq!(bar.install_reason(), Some(InstallReason::Dependency));
+    }
+
+    #[test]
+    fn install_handler_fails_on_missing_pkg() {
+        let mut env = TestEnv::new("install-missing");
+        env.add_pkg("foo", "1.0.0", &[], &["usr/bin/foo"]);
+        let index_db = env.index_db();
+        let mut local_db = PkgDb::with_set(Set::Local);
+
+        let res = install_handler(
+            &["baz"],
+            &env.config,
+            &mut local_db,
+            &index_db,
+            &env.fetcher,
+            &SilentUi,
+        );
+        assert!(res.is_err());
+        assert!(local_db.is_empty());
+        assert!(env.fetcher.requests().is_empty());
+    }
+
+    #[test]
+    fn install_handler_installs_accepted_plan() {
+        let mut env = TestEnv::new("install-accept");
+        env.add_pkg("foo", "1.0.0", &[], &["usr/bin/foo"]);
+        let index_db = env.index_db();
+        let mut local_db = PkgDb::with_set(Set::Local);
+        let ui = ScriptedUi::new(&[true]);
+
+        let trans = install_handler(
+            &["foo"],
+            &env.config,
+            &mut local_db,
+            &index_db,
+            &env.fetcher,
+            &ui,
+        )
+        .unwrap();
+        assert!(trans.done);
+        assert!(local_db.contains_name("foo"));
+        assert!(env.root_path("usr/bin/foo").is_file());
+        let log = ui.log();
+        assert!(log.iter().any(|e| e == "Install"));
+        assert!(log.iter().any(|e| e.starts_with("PkgInstalled")));
+    }
+
+    #[test]
+    fn install_handler_does_nothing_if_rejected() {
+        let mut env = TestEnv::new("install-reject");
+        env.add_pkg("foo", "1.0.0", &[], &["usr/bin/foo"]);
+        let index_db = env.index_db();
+        let mut local_db = PkgDb::with_set(Set::Local);
+        let ui = ScriptedUi::new(&[false]);
+
+        let trans = install_handler(
+            &["foo"],
+            &env.config,
+            &mut local_db,
+            &index_db,
+            &env.fetcher,
+            &ui,
+        )
+        .unwrap();
+        assert!(!trans.done);
+        assert_eq!(trans.plan.len(), 1);
+        assert!(local_db.is_empty());
+        assert!(!env.root_path("usr/bin/foo").exists());
+        assert!(env.fetcher.requests().is_empty());
+        assert!(!ui.log().iter().any(|e| e.starts_with("PkgInstalled")));
+    }
+
+    #[test]
+    fn install_handler_undoes_failed_install() {
+        let mut env = TestEnv::new("install-undo");
+        // packages are installed sorted by name, so `bar` is installed before `foo` fails
+        env.add_pkg("bar", "1.0.0", &[], &["usr/lib/bar"]);
+        env.add_archive("foo", "1.0.0", &["bar>=1.0.0"], b"not an archive");
+        let index_db = env.index_db();
+        let mut local_db = PkgDb::with_set(Set::Local);
+        let ui = ScriptedUi::new(&[true]);
+
+        let res = install_handler(
+            &["foo"],
+            &env.config,
+            &mut local_db,
+            &index_db,
+            &env.fetcher,
+            &ui,
+        );
+        assert!(res.is_err());
+        assert!(local_db.is_empty());
+        assert!(!env.root_path("usr/lib/bar").exists());
+        let log = ui.log();
+        assert!(log.iter().any(|e| e.starts_with("PkgInstalled")));
+        assert!(log.iter().any(|e| e.starts_with("PkgRemoved")));
+    }
+
+    /// Returns a `TestEnv` with `bar` 1.0.0 and `foo` 1.0.0 installed, and its local `PkgDb`.
+    fn installed_v1(name: &str) -> (TestEnv, PkgDb) {
+        let mut env = TestEnv::new(name);
+        env.add_pkg("bar", "1.0.0", &[], &["usr/lib/bar", "usr/lib/bar-old"]);
+        env.add_pkg("foo", "1.0.0", &[], &["usr/bin/foo"]);
+        let mut local_db = PkgDb::with_set(Set::Local);
+        install_handler(
+            &["bar", "foo"],
+            &env.config,
+            &mut local_db,
+            &env.index_db(),
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap();
+        (env, local_db)
+    }
+
+    #[test]
+    fn upgrade_removes_dropped_files() {
+        let (mut env, mut local_db) = installed_v1("upgrade");
+        env.add_pkg("bar", "2.0.0", &[], &["usr/lib/bar", "usr/lib/bar-new"]);
+
+        let trans = upgrade_handler(
+            &env.config,
+            &mut local_db,
+            &env.index_db(),
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap();
+        assert!(trans.done);
+        assert_eq!(
+            local_db.get_pkg_info("bar").unwrap().version().to_string(),
+            "2.0.0"
+        );
+        assert_eq!(
+            fs::read_to_string(env.root_path("usr/lib/bar")).unwrap(),
+            "usr/lib/bar 2.0.0"
+        );
+        assert!(env.root_path("usr/lib/bar-new").is_file());
+        assert!(!env.root_path("usr/lib/bar-old").exists());
+        assert!(env.root_path("usr/bin/foo").is_file());
+    }
+
+    #[test]
+    fn failed_upgrade_restores_updated_pkgs() {
+        let (mut env, mut local_db) = installed_v1("upgrade-undo");
+        // `bar` is updated before `foo` fails, as packages are installed sorted by name
+        env.add_pkg("bar", "2.0.0", &[], &["usr/lib/bar", "usr/lib/bar-new"]);
+        env.add_archive("foo", "2.0.0", &[], b"not an archive");
+        let ui = ScriptedUi::new(&[true]);
+
+        let res = upgrade_handler(
+            &env.config,
+            &mut local_db,
+            &env.index_db(),
+            &env.fetcher,
+            &ui,
+        );
+        assert!(res.is_err());
+        for name in &["bar", "foo"] {
+            let info = local_db.get_pkg_info(name).unwrap();
+            assert_eq!(info.version().to_string(), "1.0.0", "{}", name);
+        }
+        assert_eq!(
+            fs::read_to_string(env.root_path("usr/lib/bar")).unwrap(),
+            "usr/lib/bar 1.0.0"
+        );
+        assert!(env.root_path("usr/lib/bar-old").is_file());
+        assert!(!env.root_path("usr/lib/bar-new").exists());
+        assert_eq!(
+            fs::read_to_string(env.root_path("usr/bin/foo")).unwrap(),
+            "usr/bin/foo 1.0.0"
+        );
+        let log = ui.log();
+        assert!(log.iter().any(|e| e.starts_with("PkgRestored")));
+        assert!(!log.iter().any(|e| e.starts_with("PkgRemoved")));
+    }
+}
@@ -0,0 +1,240 @@
+//! Listing of the packages installed in the system.
+
+use regex::Regex;
+use semver::Version;
+use serde_derive::Serialize;
+
+use super::info::files_size;
+use crate::core::{InstallReason, PkgDb, SetInfo};
+
+/// An installed package, as listed by `list_installed`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ListEntry {
+    pub name: String,
+    pub version: Version,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_reason: Option<InstallReason>,
+    /// Set of the repository the package belongs to (for example, `core`), if the package is in
+    /// the index.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub set: Option<String>,
+    /// Size in bytes of the installed files of the package.
+    pub size: u64,
+    /// Version of the package in the index, if newer than the installed version.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgrade: Option<Version>,
+}
+
+/// Filters of `list_installed`. A package is listed if it matches all the given filters, the
+/// default filter lists all the packages.
+#[derive(Debug, Clone, Default)]
+pub struct ListFilter {
+    /// Only packages installed for this reason.
+    pub reason: Option<InstallReason>,
+    /// Only packages installed as dependencies that no installed package depends on.
+    pub orphans: bool,
+    /// Only packages with a newer version in the index.
+    pub upgradable: bool,
+    /// Only packages of this set of the repository.
+    pub set: Option<String>,
+    /// Only packages whose name matches this regular expression.
+    pub name: Option<Regex>,
+    /// Only packages whose installed files take at least this number of bytes.
+    pub min_size: Option<u64>,
+    /// Only packages whose installed files take at most this number of bytes.
+    pub max_size: Option<u64>,
+}
+
+impl ListFilter {
+    fn matches(&self, entry: &ListEntry, local_db: &PkgDb) -> bool {
+        if self.reason.is_some() && entry.install_reason != self.reason {
+            return false;
+        }
+        if self.orphans
+            && (entry.install_reason != Some(InstallReason::Dependency)
+                || !local_db.reverse_depends(&entry.name).is_empty())
+        {
+            return false;
+        }
+        if self.upgradable && entry.upgrade.is_none() {
+            return false;
+        }
+        if self.set.is_some() && entry.set != self.set {
+            return false;
+        }
+        if let Some(re) = &self.name {
+            if !re.is_match(&entry.name) {
+                return false;
+            }
+        }
+        self.min_size.map_or(true, |min| entry.size >= min)
+            && self.max_size.map_or(true, |max| entry.size <= max)
+    }
+}
+
+/// Returns the packages of the local `PkgDb` that match the `filter`, sorted by name. The set
+/// and the upgrades of the packages are taken from the `index_db`.
+pub fn list_installed(local_db: &PkgDb, index_db: &PkgDb, filter: &ListFilter) -> Vec<ListEntry> {
+    let mut list: Vec<ListEntry> = local_db
+        .iter()
+        .map(|(name, info)| {
+            let index_info = index_db.get_pkg_info(name);
+            ListEntry {
+                name: name.to_string(),
+                version: info.version().clone(),
+                description: info.description().to_string(),
+                install_reason: info.install_reason(),
+                set: match index_info.map(|i| i.set_info()) {
+                    Some(Some(SetInfo::Universe(u))) => {
+                        u.location().split('/').next().map(|s| s.to_string())
+                    }
+                    _ => None,
+                },
+                size: match info.set_info() {
+                    Some(SetInfo::Local(l)) => files_size(l.paths()).0,
+                    _ => 0,
+                },
+                upgrade: index_info
+                    .filter(|i| i.version() > info.version())
+                    .map(|i| i.version().clone()),
+            }
+        })
+        .filter(|entry| filter.matches(entry, local_db))
+        .collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nbpm::progress::parse_size;
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Removes the directory of the test files when dropped.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Returns a local `PkgDb` and an index where:
+    ///
+    /// - `app` (1 MiB) is installed explicitly and depends on `libfoo`.
+    /// - `libfoo` (10 KiB) is a dependency of `app`, and has an upgrade in the `extra` set.
+    /// - `libold` (600 KiB) is an orphan dependency.
+    fn test_dbs(name: &str) -> (TempDir, PkgDb, PkgDb) {
+        let dir = env::temp_dir().join(format!("nbkit-test-list-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut local = String::from("set = 'local'\n");
+        let pkgs = [
+            ("app", "explicit", "['libfoo']", 1024 * 1024),
+            ("libfoo", "dependency", "[]", 10 * 1024),
+            ("libold", "dependency", "[]", 600 * 1024),
+        ];
+        for (pkg, reason, depends, size) in &pkgs {
+            let file = dir.join(pkg);
+            fs::write(&file, vec![0; *size]).unwrap();
+            local.push_str(&format!(
+                "[{0}]\nversion = '1.0.0'\ndescription = '{0}'\ndepends = {1}\n\
+                 install-reason = '{2}'\n[{0}.local]\npaths = ['{3}']\n",
+                pkg,
+                depends,
+                reason,
+                file.display()
+            ));
+        }
+        let index = "set = 'universe'\n\
+             [app]\nversion = '1.0.0'\ndescription = 'app'\n\
+             [app.universe]\nlocation = 'core/app'\n\
+             [libfoo]\nversion = '1.1.0'\ndescription = 'libfoo'\n\
+             [libfoo.universe]\nlocation = 'extra/libfoo'\n";
+        (
+            TempDir(dir),
+            toml::from_str(&local).unwrap(),
+            toml::from_str(index).unwrap(),
+        )
+    }
+
+    fn list_names(local_db: &PkgDb, index_db: &PkgDb, filter: &ListFilter) -> Vec<String> {
+        list_installed(local_db, index_db, filter)
+            .into_iter()
+            .map(|e| e.name)
+            .collect()
+    }
+
+    #[test]
+    fn lists_all_pkgs_by_default() {
+        let (_dir, local_db, index_db) = test_dbs("all");
+        let list = list_installed(&local_db, &index_db, &ListFilter::default());
+        let names: Vec<&str> = list.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["app", "libfoo", "libold"]);
+        assert_eq!(list[0].size, 1024 * 1024);
+        assert_eq!(list[0].set.as_deref(), Some("core"));
+        assert_eq!(list[1].upgrade, Some(Version::parse("1.1.0").unwrap()));
+        assert_eq!(list[2].set, None);
+    }
+
+    #[test]
+    fn filters_orphans() {
+        let (_dir, local_db, index_db) = test_dbs("orphans");
+        let filter = ListFilter {
+            orphans: true,
+            ..ListFilter::default()
+        };
+        assert_eq!(list_names(&local_db, &index_db, &filter), vec!["libold"]);
+    }
+
+    #[test]
+    fn filters_by_size() {
+        let (_dir, local_db, index_db) = test_dbs("size");
+        let filter = ListFilter {
+            min_size: Some(parse_size("512K").unwrap()),
+            ..ListFilter::default()
+        };
+        assert_eq!(
+            list_names(&local_db, &index_db, &filter),
+            vec!["app", "libold"]
+        );
+
+        let filter = ListFilter {
+            min_size: Some(parse_size("512K").unwrap()),
+            max_size: Some(parse_size("1M").unwrap() - 1),
+            ..ListFilter::default()
+        };
+        assert_eq!(list_names(&local_db, &index_db, &filter), vec!["libold"]);
+
+        // the limits are inclusive
+        let filter = ListFilter {
+            min_size: Some(parse_size("10K").unwrap()),
+            max_size: Some(parse_size("10K").unwrap()),
+            ..ListFilter::default()
+        };
+        assert_eq!(list_names(&local_db, &index_db, &filter), vec!["libfoo"]);
+    }
+
+    #[test]
+    fn combines_filters() {
+        let (_dir, local_db, index_db) = test_dbs("combined");
+        let filter = ListFilter {
+            reason: Some(InstallReason::Dependency),
+            upgradable: true,
+            ..ListFilter::default()
+        };
+        assert_eq!(list_names(&local_db, &index_db, &filter), vec!["libfoo"]);
+
+        let filter = ListFilter {
+            set: Some("core".to_string()),
+            name: Some(Regex::new("^lib").unwrap()),
+            ..ListFilter::default()
+        };
+        assert!(list_names(&local_db, &index_db, &filter).is_empty());
+    }
+}
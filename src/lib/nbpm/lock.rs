@@ -0,0 +1,469 @@
+//! Lockfiles: the exact set of packages installed in a system, to install the very same
+//! packages in other systems.
+//!
+//! Lockfiles are toml files with a `[[package]]` table for every installed package:
+//!
+//! ```toml
+//! [[package]]
+//! name = "hello"
+//! version = "0.2.1"
+//! repo = "https://www.nebula.com/repo/x86_64"
+//! location = "core/hello"
+//! sha256 = "4f1c..."
+//! install-reason = "explicit"
+//! ```
+
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::install::install_graph;
+use super::plan::sort_plan;
+use super::remove::{plan_remove, remove_planned};
+use super::ui::{Event, Prompt, Ui};
+use super::{Config, NbpmError, PlanItem, Transaction};
+use crate::core::{pkgdb::PkgInfo, InstallReason, PkgDb, SetInfo};
+use crate::fetch::Fetcher;
+use crate::TypeErr;
+
+/// An installed package, as recorded in a `Lockfile`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LockEntry {
+    pub name: String,
+    pub version: Version,
+    /// Url of the repository mirror the package was downloaded from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Location of the package in the repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// SHA256 hash of the archive of the package. Meta-packages have no archive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_reason: Option<InstallReason>,
+}
+
+/// The exact set of packages installed in a system.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Lockfile {
+    #[serde(rename = "package")]
+    pub packages: Vec<LockEntry>,
+}
+
+impl Lockfile {
+    /// Creates the `Lockfile` of the packages of the local `PkgDb`, sorted by name.
+    pub fn from_pkgdb(local_db: &PkgDb) -> Lockfile {
+        let mut packages: Vec<LockEntry> = local_db
+            .iter()
+            .map(|(name, info)| {
+                let local = match info.set_info() {
+                    Some(SetInfo::Local(l)) => Some(l),
+                    _ => None,
+                };
+                LockEntry {
+                    name: name.to_string(),
+                    version: info.version().clone(),
+                    repo: local.and_then(|l| l.origin()).map(|s| s.to_string()),
+                    location: local.and_then(|l| l.location()).map(|s| s.to_string()),
+                    sha256: local.and_then(|l| l.sha256()).map(|s| s.to_string()),
+                    install_reason: info.install_reason(),
+                }
+            })
+            .collect();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Lockfile { packages }
+    }
+
+    /// Loads a `Lockfile` from a toml file.
+    pub fn load(path: &Path) -> Result<Lockfile, TypeErr> {
+        let lock_str = fs::read_to_string(path)?;
+        Ok(toml::from_str(&lock_str)?)
+    }
+
+    /// Saves the `Lockfile` as a toml file.
+    pub fn save(&self, path: &Path) -> Result<(), TypeErr> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Returns the names of the installed packages (not meta-packages) whose archive hash is not
+    /// known, as they were installed before nbpm recorded the hashes. The lockfile cannot be
+    /// installed unless `Config::allow_unhashed` is set, see `plan_locked`.
+    pub fn unhashed(&self, local_db: &PkgDb) -> Vec<&str> {
+        self.packages
+            .iter()
+            .filter(|e| e.sha256.is_none())
+            .filter(|e| local_db.get_pkg_info(&e.name).is_some_and(|i| !i.is_meta()))
+            .map(|e| e.name.as_str())
+            .collect()
+    }
+}
+
+/// Changes needed to install a `Lockfile`, see `plan_locked`.
+pub(crate) struct LockedPlan<'a> {
+    /// Packages to install or update.
+    pub graph: HashMap<String, &'a PkgInfo>,
+    pub install: Vec<PlanItem>,
+    /// Packages to remove, as computed by `plan_remove`.
+    pub remove: Vec<PlanItem>,
+}
+
+/// Computes the changes needed to install exactly the packages of the `lock`. Dependencies are
+/// not resolved: every package must be in `index_db` with the version and location of the lock,
+/// and the packages of the lock must satisfy the dependencies of each other. The repository of
+/// every package must be one of the mirrors of the `config`. If `prune` is `true`, the installed
+/// packages that are not in the lock are removed too.
+///
+/// Every package (except meta-packages) must have a hash in the lock, and packages installed
+/// with the same version must have been installed with the same hash. Packages whose hash is
+/// unknown, in the lock or in the local `PkgDb`, are only accepted (without being checked) if
+/// `Config::allow_unhashed` is set.
+///
+/// # Errors
+///
+/// If a package cannot be installed as locked, a `NbpmError::LockMismatch` error is returned.
+/// Installed packages with a newer version cause a `NbpmError::RequiresPkgDowngrade` error, and
+/// broken dependencies a `NbError::MissingDependency` or `NbError::BrokenDependency` error. If
+/// `prune` is `true` and the lock has no packages, a `NbpmError::PruneEverything` error is
+/// returned.
+pub(crate) fn plan_locked<'a>(
+    lock: &Lockfile,
+    prune: bool,
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &'a PkgDb,
+) -> Result<LockedPlan<'a>, TypeErr> {
+    if prune && lock.packages.is_empty() {
+        return Err(Box::new(NbpmError::PruneEverything("lockfile".to_string())));
+    }
+    let mirrors = config.mirrors();
+    let mut graph = HashMap::new();
+    for entry in &lock.packages {
+        let mismatch = |detail: String| NbpmError::LockMismatch(entry.name.to_string(), detail);
+        let info = match index_db.get_pkg_info(&entry.name) {
+            Some(i) => i,
+            None => return Err(Box::new(mismatch("not in the repository".to_string()))),
+        };
+        if *info.version() != entry.version {
+            return Err(Box::new(mismatch(format!(
+                "version {} locked, the repository has {}",
+                entry.version,
+                info.version()
+            ))));
+        }
+        if let Some(repo) = &entry.repo {
+            if !mirrors.contains(repo) {
+                return Err(Box::new(mismatch(format!(
+                    "locked from {}, which is not a mirror of the repository",
+                    repo
+                ))));
+            }
+        }
+        if let (Some(SetInfo::Universe(u)), Some(location)) = (info.set_info(), &entry.location) {
+            if u.location() != location {
+                return Err(Box::new(mismatch(format!(
+                    "location {} locked, the repository has {}",
+                    location,
+                    u.location()
+                ))));
+            }
+        }
+
+        // meta-packages have no archive to check
+        if !info.is_meta() {
+            if entry.sha256.is_none() && !config.allow_unhashed() {
+                return Err(Box::new(mismatch("no hash locked".to_string())));
+            }
+            // the same version might have been installed from a different archive
+            if let Some(installed) = local_db
+                .get_pkg_info(&entry.name)
+                .filter(|i| *i.version() == entry.version)
+            {
+                let installed_hash = match installed.set_info() {
+                    Some(SetInfo::Local(l)) => l.sha256(),
+                    _ => None,
+                };
+                match (installed_hash, &entry.sha256) {
+                    (Some(installed), Some(locked)) if installed != locked => {
+                        return Err(Box::new(mismatch(format!(
+                            "installed with hash {}, {} locked",
+                            installed, locked
+                        ))))
+                    }
+                    (None, _) if !config.allow_unhashed() => {
+                        return Err(Box::new(mismatch(
+                            "the hash of the installed package is unknown, reinstall it"
+                                .to_string(),
+                        )))
+                    }
+                    _ => (),
+                }
+            }
+        }
+        graph.insert(entry.name.to_string(), info);
+    }
+    PkgDb::check_subgraph_integrity(&graph)?;
+    super::utils::check_arch(&graph, config)?;
+    let install = super::utils::purge_already_installed(&mut graph, local_db)?;
+
+    let mut remove = vec![];
+    if prune {
+        let unlocked: Vec<&str> = local_db
+            .iter()
+            .filter(|(name, _)| !lock.packages.iter().any(|e| e.name == **name))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !unlocked.is_empty() {
+            remove = plan_remove(&unlocked, false, true, local_db)?;
+        }
+    }
+    Ok(LockedPlan {
+        graph,
+        install,
+        remove,
+    })
+}
+
+/// Returns the changes needed to install the `lock`, sorted by name. See `plan_locked`.
+pub fn plan_lock_install(
+    lock: &Lockfile,
+    prune: bool,
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+) -> Result<Vec<PlanItem>, TypeErr> {
+    let locked = plan_locked(lock, prune, config, local_db, index_db)?;
+    let mut plan = locked.install;
+    plan.extend(locked.remove);
+    sort_plan(&mut plan);
+    Ok(plan)
+}
+
+/// Installs exactly the packages of the `lock`, as planned by `plan_lock_install`, and then
+/// removes the packages not in the lock (if `prune` is `true`). The archive of every package
+/// must match the hash of the lock (see `plan_locked` for packages with no hash). The install
+/// reasons of the lock are kept. The plan is reported to the `ui`, that is asked for
+/// confirmation before making any change.
+///
+/// # Errors
+///
+/// See `plan_locked` and `install_handler`. If the hash of an archive does not match the lock, a
+/// `NbError::HashMismatch` error is returned and the installation is undone.
+pub fn lock_install_handler(
+    lock: &Lockfile,
+    prune: bool,
+    config: &Config,
+    local_db: &mut PkgDb,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<Transaction, TypeErr> {
+    let locked = plan_locked(lock, prune, config, local_db, index_db)?;
+    let mut plan = locked.install.clone();
+    plan.extend(locked.remove.iter().cloned());
+    sort_plan(&mut plan);
+    ui.event(Event::Plan(&plan));
+
+    if !plan.is_empty() && !ui.confirm(Prompt::Install) {
+        return Ok(Transaction { plan, done: false });
+    }
+    let explicit: Vec<&str> = lock
+        .packages
+        .iter()
+        .filter(|e| e.install_reason == Some(InstallReason::Explicit))
+        .map(|e| e.name.as_str())
+        .collect();
+    let hashes: HashMap<String, String> = lock
+        .packages
+        .iter()
+        .filter_map(|e| {
+            e.sha256
+                .as_ref()
+                .map(|h| (e.name.to_string(), h.to_string()))
+        })
+        .collect();
+    install_graph(
+        &locked.graph,
+        &locked.install,
+        &explicit,
+        &hashes,
+        config,
+        local_db,
+        fetcher,
+        ui,
+    )?;
+    remove_planned(&locked.remove, local_db, ui)?;
+    Ok(Transaction { plan, done: true })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{NbError, Set};
+    use crate::nbpm::install::install_handler;
+    use crate::nbpm::plan::Action;
+    use crate::nbpm::test_utils::TestEnv;
+    use crate::nbpm::ui::SilentUi;
+
+    /// Returns a `TestEnv` serving `foo` (depending on `bar`) and `bar`, the local `PkgDb` with
+    /// both installed and its lock.
+    fn locked_env(name: &str) -> (TestEnv, PkgDb, Lockfile) {
+        let mut env = TestEnv::new(name);
+        env.add_pkg("bar", "1.0.0", &[], &["usr/lib/bar"]);
+        env.add_pkg("foo", "1.0.0", &["bar"], &["usr/bin/foo"]);
+        let mut local_db = PkgDb::with_set(Set::Local);
+        install_handler(
+            &["foo"],
+            &env.config,
+            &mut local_db,
+            &env.index_db(),
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap();
+        let lock = Lockfile::from_pkgdb(&local_db);
+        (env, local_db, lock)
+    }
+
+    fn entry<'a>(lock: &'a mut Lockfile, name: &str) -> &'a mut LockEntry {
+        lock.packages.iter_mut().find(|e| e.name == name).unwrap()
+    }
+
+    /// Asserts that planning the `lock` fails with a `NbpmError::LockMismatch` of `pkg`.
+    fn assert_mismatch(res: Result<LockedPlan, TypeErr>, pkg: &str) {
+        match res.err().unwrap().downcast_ref::<NbpmError>() {
+            Some(NbpmError::LockMismatch(name, _)) => assert_eq!(name, pkg),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn plan_locked_installs_lock() {
+        let (env, _, lock) = locked_env("lock-plan");
+        let index_db = env.index_db();
+        let locked = plan_locked(&lock, false, &env.config, &PkgDb::new(), &index_db).unwrap();
+        let mut names: Vec<&str> = locked.install.iter().map(|p| p.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["bar", "foo"]);
+        assert!(locked.install.iter().all(|p| p.action == Action::Install));
+        assert!(locked.remove.is_empty());
+    }
+
+    #[test]
+    fn plan_locked_rejects_hash_mismatch() {
+        let (env, local_db, mut lock) = locked_env("lock-hash-mismatch");
+        entry(&mut lock, "foo").sha256 = Some("0000".to_string());
+        let index_db = env.index_db();
+        // the installed package has another hash
+        assert_mismatch(
+            plan_locked(&lock, false, &env.config, &local_db, &index_db),
+            "foo",
+        );
+
+        // the downloaded archive has another hash
+        let mut local_db = PkgDb::with_set(Set::Local);
+        let err = lock_install_handler(
+            &lock,
+            false,
+            &env.config,
+            &mut local_db,
+            &index_db,
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NbError>(),
+            Some(NbError::HashMismatch(..))
+        ));
+        assert!(local_db.is_empty());
+    }
+
+    #[test]
+    fn plan_locked_unknown_hash_needs_allow_unhashed() {
+        let (mut env, _, mut lock) = locked_env("lock-unhashed");
+        entry(&mut lock, "foo").sha256 = None;
+        let index_db = env.index_db();
+        assert_mismatch(
+            plan_locked(&lock, false, &env.config, &PkgDb::new(), &index_db),
+            "foo",
+        );
+
+        env.config.set_allow_unhashed(true);
+        assert!(plan_locked(&lock, false, &env.config, &PkgDb::new(), &index_db).is_ok());
+    }
+
+    #[test]
+    fn plan_locked_installed_unknown_hash_needs_allow_unhashed() {
+        let (mut env, local_db, lock) = locked_env("lock-installed-unhashed");
+        // foo installed before nbpm recorded the hashes
+        let mut local_str = toml::to_string(&local_db).unwrap();
+        let foo_hash = lock.packages.iter().find(|e| e.name == "foo").unwrap();
+        let foo_hash = foo_hash.sha256.as_ref().unwrap();
+        local_str = local_str.replacen(&format!("sha256 = \"{}\"", foo_hash), "", 1);
+        let local_db: PkgDb = toml::from_str(&local_str).unwrap();
+        let index_db = env.index_db();
+        assert_mismatch(
+            plan_locked(&lock, false, &env.config, &local_db, &index_db),
+            "foo",
+        );
+
+        env.config.set_allow_unhashed(true);
+        let locked = plan_locked(&lock, false, &env.config, &local_db, &index_db).unwrap();
+        assert!(locked.install.is_empty());
+    }
+
+    #[test]
+    fn plan_locked_rejects_foreign_repo() {
+        let (env, _, mut lock) = locked_env("lock-foreign");
+        entry(&mut lock, "bar").repo = Some("mock://other".to_string());
+        assert_mismatch(
+            plan_locked(&lock, false, &env.config, &PkgDb::new(), &env.index_db()),
+            "bar",
+        );
+    }
+
+    #[test]
+    fn plan_locked_rejects_other_versions_and_locations() {
+        let (env, _, mut lock) = locked_env("lock-version");
+        let index_db = env.index_db();
+        entry(&mut lock, "bar").version = Version::parse("0.9.0").unwrap();
+        assert_mismatch(
+            plan_locked(&lock, false, &env.config, &PkgDb::new(), &index_db),
+            "bar",
+        );
+
+        let (env, _, mut lock) = locked_env("lock-location");
+        entry(&mut lock, "bar").location = Some("extra/bar".to_string());
+        assert_mismatch(
+            plan_locked(&lock, false, &env.config, &PkgDb::new(), &env.index_db()),
+            "bar",
+        );
+    }
+
+    #[test]
+    fn malformed_lockfile_is_rejected() {
+        let parse = |s: &str| toml::from_str::<Lockfile>(s);
+        assert!(parse("[[package]]\nname = 'foo'\nversion = '1.0.0'\n").is_ok());
+        // unknown fields
+        assert!(parse("[[package]]\nname = 'foo'\nversion = '1.0.0'\nhash = 'abc'\n").is_err());
+        assert!(parse("[[packages]]\nname = 'foo'\nversion = '1.0.0'\n").is_err());
+        // missing and invalid fields
+        assert!(parse("[[package]]\nname = 'foo'\n").is_err());
+        assert!(parse("[[package]]\nname = 'foo'\nversion = 'one'\n").is_err());
+        assert!(
+            parse("[[package]]\nname = 'foo'\nversion = '1.0.0'\ninstall-reason = 'x'\n").is_err()
+        );
+
+        let env = TestEnv::new("lock-malformed");
+        let path = env.dir.join("nbpm.lock");
+        fs::write(&path, "not a lockfile").unwrap();
+        assert!(Lockfile::load(&path).is_err());
+    }
+}
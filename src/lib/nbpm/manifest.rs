@@ -0,0 +1,384 @@
+//! Declarative state of the system: a manifest lists the packages that must be installed, and
+//! nbpm computes and applies the changes needed to converge to it.
+//!
+//! Manifests are toml files with a `packages` table, containing the name and the version
+//! requirement of every package:
+//!
+//! ```toml
+//! [packages]
+//! hello = "*"
+//! shell = ">=1.0"
+//! ```
+
+use semver::VersionReq;
+use serde_derive::Deserialize;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use super::install::{install_graph, resolve_install};
+use super::plan::sort_plan;
+use super::remove::{plan_remove, remove_planned};
+use super::ui::{Event, Prompt, Ui};
+use super::{Config, NbpmError, PlanItem, Transaction};
+use crate::core::{pkgdb::PkgInfo, NbError, PkgDb};
+use crate::fetch::Fetcher;
+use crate::TypeErr;
+
+/// The packages that must be installed in the system.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Name and version requirement of every package.
+    pub packages: BTreeMap<String, VersionReq>,
+}
+
+impl Manifest {
+    /// Loads a `Manifest` from a toml file.
+    pub fn load(path: &Path) -> Result<Manifest, TypeErr> {
+        let manifest_str = fs::read_to_string(path)?;
+        Ok(toml::from_str(&manifest_str)?)
+    }
+
+    /// Returns the names of the packages of the manifest, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        self.packages.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Changes needed to apply a `Manifest`, see `plan_apply`.
+pub(crate) struct ApplyPlan<'a> {
+    /// Packages to install or update, as computed by `resolve_install`.
+    pub graph: HashMap<String, &'a PkgInfo>,
+    pub install: Vec<PlanItem>,
+    /// Packages to remove, as computed by `plan_remove`.
+    pub remove: Vec<PlanItem>,
+}
+
+/// Computes the changes needed to apply the `manifest`: the packages of the manifest that are
+/// missing, or whose installed version does not match the requirement of the manifest, are
+/// installed (or updated) from `index_db`, with their dependencies. If `prune` is `true`, the
+/// installed packages that are not in the manifest and are not needed by any package of the
+/// manifest are removed too.
+///
+/// Installed packages matching the requirement are left as they are, even if `index_db` has a
+/// newer version that also matches: applying a manifest converges to it, but never upgrades
+/// (see `upgrade_handler`).
+///
+/// # Errors
+///
+/// The function returns an error in the following cases:
+///
+/// - A package of the manifest has to be installed, but it is not in the index, or the version in
+///   the index does not match the requirement of the manifest (`NbpmError::ManifestVersion`).
+/// - `prune` is `true` and the manifest has no packages (`NbpmError::PruneEverything`).
+/// - The packages cannot be installed, see `plan_install`.
+/// - Removing the packages breaks a package that stays installed, see `plan_remove`.
+pub(crate) fn plan_apply<'a>(
+    manifest: &Manifest,
+    prune: bool,
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &'a PkgDb,
+) -> Result<ApplyPlan<'a>, TypeErr> {
+    if prune && manifest.packages.is_empty() {
+        return Err(Box::new(NbpmError::PruneEverything("manifest".to_string())));
+    }
+
+    // packages installed with a version matching the manifest are kept
+    let mut kept = vec![];
+    let mut to_install = vec![];
+    for (name, req) in &manifest.packages {
+        if let Some(installed) = local_db.get_pkg_info(name) {
+            if req.matches(installed.version()) {
+                kept.push(name.as_str());
+                continue;
+            }
+        }
+        let info = match index_db.get_pkg_info(name) {
+            Some(i) => i,
+            None => return Err(Box::new(NbError::PkgNotFound(name.to_string()))),
+        };
+        if !req.matches(info.version()) {
+            return Err(Box::new(NbpmError::ManifestVersion(
+                name.to_string(),
+                req.clone(),
+                info.version().clone(),
+            )));
+        }
+        to_install.push(name.as_str());
+    }
+
+    let (graph, install) = resolve_install(&to_install, config, local_db, index_db)?;
+    let mut remove = vec![];
+    if prune {
+        // the packages of the manifest and all their dependencies, as installed for the kept
+        // packages and as in the index for the rest
+        let mut needed: HashSet<String> = local_db
+            .get_subgraph(Some(&kept), true)?
+            .into_keys()
+            .collect();
+        needed.extend(index_db.get_subgraph(Some(&to_install), true)?.into_keys());
+        let unneeded: Vec<&str> = local_db
+            .iter()
+            .filter(|(name, _)| !needed.contains(*name))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !unneeded.is_empty() {
+            remove = plan_remove(&unneeded, false, true, local_db)?;
+        }
+    }
+    Ok(ApplyPlan {
+        graph,
+        install,
+        remove,
+    })
+}
+
+/// Returns the changes needed to apply the `manifest`, sorted by name. See `plan_apply`.
+pub fn plan_manifest(
+    manifest: &Manifest,
+    prune: bool,
+    config: &Config,
+    local_db: &PkgDb,
+    index_db: &PkgDb,
+) -> Result<Vec<PlanItem>, TypeErr> {
+    let apply = plan_apply(manifest, prune, config, local_db, index_db)?;
+    let mut plan = apply.install;
+    plan.extend(apply.remove);
+    sort_plan(&mut plan);
+    Ok(plan)
+}
+
+/// Applies the `manifest` as planned by `plan_manifest`: packages are installed first, and then
+/// the unneeded packages are removed (if `prune` is `true`). The packages of the manifest are
+/// marked as explicitly installed. The plan is reported to the `ui`, that is asked for
+/// confirmation before making any change.
+///
+/// # Errors
+///
+/// See `plan_apply`, `install_handler` and `remove_handler`. If the installation fails, no
+/// package is removed.
+pub fn apply_handler(
+    manifest: &Manifest,
+    prune: bool,
+    config: &Config,
+    local_db: &mut PkgDb,
+    index_db: &PkgDb,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<Transaction, TypeErr> {
+    let apply = plan_apply(manifest, prune, config, local_db, index_db)?;
+    let mut plan = apply.install.clone();
+    plan.extend(apply.remove.iter().cloned());
+    sort_plan(&mut plan);
+    ui.event(Event::Plan(&plan));
+
+    if !plan.is_empty() && !ui.confirm(Prompt::Apply) {
+        return Ok(Transaction { plan, done: false });
+    }
+    let names = manifest.names();
+    install_graph(
+        &apply.graph,
+        &apply.install,
+        &names,
+        &HashMap::new(),
+        config,
+        local_db,
+        fetcher,
+        ui,
+    )?;
+    remove_planned(&apply.remove, local_db, ui)?;
+    Ok(Transaction { plan, done: true })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{InstallReason, Set};
+    use crate::nbpm::install::install_handler;
+    use crate::nbpm::plan::Action;
+    use crate::nbpm::test_utils::TestEnv;
+    use crate::nbpm::ui::SilentUi;
+
+    fn manifest(toml_str: &str) -> Manifest {
+        toml::from_str(toml_str).unwrap()
+    }
+
+    /// Returns the name, action and target version of every item of the plan.
+    fn summary(plan: &[PlanItem]) -> Vec<(String, Action, Option<String>)> {
+        plan.iter()
+            .map(|p| {
+                (
+                    p.name.clone(),
+                    p.action,
+                    p.to.as_ref().map(|v| v.to_string()),
+                )
+            })
+            .collect()
+    }
+
+    fn item(name: &str, action: Action, to: Option<&str>) -> (String, Action, Option<String>) {
+        (name.to_string(), action, to.map(|v| v.to_string()))
+    }
+
+    /// Returns a `TestEnv` serving `foo` (depending on `bar`), `bar` and `baz`, all 1.0.0, and
+    /// the local `PkgDb` with `names` installed.
+    fn installed(name: &str, names: &[&str]) -> (TestEnv, PkgDb) {
+        let mut env = TestEnv::new(name);
+        env.add_pkg("bar", "1.0.0", &[], &["usr/lib/bar"]);
+        env.add_pkg("foo", "1.0.0", &["bar"], &["usr/bin/foo"]);
+        env.add_pkg("baz", "1.0.0", &[], &["usr/bin/baz"]);
+        let mut local_db = PkgDb::with_set(Set::Local);
+        if !names.is_empty() {
+            let index_db = env.index_db();
+            install_handler(
+                names,
+                &env.config,
+                &mut local_db,
+                &index_db,
+                &env.fetcher,
+                &SilentUi,
+            )
+            .unwrap();
+        }
+        (env, local_db)
+    }
+
+    fn apply(env: &TestEnv, manifest: &Manifest, prune: bool, local_db: &mut PkgDb) -> Transaction {
+        let index_db = env.index_db();
+        apply_handler(
+            manifest,
+            prune,
+            &env.config,
+            local_db,
+            &index_db,
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn apply_installs_missing_pkgs() {
+        let (env, mut local_db) = installed("apply-install", &[]);
+        let trans = apply(
+            &env,
+            &manifest("[packages]\nfoo = '*'\n"),
+            false,
+            &mut local_db,
+        );
+
+        assert!(trans.done);
+        assert_eq!(
+            summary(&trans.plan),
+            vec![
+                item("bar", Action::Install, Some("1.0.0")),
+                item("foo", Action::Install, Some("1.0.0"))
+            ]
+        );
+        assert!(env.root_path("usr/bin/foo").is_file());
+        let reason = |name| local_db.get_pkg_info(name).unwrap().install_reason();
+        assert_eq!(reason("foo"), Some(InstallReason::Explicit));
+        assert_eq!(reason("bar"), Some(InstallReason::Dependency));
+    }
+
+    #[test]
+    fn apply_upgrades_pkgs_not_matching() {
+        let (mut env, mut local_db) = installed("apply-upgrade", &["foo"]);
+        env.add_pkg("foo", "2.0.0", &["bar"], &["usr/bin/foo"]);
+        let trans = apply(
+            &env,
+            &manifest("[packages]\nfoo = '>=2.0'\n"),
+            false,
+            &mut local_db,
+        );
+
+        assert_eq!(
+            summary(&trans.plan),
+            vec![item("foo", Action::Update, Some("2.0.0"))]
+        );
+        assert_eq!(
+            fs::read_to_string(env.root_path("usr/bin/foo")).unwrap(),
+            "usr/bin/foo 2.0.0"
+        );
+    }
+
+    #[test]
+    fn apply_keeps_matching_version() {
+        let (mut env, mut local_db) = installed("apply-keep", &["foo"]);
+        // a newer version matching the manifest is not installed
+        env.add_pkg("foo", "1.1.0", &["bar"], &["usr/bin/foo"]);
+        let manifest = manifest("[packages]\nfoo = '^1.0'\n");
+        let plan =
+            plan_manifest(&manifest, false, &env.config, &local_db, &env.index_db()).unwrap();
+        assert!(plan.is_empty());
+
+        let trans = apply(&env, &manifest, false, &mut local_db);
+        assert!(trans.plan.is_empty());
+        assert_eq!(
+            local_db.get_pkg_info("foo").unwrap().version().to_string(),
+            "1.0.0"
+        );
+    }
+
+    #[test]
+    fn apply_fails_if_index_does_not_match() {
+        let (env, local_db) = installed("apply-mismatch", &[]);
+        let manifest = manifest("[packages]\nfoo = '>=2.0'\n");
+        let err = plan_manifest(&manifest, false, &env.config, &local_db, &env.index_db())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<NbpmError>(),
+            Some(NbpmError::ManifestVersion(..))
+        ));
+    }
+
+    #[test]
+    fn apply_prunes_unneeded_pkgs() {
+        let (env, mut local_db) = installed("apply-prune", &["foo", "baz"]);
+        let manifest = manifest("[packages]\nfoo = '*'\n");
+
+        // without --prune nothing changes
+        let plan =
+            plan_manifest(&manifest, false, &env.config, &local_db, &env.index_db()).unwrap();
+        assert!(plan.is_empty());
+
+        let trans = apply(&env, &manifest, true, &mut local_db);
+        assert_eq!(
+            summary(&trans.plan),
+            vec![item("baz", Action::Remove, None)]
+        );
+        assert!(!local_db.contains_name("baz"));
+        assert!(!env.root_path("usr/bin/baz").exists());
+        // dependencies of the packages of the manifest are kept
+        assert!(local_db.contains_name("bar"));
+    }
+
+    #[test]
+    fn manifest_rejects_unknown_keys() {
+        assert!(toml::from_str::<Manifest>("[packages]\nfoo = '*'\n[extra]\nbar = '*'\n").is_err());
+        assert!(toml::from_str::<Manifest>("pakages = {}\n").is_err());
+        assert!(toml::from_str::<Manifest>("[packages]\nfoo = 'not a version'\n").is_err());
+    }
+
+    #[test]
+    fn empty_manifest() {
+        let (env, local_db) = installed("apply-empty", &["foo"]);
+        let manifest = manifest("[packages]\n");
+        assert!(manifest.names().is_empty());
+
+        let plan =
+            plan_manifest(&manifest, false, &env.config, &local_db, &env.index_db()).unwrap();
+        assert!(plan.is_empty());
+        let err = plan_manifest(&manifest, true, &env.config, &local_db, &env.index_db())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<NbpmError>(),
+            Some(NbpmError::PruneEverything(_))
+        ));
+    }
+}
@@ -1,15 +1,33 @@
 use std::error::Error;
 use std::process::exit;
 
+pub mod cache;
 pub mod cli;
 pub mod config;
+pub mod dryrun;
 pub mod errors;
+pub mod files;
+pub mod history;
+pub mod info;
 pub mod install;
+pub mod list;
+pub mod lock;
+pub mod manifest;
+pub mod plan;
+pub mod progress;
 pub mod remove;
+pub mod terminal;
+#[cfg(test)]
+pub(crate) mod test_utils;
+pub mod ui;
 pub mod utils;
 
 pub use config::Config;
 pub use errors::NbpmError;
+pub use plan::{Action, PlanItem, Transaction};
+pub use progress::ProgressBar;
+pub use terminal::TerminalUi;
+pub use ui::{Event, Prompt, Ui};
 
 // constant and default variables of nbpm
 
@@ -20,8 +38,15 @@ pub const DEF_NBPM_ROOT: &str = "/";
 /// are stored.
 pub const DEF_NBPM_PATH: &str = "/etc/nbpm";
 
-/// The default URL to a nebula repository.
-pub const DEF_NBPM_REPO: &str = "www.nebula.com/repo/x86_64";
+/// The default URL to a nebula repository. Besides HTTP(S), `file://` URLs and plain paths to a
+/// local directory are also accepted as repository URLs.
+pub const DEF_NBPM_REPO: &str = "https://www.nebula.com/repo/{arch}";
+
+/// Placeholder in repository URLs that is replaced by the architecture of the system.
+pub const ARCH_PLACEHOLDER: &str = "{arch}";
+
+/// Default maximum number of packages to download at the same time.
+pub const DEF_NBPM_PARALLEL_DOWNLOADS: usize = 4;
 
 // NOTE: All paths below are relative paths to nbpm's root directory (default : `DEF_NBPM_PATH`)
 
@@ -32,19 +57,33 @@ pub const NBPM_CONFIG_FILE: &str = "config.toml";
 /// packages is stored.
 pub const LOCAL_DB_PATH: &str = "local_db.toml";
 
+/// File where the history of the changes made by nbpm is stored.
+pub const HISTORY_PATH: &str = "history.toml";
+
 /// Path where the repository index is stored.
 pub const LOCAL_INDEX_PATH: &str = "index/index.toml";
 
-/// Path to the working directory of nbpm. The packages being installed will be downloaded in this
-/// path.
+/// Default path to the working directory of nbpm, see `Config::work_dir`. The packages being
+/// installed will be downloaded in this path.
 pub const NBPM_WORK_DIR: &str = "/tmp/nbpm";
 
-/// A directory inside `NBPM_WORK_DIR` where the packages will be handled individually. For
-/// example, packages will be extracted in this directory in the first steps of the installation
-/// process.
-pub const NBPM_WORK_CURR: &str = "/tmp/nbpm/current";
+/// Name of a directory inside the working directory where the packages will be handled
+/// individually. For example, packages will be extracted in this directory in the first steps of
+/// the installation process.
+pub const NBPM_WORK_CURR: &str = "current";
+
+/// Name of a directory inside the working directory where the files of the installed versions
+/// of the packages being updated are kept, to restore them if the installation fails.
+pub const NBPM_WORK_BACKUP: &str = "backup";
 
 pub fn exit_with_err(err: Box<dyn Error>) -> ! {
     eprintln!("Error: {}", err);
     exit(1);
 }
+
+/// Same as `exit_with_err`, but the error is printed to the standard output as a JSON object,
+/// for example: `{"error":"Package foo not found"}`.
+pub fn exit_with_json_err(err: Box<dyn Error>) -> ! {
+    println!("{}", serde_json::json!({ "error": err.to_string() }));
+    exit(1);
+}
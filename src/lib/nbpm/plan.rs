@@ -0,0 +1,61 @@
+//! Plans of the changes nbpm makes in the system, computed before making them so they can be
+//! shown to the user.
+
+use semver::Version;
+use serde_derive::{Deserialize, Serialize};
+
+use std::fmt;
+
+/// Action taken on a package.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Install,
+    Update,
+    Remove,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Install => write!(f, "install"),
+            Action::Update => write!(f, "update"),
+            Action::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+/// An action to take on a package, with the version of the package before (if installed) and
+/// after (if not removed) the action.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanItem {
+    pub name: String,
+    pub action: Action,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Version>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Version>,
+}
+
+impl fmt::Display for PlanItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.from, &self.to) {
+            (Some(from), Some(to)) => write!(f, "{} {} -> {}", self.name, from, to),
+            (Some(v), None) | (None, Some(v)) => write!(f, "{} {}", self.name, v),
+            (None, None) => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Sorts the items of a plan by name.
+pub fn sort_plan(plan: &mut [PlanItem]) {
+    plan.sort_by(|a, b| a.name.cmp(&b.name));
+}
+
+/// Result of an operation that makes changes in the system: the planned changes and whether
+/// they were made (`false` if the user cancelled the operation).
+#[derive(Serialize, Debug, Clone)]
+pub struct Transaction {
+    pub plan: Vec<PlanItem>,
+    pub done: bool,
+}
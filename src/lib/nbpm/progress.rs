@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io::{stdout, Write};
+use std::sync::Mutex;
+
+use crate::fetch::Progress;
+use crate::TypeErr;
+
+/// Width (in characters) of the bar drawn by `ProgressBar`.
+const BAR_WIDTH: usize = 30;
+
+/// A `Progress` that draws a progress bar in the terminal. When several files are fetched at the
+/// same time, a single bar showing the aggregated progress of all the files is drawn.
+#[derive(Default)]
+pub struct ProgressBar {
+    /// Contains the fetched and total bytes of the files currently being fetched, by url.
+    active: Mutex<HashMap<String, (u64, Option<u64>)>>,
+}
+
+impl ProgressBar {
+    pub fn new() -> ProgressBar {
+        ProgressBar {
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Draws the bar for the given amount of fetched bytes and files.
+    fn draw(done: u64, total: Option<u64>, files: usize) {
+        let line = match total {
+            Some(total) if total > 0 => {
+                let ratio = (done as f64 / total as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                format!(
+                    "    [{}{}] {:>3}% {}/{}",
+                    "#".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    (ratio * 100.0) as usize,
+                    human_size(done),
+                    human_size(total),
+                )
+            }
+            // the size of some file is unknown, only show the amount of fetched data
+            _ => format!("    {}", human_size(done)),
+        };
+        if files > 1 {
+            print!("\r{} ({} files)  ", line, files);
+        } else {
+            print!("\r{}  ", line);
+        }
+        let _ = stdout().flush();
+    }
+}
+
+impl Progress for ProgressBar {
+    fn update(&self, url: &str, done: u64, total: Option<u64>) {
+        let mut active = match self.active.lock() {
+            Ok(a) => a,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        active.insert(url.to_string(), (done, total));
+
+        let done = active.values().map(|(d, _)| d).sum();
+        let total = active.values().map(|(_, t)| *t).sum();
+        Self::draw(done, total, active.len());
+    }
+
+    fn finish(&self, url: &str) {
+        let mut active = match self.active.lock() {
+            Ok(a) => a,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let size = active.remove(url).map(|(d, _)| d).unwrap_or(0);
+        // clear the bar and show the fetched file in its own line
+        print!("\r{}\r", " ".repeat(BAR_WIDTH + 40));
+        println!("    {} ({})", url, human_size(size));
+        let _ = stdout().flush();
+    }
+}
+
+/// Formats the given number of bytes in a human readable way, for example: `3.2 MiB`.
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// Parses a size in bytes, optionally followed by a unit (`K`, `M`, `G` or `T`, with or without
+/// `iB` or `B`, always powers of 1024), for example: `512`, `10K` or `1.5 MiB`.
+pub fn parse_size(size: &str) -> Result<u64, TypeErr> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let exp = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 1,
+        "M" | "MB" | "MIB" => 2,
+        "G" | "GB" | "GIB" => 3,
+        "T" | "TB" | "TIB" => 4,
+        _ => return Err(format!("Invalid size unit in {:?}", size).into()),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|e| format!("Invalid size {:?}: {}", size, e))?;
+    Ok((number * 1024f64.powi(exp)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_size("10M").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("10mb").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_size("1.5 MiB").unwrap(), 1024 * 1024 * 3 / 2);
+        assert_eq!(parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("7B").unwrap(), 7);
+    }
+
+    #[test]
+    fn parse_size_rejects_invalid_sizes() {
+        for size in &["", "M", "10X", "ten", "1.2.3K", "-5K"] {
+            assert!(parse_size(size).is_err(), "{:?} was accepted", size);
+        }
+    }
+}
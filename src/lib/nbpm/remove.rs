@@ -1,64 +1,87 @@
 use std::fs;
 use std::path::Path;
 
-use super::NbpmError;
+use super::plan::{sort_plan, Action, PlanItem};
+use super::ui::{Event, Prompt, Ui};
+use super::{NbpmError, Transaction};
 use crate::core::{pkgdb::PkgInfo, PkgDb, SetInfo};
 use crate::TypeErr;
 
-pub fn remove_handler(
+/// Computes the packages to remove in order to remove the packages in `to_remove`, with their
+/// dependencies if `recursive` is `true`. If `check_conflicts` is `true`, the packages are only
+/// removed if no other installed package depends on them (see `PkgDb::check_remove`). The
+/// returned plan is sorted by name.
+pub fn plan_remove(
     to_remove: &[&str],
     recursive: bool,
-    ask_user: bool,
     check_conflicts: bool,
-    local_db: &mut PkgDb,
-) -> Result<(), TypeErr> {
-    let graph = local_db.get_subgraph(Some(&to_remove), recursive)?;
-
-    if ask_user {
-        // ask the user for confirmation before removing the packages
-        println!(
-            "The following packages are going to be removed ({}):",
-            graph.len()
-        );
-        graph
-            .iter()
-            .for_each(|(name, info)| println!("     {} {}", name, info.version()));
-        match crate::utils::read_line("Are you sure you want to remove this packages? [Y/n] ") {
-            Ok(line) => {
-                if !line.is_empty() && line != "y" && line != "Y" {
-                    println!("Operation cancelled");
-                    return Ok(());
-                }
-            }
-            Err(e) => return Err(e),
-        }
-    }
-
+    local_db: &PkgDb,
+) -> Result<Vec<PlanItem>, TypeErr> {
+    let graph = local_db.get_subgraph(Some(to_remove), recursive)?;
     if check_conflicts {
-        println!("[*] Checking for conflicts...");
         let to_remove_names: Vec<&str> = graph.keys().map(|k| k.as_str()).collect();
         local_db.check_remove(to_remove_names)?;
     }
+    let mut plan: Vec<PlanItem> = graph
+        .iter()
+        .map(|(name, info)| PlanItem {
+            name: name.to_string(),
+            action: Action::Remove,
+            from: Some(info.version().clone()),
+            to: None,
+        })
+        .collect();
+    sort_plan(&mut plan);
+    Ok(plan)
+}
 
+/// Removes the packages in `to_remove` as planned by `plan_remove`, both their files and their
+/// entries in the local `PkgDb`. The plan is reported to the `ui`, that is asked for
+/// confirmation before removing any package.
+///
+/// # Errors
+///
+/// For errors computing the plan see `plan_remove`. If some packages cannot be removed, a
+/// `NbpmError::CannotRemovePkgs` error is returned, with the errors of every package.
+pub fn remove_handler(
+    to_remove: &[&str],
+    recursive: bool,
+    check_conflicts: bool,
+    local_db: &mut PkgDb,
+    ui: &dyn Ui,
+) -> Result<Transaction, TypeErr> {
+    let plan = plan_remove(to_remove, recursive, check_conflicts, local_db)?;
+    ui.event(Event::Plan(&plan));
+    if !ui.confirm(Prompt::Remove) {
+        return Ok(Transaction { plan, done: false });
+    }
+    remove_planned(&plan, local_db, ui)?;
+    Ok(Transaction { plan, done: true })
+}
+
+/// Removes the packages of a `plan` computed by `plan_remove`, without asking for confirmation.
+/// Every removed package is reported to the `ui`.
+pub(crate) fn remove_planned(
+    plan: &[PlanItem],
+    local_db: &mut PkgDb,
+    ui: &dyn Ui,
+) -> Result<(), TypeErr> {
     let mut errors = vec![];
-    for (pkg_name, pkg_info) in &graph {
-        println!("Removing {}...", pkg_name);
+    for item in plan {
+        // it's safe to call unwrap here, as all the packages of the plan are in the local db
+        let pkg_info = local_db.get_pkg_info(&item.name).unwrap();
         // remove package's files
         if let Err(err) = remove_local_pkg_files(pkg_info) {
-            eprintln!("Error while removing {}\n", pkg_name);
-            errors.push((pkg_name.to_string(), err));
+            errors.push((item.name.to_string(), err));
         }
     }
     // remove package from the local `PkgDb`, this is not done in the previous loop due to the
     // borrowing of `local_db`...
-    let to_remove_names: Vec<String> = graph.keys().map(|k| k.to_string()).collect();
-    println!();
-    for name in to_remove_names {
-        println!("Removing {} from local db", name);
+    for item in plan {
         // disable conflict check as it was done earlier
-        if let Err(e) = local_db.remove(&name, false) {
-            eprintln!("Error while removing {} from local db\n", name);
-            errors.push((name.to_string(), e));
+        match local_db.remove(&item.name, false) {
+            Ok(()) => ui.event(Event::PkgRemoved(item)),
+            Err(e) => errors.push((item.name.to_string(), e)),
         }
     }
 
@@ -85,10 +108,21 @@ pub fn remove_local_pkg_files(info: &PkgInfo) -> Result<(), TypeErr> {
         None => return Ok(()), // the package is a metapackage
     };
 
+    let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+    remove_paths(&paths)
+}
+
+/// Removes the given files and directories. Directories are only removed if they are empty once
+/// the files are removed.
+///
+/// # Errors
+///
+/// See `remove_local_pkg_files`.
+pub(crate) fn remove_paths(paths: &[&Path]) -> Result<(), TypeErr> {
     let mut errors = vec![];
     let mut dirs = vec![];
     // in this loop, only files are deleted, directories are ignored
-    paths.iter().map(|p| Path::new(p)).for_each(|p| {
+    paths.iter().for_each(|&p| {
         if p.is_dir() {
             dirs.push(p);
         } else if let Err(e) = remove_path(p) {
@@ -97,7 +131,8 @@ pub fn remove_local_pkg_files(info: &PkgInfo) -> Result<(), TypeErr> {
     });
 
     // now that all files are removed, try to remove directories. As directories only get removed
-    // if they are empty
+    // if they are empty, nested directories are removed before their parents
+    dirs.sort_by(|a, b| b.cmp(a));
     dirs.iter().for_each(|p| {
         if let Err(e) = remove_path(p) {
             errors.push((p.to_path_buf(), e));
@@ -132,3 +167,57 @@ pub fn remove_path(path: &Path) -> Result<(), TypeErr> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Set;
+    use crate::nbpm::install::install_handler;
+    use crate::nbpm::test_utils::TestEnv;
+    use crate::nbpm::ui::{ScriptedUi, SilentUi};
+
+    /// Returns a `TestEnv` with the package `foo` installed, and its local `PkgDb`.
+    fn installed_foo(name: &str) -> (TestEnv, PkgDb) {
+        let mut env = TestEnv::new(name);
+        env.add_pkg("foo", "1.0.0", &[], &["usr/bin/foo"]);
+        let mut local_db = PkgDb::with_set(Set::Local);
+        install_handler(
+            &["foo"],
+            &env.config,
+            &mut local_db,
+            &env.index_db(),
+            &env.fetcher,
+            &SilentUi,
+        )
+        .unwrap();
+        (env, local_db)
+    }
+
+    #[test]
+    fn remove_handler_removes_accepted_plan() {
+        let (env, mut local_db) = installed_foo("remove-accept");
+        let ui = ScriptedUi::new(&[true]);
+
+        let trans = remove_handler(&["foo"], false, true, &mut local_db, &ui).unwrap();
+        assert!(trans.done);
+        assert!(local_db.is_empty());
+        assert!(!env.root_path("usr/bin/foo").exists());
+        assert!(!env.root_path("usr").exists());
+        let log = ui.log();
+        assert!(log.iter().any(|e| e == "Remove"));
+        assert!(log.iter().any(|e| e.starts_with("PkgRemoved")));
+    }
+
+    #[test]
+    fn remove_handler_does_nothing_if_rejected() {
+        let (env, mut local_db) = installed_foo("remove-reject");
+        let ui = ScriptedUi::new(&[false]);
+
+        let trans = remove_handler(&["foo"], false, true, &mut local_db, &ui).unwrap();
+        assert!(!trans.done);
+        assert_eq!(trans.plan.len(), 1);
+        assert!(local_db.contains_name("foo"));
+        assert!(env.root_path("usr/bin/foo").is_file());
+        assert!(!ui.log().iter().any(|e| e.starts_with("PkgRemoved")));
+    }
+}
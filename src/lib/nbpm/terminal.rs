@@ -0,0 +1,85 @@
+//! The `Ui` of nbpm's command line interface.
+
+use std::io::{self, IsTerminal, Write};
+
+use super::ui::{Event, Prompt, Ui};
+use super::{Action, ProgressBar};
+use crate::fetch::Progress;
+
+/// A `Ui` that shows the events in the terminal, drawing a `ProgressBar` for downloads, and
+/// reads the answers to the prompts from the standard input.
+///
+/// In `json` mode, the standard output is left for the JSON output of the command: only
+/// errors and prompts are shown, in the standard error.
+///
+/// If `noconfirm` is `true`, all the prompts are accepted without asking. Otherwise, prompts are
+/// refused when the standard input is not a terminal, instead of waiting for an answer that
+/// might never come (see `refuses_prompts`).
+pub struct TerminalUi {
+    bar: ProgressBar,
+    json: bool,
+    noconfirm: bool,
+}
+
+impl TerminalUi {
+    pub fn new(json: bool, noconfirm: bool) -> TerminalUi {
+        TerminalUi {
+            bar: ProgressBar::new(),
+            json,
+            noconfirm,
+        }
+    }
+
+    /// Returns `true` if prompts are refused without asking, as the standard input is not a
+    /// terminal and `noconfirm` is not set.
+    pub fn refuses_prompts(&self) -> bool {
+        !self.noconfirm && !io::stdin().is_terminal()
+    }
+}
+
+impl Ui for TerminalUi {
+    fn event(&self, event: Event) {
+        match event {
+            Event::MirrorFailed { mirror, error } => {
+                eprintln!("[!] Mirror {} failed: {}", mirror, error)
+            }
+            Event::Error(e) => eprintln!("Error: {}", e),
+            _ if self.json => (),
+            Event::Plan([]) => println!("Nothing to do"),
+            Event::Plan(plan) => {
+                println!("Packages to be changed ({}):", plan.len());
+                plan.iter()
+                    .for_each(|p| println!("    {:<8} {}", p.action.to_string(), p));
+            }
+            Event::Download { url, done, total } => self.bar.update(url, done, total),
+            Event::Downloaded(url) => self.bar.finish(url),
+            Event::FileInstalled(_) => (),
+            Event::PkgInstalled(item) if item.action == Action::Update => {
+                println!("[*] Updated {}", item)
+            }
+            Event::PkgInstalled(item) => println!("[*] Installed {}", item),
+            Event::PkgRemoved(item) => println!("[*] Removed {}", item),
+            Event::PkgRestored(item) => match &item.from {
+                Some(v) => println!("[*] Restored {} {}", item.name, v),
+                None => println!("[*] Restored {}", item.name),
+            },
+        }
+    }
+
+    fn confirm(&self, prompt: Prompt) -> bool {
+        if self.noconfirm {
+            return true;
+        } else if self.refuses_prompts() {
+            return false;
+        }
+        // the prompt is written to the standard error, as the standard output might be JSON
+        eprint!("\n{} [Y/n] ", prompt);
+        let _ = io::stderr().flush();
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line).is_err() {
+            return false;
+        }
+        let line = line.trim();
+        line.is_empty() || line == "y" || line == "Y"
+    }
+}
@@ -0,0 +1,155 @@
+//! Helpers for the tests of nbpm: a throwaway nbpm setup serving a repository of packages with a
+//! `MockFetcher`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{Config, LOCAL_INDEX_PATH};
+use crate::core::PkgDb;
+use crate::fetch::MockFetcher;
+use crate::repo::{REPO_BIN_DIR, REPO_INDEX_PATH, REPO_PKG_INFO};
+use crate::utils;
+
+/// URL of the repository served by the `MockFetcher` of a `TestEnv`.
+pub const TEST_REPO: &str = "mock://repo";
+
+/// A temporary directory with the home, the root and the working directory of nbpm, and a
+/// repository served by a `MockFetcher`. The directory is removed when the `TestEnv` is dropped.
+pub struct TestEnv {
+    pub dir: PathBuf,
+    pub config: Config,
+    pub fetcher: MockFetcher,
+    /// Entries of the index of the repository in `toml` format, by package name.
+    index: BTreeMap<String, String>,
+}
+
+impl TestEnv {
+    /// Creates an empty `TestEnv`. `name` must be unique among the tests, as it is used to name
+    /// the temporary directory.
+    pub fn new(name: &str) -> TestEnv {
+        let dir = env::temp_dir().join(format!("nbkit-test-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        for sub in &["home/index", "root", "pkgs"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let config = toml::from_str(&format!(
+            "nbpm-home = '{0}/home'\nroot-dir = '{0}/root'\nwork-dir = '{0}/work'\nrepo_url = '{1}'",
+            dir.display(),
+            TEST_REPO
+        ))
+        .unwrap();
+        TestEnv {
+            dir,
+            config,
+            fetcher: MockFetcher::new(),
+            index: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a package to the repository, containing the given `files` (and their parent
+    /// directories), or replaces it if already added. The contents of every file is its own path
+    /// followed by the version of the package. Returns the archive of the package.
+    pub fn add_pkg(
+        &mut self,
+        name: &str,
+        version: &str,
+        depends: &[&str],
+        files: &[&str],
+    ) -> Vec<u8> {
+        let staging = self.dir.join("pkgs").join(name);
+        if staging.exists() {
+            fs::remove_dir_all(&staging).unwrap();
+        }
+        let mut paths = vec![];
+        for file in files {
+            let mut parent = Path::new(file).parent();
+            while let Some(dir) = parent.filter(|p| !p.as_os_str().is_empty()) {
+                paths.push(dir.to_string_lossy().to_string());
+                parent = dir.parent();
+            }
+            paths.push(file.to_string());
+            fs::create_dir_all(staging.join(file).parent().unwrap()).unwrap();
+            fs::write(staging.join(file), format!("{} {}", file, version)).unwrap();
+        }
+        paths.sort();
+        paths.dedup();
+        fs::write(
+            staging.join(REPO_PKG_INFO),
+            format!(
+                "[{0}]\nversion = '{1}'\ndepends = {2:?}\ndescription = '{0}'\n\n[{0}.local]\npaths = {3:?}\n",
+                name, version, depends, paths
+            ),
+        )
+        .unwrap();
+
+        let archive = self.dir.join("pkgs").join(format!("{}.tar.xz", name));
+        let archive_str = archive.to_string_lossy();
+        let staging_str = staging.to_string_lossy();
+        utils::run_cmd("tar", &["cJf", &archive_str, "-C", &staging_str, "."]).unwrap();
+        let contents = fs::read(&archive).unwrap();
+        self.add_archive(name, version, depends, &contents);
+        contents
+    }
+
+    /// Adds a package to the repository served as the given `archive`, with the right hash. If
+    /// the package was already added, it is replaced.
+    pub fn add_archive(&mut self, name: &str, version: &str, depends: &[&str], archive: &[u8]) {
+        let path = self.dir.join("pkgs").join(format!("{}.archive", name));
+        fs::write(&path, archive).unwrap();
+        let hash = utils::file2hash(&path).unwrap();
+
+        let location = format!("{}/{}/core/{}", TEST_REPO, REPO_BIN_DIR, name);
+        self.fetcher
+            .insert(&format!("{}/{}.tar.xz", location, name), archive);
+        self.fetcher.insert(
+            &format!("{}/{}.sha256", location, name),
+            format!("{}\n", hash).as_bytes(),
+        );
+        self.index.insert(
+            name.to_string(),
+            format!(
+                "\n[{0}]\nversion = '{1}'\ndepends = {2:?}\ndescription = '{0}'\n\n[{0}.universe]\nlocation = 'core/{0}'\n",
+                name, version, depends
+            ),
+        );
+    }
+
+    /// Returns the index of the repository in `toml` format.
+    fn index_toml(&self) -> String {
+        let entries: String = self.index.values().map(|e| e.as_str()).collect();
+        format!("set = 'universe'\n{}", entries)
+    }
+
+    /// Returns the index of the repository.
+    pub fn index_db(&self) -> PkgDb {
+        toml::from_str(&self.index_toml()).unwrap()
+    }
+
+    /// Serves the index of the repository, as `update_index` downloads it.
+    pub fn publish_index(&mut self) {
+        self.fetcher.insert(
+            &format!("{}/{}", TEST_REPO, REPO_INDEX_PATH),
+            self.index_toml().as_bytes(),
+        );
+    }
+
+    /// Returns the path where `update_index` stores the index.
+    pub fn local_index_path(&self) -> PathBuf {
+        Path::new(self.config.home()).join(LOCAL_INDEX_PATH)
+    }
+
+    /// Returns the path of `file` inside the root.
+    pub fn root_path(&self, file: &str) -> PathBuf {
+        Path::new(self.config.root()).join(file)
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,154 @@
+//! Interface between the operations of nbpm and the user.
+//!
+//! Operations such as `install_handler` or `remove_handler` do not write to the terminal nor
+//! read from it. Instead, they report what they do as typed `Event`s and ask for confirmation
+//! through a `Ui`, so the same operations can be used from the command line, a daemon or a GUI.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::PlanItem;
+use crate::fetch::Progress;
+
+/// Something that happened during an operation of nbpm.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// The changes the operation is going to make were computed.
+    Plan(&'a [PlanItem]),
+    /// A chunk of the file in `url` was downloaded. `total` is the size of the file, if known.
+    Download {
+        url: &'a str,
+        done: u64,
+        total: Option<u64>,
+    },
+    /// The file in `url` was completely downloaded.
+    Downloaded(&'a str),
+    /// A mirror failed to serve a file, the next mirror is tried.
+    MirrorFailed {
+        mirror: &'a str,
+        error: &'a dyn Error,
+    },
+    /// A file of a package was written to the system.
+    FileInstalled(&'a Path),
+    /// A package was installed (or updated).
+    PkgInstalled(&'a PlanItem),
+    /// A package was removed.
+    PkgRemoved(&'a PlanItem),
+    /// A package being updated was restored to its installed version, as the operation failed.
+    PkgRestored(&'a PlanItem),
+    /// An error that does not stop the operation by itself, for example, an error while
+    /// undoing a failed installation.
+    Error(&'a dyn Error),
+}
+
+/// Questions a `Ui` is asked before making changes in the system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prompt {
+    /// Install the packages of the last `Event::Plan`.
+    Install,
+    /// Upgrade the packages of the last `Event::Plan`.
+    Upgrade,
+    /// Remove the packages of the last `Event::Plan`.
+    Remove,
+    /// Make the changes of the last `Event::Plan` to apply a manifest.
+    Apply,
+}
+
+impl fmt::Display for Prompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prompt::Install => write!(f, "Are you sure you want to install this packages?"),
+            Prompt::Upgrade => write!(f, "Are you sure you want to upgrade this packages?"),
+            Prompt::Apply => write!(f, "Are you sure you want to apply this changes?"),
+            Prompt::Remove => write!(f, "Are you sure you want to remove this packages?"),
+        }
+    }
+}
+
+/// Receives the events of the operations of nbpm and answers their confirmation prompts.
+pub trait Ui: Sync {
+    /// Called for every `Event` of the operation.
+    fn event(&self, event: Event);
+
+    /// Asks the user a question, returns `true` if the user accepts.
+    fn confirm(&self, prompt: Prompt) -> bool;
+}
+
+/// A `Ui` that ignores all the events and accepts every prompt. Used for non interactive
+/// operations, such as bootstrapping build environments.
+pub struct SilentUi;
+
+impl Ui for SilentUi {
+    fn event(&self, _event: Event) {}
+
+    fn confirm(&self, _prompt: Prompt) -> bool {
+        true
+    }
+}
+
+/// A `Ui` that answers prompts with a predefined script, mainly intended for testing. Every
+/// event is recorded as a string (the `Debug` representation of the event), so the events of an
+/// operation can be checked afterwards.
+#[derive(Default)]
+pub struct ScriptedUi {
+    /// Answers to the next prompts, in order. Once the script runs out, prompts are rejected.
+    answers: Mutex<VecDeque<bool>>,
+    /// Received events and prompts, in order.
+    log: Mutex<Vec<String>>,
+}
+
+impl ScriptedUi {
+    pub fn new(answers: &[bool]) -> ScriptedUi {
+        ScriptedUi {
+            answers: Mutex::new(answers.iter().copied().collect()),
+            log: Mutex::new(vec![]),
+        }
+    }
+
+    /// Returns all the events and prompts received by the `Ui`, in order.
+    pub fn log(&self) -> Vec<String> {
+        match self.log.lock() {
+            Ok(l) => l.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn record(&self, entry: String) {
+        match self.log.lock() {
+            Ok(mut l) => l.push(entry),
+            Err(poisoned) => poisoned.into_inner().push(entry),
+        }
+    }
+}
+
+impl Ui for ScriptedUi {
+    fn event(&self, event: Event) {
+        self.record(format!("{:?}", event));
+    }
+
+    fn confirm(&self, prompt: Prompt) -> bool {
+        self.record(format!("{:?}", prompt));
+        let answer = match self.answers.lock() {
+            Ok(mut a) => a.pop_front(),
+            Err(poisoned) => poisoned.into_inner().pop_front(),
+        };
+        answer.unwrap_or(false)
+    }
+}
+
+/// Reports the progress of fetched files to a `Ui`, as `Event::Download` and
+/// `Event::Downloaded` events.
+pub struct UiProgress<'a>(pub &'a dyn Ui);
+
+impl Progress for UiProgress<'_> {
+    fn update(&self, url: &str, done: u64, total: Option<u64>) {
+        self.0.event(Event::Download { url, done, total });
+    }
+
+    fn finish(&self, url: &str) {
+        self.0.event(Event::Downloaded(url));
+    }
+}
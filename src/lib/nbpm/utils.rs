@@ -1,28 +1,29 @@
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs;
-use std::io::{stdin, stdout, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Mutex;
+use std::thread;
 
+use super::plan::{sort_plan, Action, PlanItem};
+use super::ui::{Event, Ui, UiProgress};
 use super::{config::Config, NbpmError};
-use super::{LOCAL_DB_PATH, LOCAL_INDEX_PATH, NBPM_WORK_CURR, NBPM_WORK_DIR};
-use crate::core::{pkgdb::PkgInfo, PkgDb, Set, SetInfo};
-use crate::repo::REPO_BIN_DIR;
+use super::{LOCAL_DB_PATH, LOCAL_INDEX_PATH};
+use crate::core::{pkgdb::PkgInfo, NbError, PkgDb, Set, SetInfo};
+use crate::fetch::{Fetcher, NoProgress};
+use crate::repo::index::read_hash_file;
+use crate::repo::{REPO_BIN_DIR, REPO_INDEX_PATH};
 use crate::{utils, TypeErr};
 
-/// Read user input from command line in form of a `String`.
-pub fn read_line(prompt: &str) -> Result<String, TypeErr> {
-    print!("{}", prompt);
-    stdout().flush()?;
-    let mut line = String::new();
-    let _n = stdin().read_line(&mut line)?;
-    line = line.trim_end().to_string();
-    Ok(line)
-}
-
 /// Given a `Set` and the `Config` for `nbpm`, the function loads the index
 /// `PkgDb` (if `set` is `Universe`) or local db `PkgDb` (if `set` is `Local`).
-pub fn load_pkgdb(config: &Config, set: Set) -> Result<PkgDb, NbpmError> {
+///
+/// # Errors
+///
+/// If the db cannot be loaded, a `NbpmError::LocalDbLoad` or `NbpmError::RepoIndexLoad` error is
+/// returned.
+pub fn load_pkgdb(config: &Config, set: Set) -> Result<PkgDb, TypeErr> {
     let db_path = format!(
         "{}/{}",
         config.home(),
@@ -35,68 +36,69 @@ pub fn load_pkgdb(config: &Config, set: Set) -> Result<PkgDb, NbpmError> {
     match PkgDb::load(Path::new(&db_path)) {
         Ok(db) => Ok(db),
         Err(e) => match set {
-            Set::Local => Err(NbpmError::LocalDbLoad(format!("{}: {}", db_path, e))),
-            Set::Universe => Err(NbpmError::RepoIndexLoad(format!("{}: {}", db_path, e))),
+            Set::Local => Err(Box::new(NbpmError::LocalDbLoad(format!(
+                "{}: {}",
+                db_path, e
+            )))),
+            Set::Universe => Err(Box::new(NbpmError::RepoIndexLoad(format!(
+                "{}: {}",
+                db_path, e
+            )))),
         },
     }
 }
 
-/// Creates the working directory of nbpm according to `nbpm::NBPM_WORK_DIR`. If the directory
-/// already exits, this function does nothing. It also creates the current working directory
-/// in `nbpm::NBPM_WORK_CURR`.
-pub fn init_working_dir() -> Result<(), TypeErr> {
-    if Path::new(NBPM_WORK_DIR).is_dir() {
-        //if the directory exists, delete all its contents, as they are considered old
-        if let Err(e) = fs::remove_dir_all(NBPM_WORK_DIR) {
-            return Err(Box::new(e));
-        }
-    }
-
-    if let Err(e) = fs::create_dir(NBPM_WORK_DIR) {
-        return Err(Box::new(e));
-    }
-
-    // same with nbpm's current working directory
-    if Path::new(NBPM_WORK_CURR).is_dir() {
-        clean_work_curr()?;
-    }
-    if let Err(e) = fs::create_dir(NBPM_WORK_CURR) {
-        return Err(Box::new(e));
+/// Creates the working directory of nbpm (`Config::work_dir`). If the directory already exists,
+/// all its contents are deleted, as they are considered old. It also creates the current working
+/// directory (`Config::work_curr`).
+pub fn init_working_dir(config: &Config) -> Result<(), TypeErr> {
+    if Path::new(config.work_dir()).is_dir() {
+        fs::remove_dir_all(config.work_dir())?;
     }
+    fs::create_dir_all(config.work_dir())?;
+    fs::create_dir(config.work_curr())?;
     Ok(())
 }
 
-/// Cleans all the contents of `NBPM_WORK_CURR` directory.
-pub fn clean_work_curr() -> Result<(), TypeErr> {
-    if let Err(e) = fs::remove_dir_all(NBPM_WORK_CURR) {
-        return Err(Box::new(e));
-    }
-
-    if let Err(e) = fs::create_dir(NBPM_WORK_CURR) {
-        return Err(Box::new(e));
-    }
+/// Cleans all the contents of the current working directory (`Config::work_curr`).
+pub fn clean_work_curr(config: &Config) -> Result<(), TypeErr> {
+    fs::remove_dir_all(config.work_curr())?;
+    fs::create_dir(config.work_curr())?;
     Ok(())
 }
 
-/// Downloads all the packages listed in the given graph to the `NBPM_WORK_DIR` path. `config` is
-/// also needed in order to get the url of the repository to install the packages from.
+/// Downloads all the packages listed in the given graph to the working directory of the `config`
+/// (see `Config::work_dir`) using the
+/// given `fetcher`, reporting the download progress to `ui`. `config` is also needed in
+/// order to get the url of the repository to install the packages from, and the maximum number
+/// of packages to download in parallel.
+///
+/// Packages are downloaded from the mirrors of the repository (see `fetch_from_mirrors`), and
+/// the SHA256 hash of every downloaded package is checked.
 ///
 /// In the case of successfull download of all packages, the function returns a list of tuples.
-/// Each tuple contains the name of the package and the path to the downloaded package.
+/// Each tuple contains the name of the package, the path to the downloaded package and the url
+/// of the mirror the package was downloaded from. The list is sorted by package name, no matter
+/// the order the downloads finished in.
 ///
 /// # Errors
 ///
-/// In case of failing download any package, the function returns an error describing the cause of
-/// the download failure, from more datails see `utils::download`.
+/// If the download of any package fails, the rest of the packages are still downloaded and a
+/// `NbpmError::CannotDownloadPkgs` error is returned, containing the name of every package that
+/// could not be downloaded and the cause of the failure (for more datails see the documentation
+/// of the used `Fetcher`).
 pub fn download_pkgs_to_workdir(
     graph: &HashMap<String, &PkgInfo>,
     config: &Config,
-) -> Result<Vec<(String, String)>, TypeErr> {
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<Vec<(String, String, String)>, TypeErr> {
     // initialize the working directory
-    init_working_dir()?;
+    init_working_dir(config)?;
 
-    // download all the packages to be installed
-    let mut downl_files = vec![];
+    // list of the packages to download, containing the name of the package, the location of the
+    // package and its hash in the repository and the path where the package will be downloaded to
+    let mut pending = vec![];
     for (name, info) in graph {
         //  get the location of the package in the server
         let pkg_loc = match info.set_info() {
@@ -109,27 +111,195 @@ pub fn download_pkgs_to_workdir(
 
         // name of the compressed package
         let pkg_xz_name = format!("{}.tar.xz", name);
-        // the url to download the package from
-        let pkg_url = format!(
-            "{}/{}/{}/{}",
-            config.repo_url(),
-            REPO_BIN_DIR,
-            pkg_loc,
-            pkg_xz_name
-        );
+        // path of the package and its hash, relative to the root of the repository
+        let pkg_xz_loc = format!("{}/{}/{}", REPO_BIN_DIR, pkg_loc, pkg_xz_name);
+        let pkg_hash_loc = format!("{}/{}/{}.sha256", REPO_BIN_DIR, pkg_loc, name);
         // final path where the compressed package will be downloaded to
-        let pkg_xz_path = format!("{}/{}", NBPM_WORK_DIR, pkg_xz_name);
+        let pkg_xz_path = format!("{}/{}", config.work_dir(), pkg_xz_name);
+        pending.push((name.clone(), pkg_xz_loc, pkg_hash_loc, pkg_xz_path));
+    }
+    pending.sort();
 
-        println!("[*] Downloanding: {}", pkg_url);
-        utils::download(&pkg_url, Path::new(&pkg_xz_path))?;
-        downl_files.push((name.clone(), pkg_xz_path));
+    // index of the next package to download, shared between all the download threads
+    let next = AtomicUsize::new(0);
+    // the result of the download of every package, in the same order as `pending`. As
+    // `TypeErr` cannot be sent between threads, errors are stored as strings
+    let results: Mutex<Vec<Option<Result<String, String>>>> = Mutex::new(vec![None; pending.len()]);
+    thread::scope(|scope| {
+        for _ in 0..config.parallel_downloads().min(pending.len()) {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, AtomicOrdering::SeqCst);
+                let (_, xz_loc, hash_loc, path) = match pending.get(idx) {
+                    Some(p) => p,
+                    None => break, // all packages are already downloaded
+                };
+                let res = fetch_from_mirrors(
+                    config,
+                    xz_loc,
+                    Some(hash_loc),
+                    Path::new(path),
+                    fetcher,
+                    ui,
+                )
+                .map_err(|e| e.to_string());
+                if let Ok(mut r) = results.lock() {
+                    r[idx] = Some(res);
+                }
+            });
+        }
+    });
+
+    let results = match results.into_inner() {
+        Ok(r) => r,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let mut downl_files = vec![];
+    let mut errors = vec![];
+    for ((name, _, _, path), res) in pending.into_iter().zip(results) {
+        match res {
+            Some(Ok(mirror)) => downl_files.push((name, path, mirror)),
+            Some(Err(e)) => errors.push((name, e.into())),
+            None => errors.push((name, "download did not finish".into())),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(downl_files)
+    } else {
+        Err(Box::new(NbpmError::CannotDownloadPkgs(errors)))
     }
-    Ok(downl_files)
+}
+
+/// Fetches the file located in `location` (relative to the root of the repository) from the
+/// mirrors of the repository in `config`, and saves it as `outfile`. Mirrors are tried in order
+/// and if a mirror fails (connection errors, error responses...), the next mirror is tried.
+///
+/// If `hash_location` is given, the SHA256 hash of the file is also fetched from the same mirror
+/// (from `hash_location`) and checked against the fetched file. A hash mismatch is treated as a
+/// mirror failure.
+///
+/// The download progress and the failures of the mirrors are reported to `ui`. If the file is
+/// fetched successfully, the url of the mirror that served the file is returned.
+///
+/// # Errors
+///
+/// If all mirrors fail, a `NbpmError::MirrorsFailed` error is returned, containing the url of
+/// every mirror and the error it failed with.
+pub fn fetch_from_mirrors(
+    config: &Config,
+    location: &str,
+    hash_location: Option<&str>,
+    outfile: &Path,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<String, TypeErr> {
+    let mut errors = vec![];
+    for mirror in config.mirrors() {
+        let url = format!("{}/{}", mirror, location);
+        let mut res = fetcher.fetch(&url, outfile, &UiProgress(ui));
+        if let (Ok(()), Some(hash_loc)) = (&res, hash_location) {
+            res = check_mirror_hash(&mirror, hash_loc, outfile, fetcher);
+        }
+        match res {
+            Ok(()) => return Ok(mirror.to_string()),
+            Err(e) => {
+                ui.event(Event::MirrorFailed {
+                    mirror: &mirror,
+                    error: e.as_ref(),
+                });
+                errors.push((mirror.to_string(), e));
+            }
+        }
+    }
+    Err(Box::new(NbpmError::MirrorsFailed(errors)))
+}
+
+/// Fetches the SHA256 hash in `hash_location` from the given `mirror` and checks if it matches
+/// the hash of `file`.
+///
+/// # Errors
+///
+/// If the hashes do not match, a `NbError::HashMismatch` error is returned.
+fn check_mirror_hash(
+    mirror: &str,
+    hash_location: &str,
+    file: &Path,
+    fetcher: &dyn Fetcher,
+) -> Result<(), TypeErr> {
+    let hash_url = format!("{}/{}", mirror, hash_location);
+    let mut hash_path = file.as_os_str().to_os_string();
+    hash_path.push(".sha256");
+    fetcher.fetch(&hash_url, Path::new(&hash_path), &NoProgress)?;
+
+    let expected = read_hash_file(Path::new(&hash_path))?;
+    let actual = utils::file2hash(file)?;
+    if expected != actual {
+        return Err(Box::new(NbError::HashMismatch(
+            file.display().to_string(),
+            expected,
+            actual,
+        )));
+    }
+    Ok(())
+}
+
+/// Downloads the index `PkgDb` of the repository in `config` using the given `fetcher`, and
+/// stores it in `LOCAL_INDEX_PATH`, replacing the old index. The index is downloaded from the
+/// first mirror able to serve it (see `fetch_from_mirrors`), and the download progress is
+/// reported to `ui`. Returns the url of the mirror the index was downloaded from.
+///
+/// # Errors
+///
+/// If the index cannot be downloaded from any mirror, a `NbpmError::MirrorsFailed` error is
+/// returned.
+pub fn update_index(
+    config: &Config,
+    fetcher: &dyn Fetcher,
+    ui: &dyn Ui,
+) -> Result<String, TypeErr> {
+    // path to store the new index db
+    let index_path = format!("{}/{}", config.home(), LOCAL_INDEX_PATH);
+    let mirror = fetch_from_mirrors(
+        config,
+        REPO_INDEX_PATH,
+        None,
+        Path::new(&index_path),
+        fetcher,
+        ui,
+    )?;
+    Ok(mirror)
+}
+
+/// Checks if all the packages of the given graph can be installed in the architecture of the
+/// system (see `PkgInfo::is_compatible`). If `config` allows foreign architectures, this
+/// function does nothing.
+///
+/// # Errors
+///
+/// If any package is built for another architecture, a `NbpmError::ForeignArchPkgs` error is
+/// returned, listing all the foreign packages.
+pub fn check_arch(graph: &HashMap<String, &PkgInfo>, config: &Config) -> Result<(), TypeErr> {
+    if config.allow_foreign_arch() {
+        return Ok(());
+    }
+    let mut foreign: Vec<(String, String)> = graph
+        .iter()
+        .filter(|(_, info)| !info.is_compatible(config.arch()))
+        .map(|(name, info)| (name.to_string(), info.arch().unwrap_or("").to_string()))
+        .collect();
+    if foreign.is_empty() {
+        return Ok(());
+    }
+    foreign.sort();
+    Err(Box::new(NbpmError::ForeignArchPkgs(
+        config.arch().to_string(),
+        foreign,
+    )))
 }
 
 /// Removes the packages already installed on the system (this info isobtained from the given
-/// `PkgDb`) from the given packages graph. This function also lists the names, the action nbpm
-/// will take and basic info about the packages that remain in the graph.
+/// `PkgDb`) from the given packages graph. Returns the action nbpm will take (install or update)
+/// for every package that remains in the graph, sorted by name.
 ///
 /// # Error
 ///
@@ -138,16 +308,17 @@ pub fn download_pkgs_to_workdir(
 pub fn purge_already_installed(
     graph: &mut HashMap<String, &PkgInfo>,
     db: &PkgDb,
-) -> Result<(), TypeErr> {
+) -> Result<Vec<PlanItem>, TypeErr> {
     let mut not_install = vec![]; // list of packages already installed and to be skipped
+    let mut plan = vec![];
     for (name, info) in graph.iter() {
+        let new_ver = info.version();
         match db.get_pkg_info(name) {
             Some(local_pkg_info) => {
                 // there is a package with the same name already installed in the system.
                 // Determine if the package has to be updated or if the installation of this
                 // package should be skipped.
                 let curr_ver = local_pkg_info.version(); // current version of the package
-                let new_ver = info.version();
                 match new_ver.cmp(curr_ver) {
                     // a package with the same name and versions exits in the system, so skip the
                     // instalation of this package as it is already installed
@@ -161,19 +332,189 @@ pub fn purge_already_installed(
                         )))
                     }
                     // every thing is ok, just update the package to a newer version of it
-                    Ordering::Greater => println!(
-                        "    {} {}    update {} -> {}",
-                        name, info, curr_ver, new_ver,
-                    ),
+                    Ordering::Greater => plan.push(PlanItem {
+                        name: name.to_string(),
+                        action: Action::Update,
+                        from: Some(curr_ver.clone()),
+                        to: Some(new_ver.clone()),
+                    }),
                 }
             }
             // there is no package with the same name in the local PkgDb
-            None => println!("    {} {}    install", name, info),
+            None => plan.push(PlanItem {
+                name: name.to_string(),
+                action: Action::Install,
+                from: None,
+                to: Some(new_ver.clone()),
+            }),
         }
     }
     // delete already installed packages from the graph
     for name in &not_install {
         let _ = graph.remove_entry(name);
     }
-    Ok(())
+    sort_plan(&mut plan);
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::MockFetcher;
+    use crate::nbpm::test_utils::TestEnv;
+    use crate::nbpm::ui::{ScriptedUi, SilentUi};
+
+    #[test]
+    fn update_index_downloads_the_index() {
+        let mut env = TestEnv::new("update-index");
+        env.add_pkg("foo", "1.0.0", &[], &["usr/bin/foo"]);
+        env.publish_index();
+
+        let mirror = update_index(&env.config, &env.fetcher, &SilentUi).unwrap();
+        assert_eq!(mirror, "mock://repo");
+        let index_db = PkgDb::load(&env.local_index_path()).unwrap();
+        assert!(index_db.contains_name("foo"));
+    }
+
+    #[test]
+    fn update_index_fails_without_index() {
+        let env = TestEnv::new("update-index-missing");
+        let err = update_index(&env.config, &env.fetcher, &SilentUi).unwrap_err();
+        assert!(err.to_string().contains("mock://repo"), "{}", err);
+        assert!(!env.local_index_path().exists());
+    }
+
+    /// Returns the SHA256 hash of `contents`.
+    fn hash(env: &TestEnv, contents: &str) -> String {
+        let path = env.dir.join("hashed");
+        fs::write(&path, contents).unwrap();
+        utils::file2hash(&path).unwrap()
+    }
+
+    /// Returns a `Config` for the repository `mock://a` with the mirror `mock://b`, and a
+    /// `MockFetcher` serving `file` (and its hash) from `mock://b`.
+    fn mirrored_repo(env: &TestEnv) -> (Config, MockFetcher) {
+        let config: Config = toml::from_str(&format!(
+            "nbpm-home = '{0}/home'\nwork-dir = '{0}/work'\nrepo_url = 'mock://a'\nmirrors = ['mock://b']",
+            env.dir.display()
+        ))
+        .unwrap();
+        let mut fetcher = MockFetcher::new();
+        fetcher.insert("mock://b/file", b"contents");
+        fetcher.insert(
+            "mock://b/file.sha256",
+            format!("{}  file\n", hash(env, "contents")).as_bytes(),
+        );
+        (config, fetcher)
+    }
+
+    #[test]
+    fn fetch_from_mirrors_skips_failed_mirror() {
+        let env = TestEnv::new("mirrors-failed");
+        let (config, fetcher) = mirrored_repo(&env);
+        let ui = ScriptedUi::new(&[]);
+        let outfile = env.dir.join("file");
+
+        let mirror = fetch_from_mirrors(
+            &config,
+            "file",
+            Some("file.sha256"),
+            &outfile,
+            &fetcher,
+            &ui,
+        )
+        .unwrap();
+        assert_eq!(mirror, "mock://b");
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "contents");
+        assert_eq!(
+            fetcher.requests(),
+            vec!["mock://a/file", "mock://b/file", "mock://b/file.sha256"]
+        );
+        assert!(ui.log().iter().any(|e| e.contains("MirrorFailed")));
+    }
+
+    #[test]
+    fn fetch_from_mirrors_skips_mirror_with_bad_hash() {
+        let env = TestEnv::new("mirrors-bad-hash");
+        let (config, mut fetcher) = mirrored_repo(&env);
+        fetcher.insert("mock://a/file", b"tampered");
+        fetcher.insert(
+            "mock://a/file.sha256",
+            format!("{}\n", hash(&env, "contents")).as_bytes(),
+        );
+        let outfile = env.dir.join("file");
+
+        let mirror = fetch_from_mirrors(
+            &config,
+            "file",
+            Some("file.sha256"),
+            &outfile,
+            &fetcher,
+            &SilentUi,
+        )
+        .unwrap();
+        assert_eq!(mirror, "mock://b");
+        assert_eq!(fs::read_to_string(&outfile).unwrap(), "contents");
+    }
+
+    #[test]
+    fn fetch_from_mirrors_fails_if_all_mirrors_fail() {
+        let env = TestEnv::new("mirrors-all-failed");
+        let (config, _) = mirrored_repo(&env);
+        let outfile = env.dir.join("file");
+
+        let err = fetch_from_mirrors(
+            &config,
+            "file",
+            None,
+            &outfile,
+            &MockFetcher::new(),
+            &SilentUi,
+        )
+        .unwrap_err();
+        match err.downcast_ref::<NbpmError>() {
+            Some(NbpmError::MirrorsFailed(errors)) => {
+                let mirrors: Vec<&str> = errors.iter().map(|(m, _)| m.as_str()).collect();
+                assert_eq!(mirrors, vec!["mock://a", "mock://b"]);
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn download_pkgs_to_workdir_reports_all_failures() {
+        let mut env = TestEnv::new("download-pkgs");
+        for name in &["foo", "bar", "baz", "qux"] {
+            env.add_pkg(name, "1.0.0", &[], &[&format!("usr/bin/{}", name)]);
+        }
+        // bar is tampered with, and qux is not served at all
+        env.fetcher
+            .insert("mock://repo/bin/core/bar/bar.tar.xz", b"tampered");
+        let mut index_db = env.index_db();
+        let qux = index_db.get_pkg_info("qux").unwrap().clone();
+        index_db.remove("qux", false).unwrap();
+        let _ = index_db.insert("quux", qux);
+        let graph: HashMap<String, &PkgInfo> =
+            index_db.iter().map(|(n, i)| (n.to_string(), i)).collect();
+
+        let err =
+            download_pkgs_to_workdir(&graph, &env.config, &env.fetcher, &SilentUi).unwrap_err();
+        match err.downcast_ref::<NbpmError>() {
+            Some(NbpmError::CannotDownloadPkgs(errors)) => {
+                let failed: Vec<&str> = errors.iter().map(|(n, _)| n.as_str()).collect();
+                assert_eq!(failed, vec!["bar", "quux"]);
+            }
+            _ => panic!("unexpected error: {}", err),
+        }
+        // the rest of the packages are still downloaded
+        let work_dir = Path::new(env.config.work_dir());
+        for name in &["foo", "baz"] {
+            let path = work_dir.join(format!("{}.tar.xz", name));
+            assert_eq!(
+                fs::read(&path).unwrap(),
+                fs::read(env.dir.join("pkgs").join(format!("{}.tar.xz", name))).unwrap()
+            );
+        }
+        assert!(!work_dir.join("quux.tar.xz").exists());
+    }
 }
@@ -0,0 +1,244 @@
+//! Consistency checks for nebula repositories. See `check_repo`.
+
+use walkdir::WalkDir;
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use super::index::{self, location_pkg_name, pkg_archive_path, pkg_hash_path};
+use super::{RepoError, REPO_SRC_DIR, REPO_TEMPLATE};
+use crate::core::{pkgdb::PkgInfo, NbError, PkgDb, SetInfo};
+use crate::{utils, TypeErr};
+
+/// Finds the locations (relative to `src`) of the sources of all the packages of the repository,
+/// this is, every directory containing a `template` script. The returned list is sorted.
+pub fn find_srcs(repo: &Path) -> Result<Vec<String>, TypeErr> {
+    let src = repo.join(REPO_SRC_DIR);
+    let mut locations = vec![];
+    if !src.is_dir() {
+        return Ok(locations);
+    }
+    for entry in WalkDir::new(&src) {
+        let entry = entry?;
+        if !entry.path().is_file() || entry.file_name() != REPO_TEMPLATE {
+            continue;
+        }
+        if let Some(parent) = entry.path().parent() {
+            if let Some(loc) = parent.strip_prefix(&src)?.to_str() {
+                locations.push(loc.to_string());
+            }
+        }
+    }
+    locations.sort();
+    Ok(locations)
+}
+
+/// Checks the consistency of the repository, and returns every problem found (an empty list
+/// means the repository is consistent). The following is checked:
+///
+/// - `src` and `bin` are symmetrical.
+/// - Every binary package has a `.sha256` file, and the hash is correct.
+/// - Every package of the index has a binary package, and every binary package is in the index.
+/// - The dependency graph of the index is closed (see `PkgDb::subgraph_integrity_errors`).
+/// - The `nbinfo.toml` embedded in every binary package matches the index.
+///
+/// # Errors
+///
+/// An error is only returned if the checks cannot be performed, for example, if the index cannot
+/// be loaded.
+pub fn check_repo(repo: &Path) -> Result<Vec<TypeErr>, TypeErr> {
+    let mut problems: Vec<TypeErr> = vec![];
+
+    // ---- src and bin symmetry ---- //
+    let srcs: BTreeSet<String> = find_srcs(repo)?.into_iter().collect();
+    let bins: BTreeSet<String> = index::find_pkgs(repo)?.into_iter().collect();
+    for loc in srcs.difference(&bins) {
+        problems.push(Box::new(RepoError::MissingBin(loc.to_string())));
+    }
+    for loc in bins.difference(&srcs) {
+        problems.push(Box::new(RepoError::MissingSrc(loc.to_string())));
+    }
+
+    // ---- hashes ---- //
+    for loc in &bins {
+        let hash_path = pkg_hash_path(repo, loc);
+        if !hash_path.is_file() {
+            problems.push(Box::new(RepoError::MissingHash(loc.to_string())));
+            continue;
+        }
+        let archive = pkg_archive_path(repo, loc);
+        // unreadable files are problems of the package, the rest of the checks go on
+        let expected = match index::read_hash_file(&hash_path) {
+            Ok(h) => h,
+            Err(e) => {
+                problems.push(format!("Cannot read {}: {}", hash_path.display(), e).into());
+                continue;
+            }
+        };
+        let actual = match utils::file2hash(&archive) {
+            Ok(h) => h,
+            Err(e) => {
+                problems.push(format!("Cannot read {}: {}", archive.display(), e).into());
+                continue;
+            }
+        };
+        if expected != actual {
+            problems.push(Box::new(NbError::HashMismatch(
+                archive.display().to_string(),
+                expected,
+                actual,
+            )));
+        }
+    }
+
+    // ---- index and binary packages ---- //
+    let index_db = index::load_index(repo)?;
+    let mut indexed = BTreeSet::new(); // locations of the packages of the index
+    for (name, info) in index_db.iter() {
+        let loc = match info.set_info() {
+            Some(SetInfo::Universe(u)) => u.location(),
+            Some(SetInfo::Local(_)) => {
+                problems.push(Box::new(RepoError::InfoMismatch(
+                    name.to_string(),
+                    "set".to_string(),
+                )));
+                continue;
+            }
+            None => continue, // meta-packages have no binary package
+        };
+        indexed.insert(loc.to_string());
+        if location_pkg_name(loc) != name || !bins.contains(loc) {
+            problems.push(Box::new(RepoError::MissingArchive(
+                name.to_string(),
+                loc.to_string(),
+            )));
+            continue;
+        }
+        // check the info embedded in the binary package
+        match index::read_archive_info(&pkg_archive_path(repo, loc), name) {
+            Ok(embedded) => problems.extend(info_mismatches(name, info, &embedded)),
+            Err(e) => problems.push(e),
+        }
+    }
+    for loc in bins.difference(&indexed) {
+        problems.push(Box::new(RepoError::NotInIndex(loc.to_string())));
+    }
+
+    // ---- dependency graph ---- //
+    problems.extend(
+        dependency_errors(&index_db)
+            .into_iter()
+            .map(|e| Box::new(e) as TypeErr),
+    );
+
+    Ok(problems)
+}
+
+/// Returns the problems that break the integrity of the dependency graph of the whole `db`.
+fn dependency_errors(db: &PkgDb) -> Vec<NbError> {
+    let graph: HashMap<String, &PkgInfo> = db.iter().map(|(n, i)| (n.to_string(), i)).collect();
+    PkgDb::subgraph_integrity_errors(&graph)
+}
+
+/// Compares the `PkgInfo` of a package in the index with the `PkgInfo` embedded in its binary
+/// package, and returns a `RepoError::InfoMismatch` for every field that does not match.
+fn info_mismatches(name: &str, index: &PkgInfo, embedded: &PkgInfo) -> Vec<TypeErr> {
+    let mut fields = vec![];
+    if index.version() != embedded.version() {
+        fields.push("version");
+    }
+    if index.description() != embedded.description() {
+        fields.push("description");
+    }
+    if index.depends() != embedded.depends() {
+        fields.push("dependency list");
+    }
+    if index.arch() != embedded.arch() {
+        fields.push("architecture");
+    }
+    if index.provides() != embedded.provides() {
+        fields.push("provided sonames");
+    }
+    if index.is_meta() != embedded.is_meta() {
+        fields.push("set");
+    }
+    fields
+        .into_iter()
+        .map(|f| Box::new(RepoError::InfoMismatch(name.to_string(), f.to_string())) as TypeErr)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::test_utils::TestRepo;
+    use crate::repo::REPO_BIN_DIR;
+
+    use std::fs;
+
+    #[test]
+    fn check_repo_accepts_consistent_repo() {
+        let repo = TestRepo::new("check-ok");
+        repo.add_pkg("core/foo", "1.0.0", &["bar>=2.0"]);
+        repo.add_pkg("extra/bar", "2.1.0", &[]);
+        index::add_pkgs(repo.path(), &["core/foo", "extra/bar"]).unwrap();
+
+        let problems = check_repo(repo.path()).unwrap();
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn check_repo_reports_all_problems() {
+        let repo = TestRepo::new("check-broken");
+        repo.add_pkg("core/foo", "1.0.0", &["bar>=3.0"]);
+        repo.add_pkg("extra/bar", "2.1.0", &[]);
+        repo.add_pkg("core/gone", "1.0.0", &[]);
+        repo.add_pkg("core/changed", "1.0.0", &[]);
+        repo.add_pkg("core/garbled", "1.0.0", &[]);
+        let locations = [
+            "core/foo",
+            "extra/bar",
+            "core/gone",
+            "core/changed",
+            "core/garbled",
+        ];
+        index::add_pkgs(repo.path(), &locations).unwrap();
+
+        // missing archive
+        fs::remove_dir_all(repo.path().join(REPO_BIN_DIR).join("core/gone")).unwrap();
+        // hash mismatch
+        fs::write(pkg_hash_path(repo.path(), "core/changed"), "0000\n").unwrap();
+        // unreadable hash
+        fs::write(pkg_hash_path(repo.path(), "core/garbled"), [0xff, 0xfe]).unwrap();
+
+        let problems: Vec<String> = check_repo(repo.path())
+            .unwrap()
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let expected = [
+            RepoError::MissingArchive("gone".to_string(), "core/gone".to_string()).to_string(),
+            format!(
+                "Cannot read {}",
+                pkg_hash_path(repo.path(), "core/garbled").display()
+            ),
+            "Expected version (>=3.0.0), got (2.1.0): bar required by foo".to_string(),
+        ];
+        for problem in &expected {
+            assert!(
+                problems.iter().any(|p| p.contains(problem.as_str())),
+                "{} not in {:?}",
+                problem,
+                problems
+            );
+        }
+        let archive = pkg_archive_path(repo.path(), "core/changed");
+        assert!(
+            problems
+                .iter()
+                .any(|p| p.contains(&archive.display().to_string()) && p.contains("0000")),
+            "no hash mismatch in {:?}",
+            problems
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum RepoError {
+    /// A package has sources in `src` but no binary package in `bin`. Contains the location of
+    /// the package.
+    MissingBin(String),
+    /// A package has a binary package in `bin` but no sources in `src`. Contains the location of
+    /// the package.
+    MissingSrc(String),
+    /// Contains the location of the binary package without a SHA256 hash file.
+    MissingHash(String),
+    /// A package of the index has no binary package. Contains the name of the package and the
+    /// location from the index.
+    MissingArchive(String, String),
+    /// Contains the location of the binary package that is not in the index.
+    NotInIndex(String),
+    /// The `nbinfo.toml` embedded in a binary package does not match the index. Contains the name
+    /// of the package and the name of the field that does not match.
+    InfoMismatch(String, String),
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            RepoError::MissingBin(loc) => write!(f, "Package {} has no binary package", loc),
+            RepoError::MissingSrc(loc) => write!(f, "Package {} has no sources", loc),
+            RepoError::MissingHash(loc) => write!(f, "Package {} has no SHA256 hash", loc),
+            RepoError::MissingArchive(name, loc) => write!(
+                f,
+                "Package {} of the index has no binary package in {}",
+                name, loc
+            ),
+            RepoError::NotInIndex(loc) => write!(f, "Package {} is not in the index", loc),
+            RepoError::InfoMismatch(name, field) => write!(
+                f,
+                "The {} of package {} does not match the index",
+                field, name
+            ),
+        }
+    }
+}
+
+impl Error for RepoError {}
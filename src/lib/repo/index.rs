@@ -0,0 +1,361 @@
+//! Generation and maintenance of the index `PkgDb` of a repository.
+//!
+//! The index is generated from the binary packages in `bin`. The `nbinfo.toml` file embedded in
+//! every package is read, and its `InfoLocal` is replaced by an `InfoUniverse` pointing to the
+//! location of the package inside `bin`.
+//!
+//! **NOTE**: As all paths in this crate's `repo` module, the `repo` parameters of the functions
+//! below are the path to the repo/{architecture} directory.
+
+use walkdir::WalkDir;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{REPO_BIN_DIR, REPO_HASH_EXT, REPO_INDEX_PATH, REPO_PKG_EXT, REPO_PKG_INFO};
+use crate::core::{pkgdb::PkgInfo, InfoUniverse, NbError, PkgDb, SetInfo};
+use crate::{utils, TypeErr};
+
+/// Returns the path to the compressed package in the given `location` (relative to `bin`).
+pub fn pkg_archive_path(repo: &Path, location: &str) -> PathBuf {
+    let name = location_pkg_name(location);
+    repo.join(REPO_BIN_DIR)
+        .join(location)
+        .join(format!("{}.{}", name, REPO_PKG_EXT))
+}
+
+/// Returns the path to the SHA256 hash of the package in the given `location` (relative to
+/// `bin`).
+pub fn pkg_hash_path(repo: &Path, location: &str) -> PathBuf {
+    let name = location_pkg_name(location);
+    repo.join(REPO_BIN_DIR)
+        .join(location)
+        .join(format!("{}.{}", name, REPO_HASH_EXT))
+}
+
+/// Returns the name of the package in the given `location`, this is, the last component of the
+/// location. For example, the name of the package in `core/foo` is `foo`.
+pub fn location_pkg_name(location: &str) -> &str {
+    location
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(location)
+}
+
+/// Finds the locations (relative to `bin`) of all the binary packages of the repository. The
+/// returned list is sorted.
+///
+/// A file is considered a binary package if its name is the name of its parent directory
+/// followed by the `REPO_PKG_EXT` extension, for example: `bin/core/foo/foo.tar.xz`.
+pub fn find_pkgs(repo: &Path) -> Result<Vec<String>, TypeErr> {
+    let bin = repo.join(REPO_BIN_DIR);
+    let mut locations = vec![];
+    if !bin.is_dir() {
+        return Ok(locations);
+    }
+    for entry in WalkDir::new(&bin) {
+        let entry = entry?;
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let parent = match path.parent() {
+            Some(p) => p,
+            None => continue,
+        };
+        let dir_name = match parent.file_name() {
+            Some(n) => n.to_string_lossy(),
+            None => continue,
+        };
+        if entry.file_name().to_string_lossy() != format!("{}.{}", dir_name, REPO_PKG_EXT) {
+            continue;
+        }
+        if let Some(loc) = parent.strip_prefix(&bin)?.to_str() {
+            locations.push(loc.to_string());
+        }
+    }
+    locations.sort();
+    Ok(locations)
+}
+
+/// Reads the SHA256 hash stored in a `.sha256` file. Only the first word of the file is read, so
+/// files written by `sha256sum` are also valid.
+pub fn read_hash_file(hash_path: &Path) -> Result<String, TypeErr> {
+    let hash_str = fs::read_to_string(hash_path)?;
+    Ok(hash_str.split_whitespace().next().unwrap_or("").to_string())
+}
+
+/// Checks the SHA256 hash of the package in the given `location` against its `.sha256` file. If
+/// the package has no hash file, the hash is computed and the file is written.
+///
+/// # Errors
+///
+/// If the hash in the `.sha256` file does not match the hash of the package, a
+/// `NbError::HashMismatch` error is returned.
+pub fn check_or_write_hash(repo: &Path, location: &str) -> Result<(), TypeErr> {
+    let archive = pkg_archive_path(repo, location);
+    let hash_path = pkg_hash_path(repo, location);
+    let actual = utils::file2hash(&archive)?;
+
+    if !hash_path.exists() {
+        fs::write(&hash_path, format!("{}\n", actual))?;
+        return Ok(());
+    }
+
+    let expected = read_hash_file(&hash_path)?;
+    if expected != actual {
+        return Err(Box::new(NbError::HashMismatch(
+            archive.display().to_string(),
+            expected,
+            actual,
+        )));
+    }
+    Ok(())
+}
+
+/// Reads the `nbinfo.toml` file embedded in the compressed package in `archive`, and returns the
+/// `PkgInfo` of the package named `name`.
+///
+/// # Errors
+///
+/// If the `nbinfo.toml` file does not contain the package, a `NbError::PkgNotFound` error is
+/// returned.
+pub fn read_archive_info(archive: &Path, name: &str) -> Result<PkgInfo, TypeErr> {
+    let archive_str = archive.to_string_lossy();
+    // the info file might be stored with or without the leading `./`
+    let info_str = match utils::run_cmd_output("tar", &["-xOf", &archive_str, REPO_PKG_INFO]) {
+        Ok(s) => s,
+        Err(_) => utils::run_cmd_output(
+            "tar",
+            &["-xOf", &archive_str, &format!("./{}", REPO_PKG_INFO)],
+        )?,
+    };
+    let mut info = toml::from_str::<HashMap<String, PkgInfo>>(&info_str)?;
+    match info.remove(name) {
+        Some(i) => Ok(i),
+        None => Err(Box::new(NbError::PkgNotFound(format!(
+            "{} in {}",
+            name, archive_str
+        )))),
+    }
+}
+
+/// Reads the package in the given `location` (relative to `bin`) and returns its name and the
+/// `PkgInfo` to be inserted in the index. The hash of the package is checked (or written if
+/// missing), see `check_or_write_hash`.
+pub fn read_pkg(repo: &Path, location: &str) -> Result<(String, PkgInfo), TypeErr> {
+    let name = location_pkg_name(location);
+    let archive = pkg_archive_path(repo, location);
+    if !archive.is_file() {
+        return Err(Box::new(NbError::MissingFile(
+            archive.display().to_string(),
+        )));
+    }
+    check_or_write_hash(repo, location)?;
+
+    let mut info = read_archive_info(&archive, name)?;
+    // packages in the index are universe packages, located in `location`
+    if !info.is_meta() {
+        *info.mut_set_info() = Some(SetInfo::Universe(InfoUniverse::from(location)));
+    }
+    Ok((name.to_string(), info))
+}
+
+/// Loads the index of the repository. If the repository has no index yet, an empty `PkgDb` is
+/// returned.
+pub fn load_index(repo: &Path) -> Result<PkgDb, TypeErr> {
+    let index_path = repo.join(REPO_INDEX_PATH);
+    if index_path.exists() {
+        PkgDb::load(&index_path)
+    } else {
+        Ok(PkgDb::new())
+    }
+}
+
+/// Generates the index of the repository from all the binary packages in `bin`. As
+/// meta-packages have no binary package, the meta-packages of the current index (if some) are
+/// kept in the new index.
+///
+/// **NOTE**: The index is not written, see `save_index`.
+pub fn generate_index(repo: &Path) -> Result<PkgDb, TypeErr> {
+    let mut index = PkgDb::new();
+    for (name, info) in load_index(repo)?.iter() {
+        if info.is_meta() {
+            let _ = index.insert(name, info.clone());
+        }
+    }
+
+    for location in find_pkgs(repo)? {
+        let (name, info) = read_pkg(repo, &location)?;
+        let _ = index.insert(&name, info);
+    }
+    Ok(index)
+}
+
+/// Adds the packages in the given `locations` (relative to `bin`) to the index of the
+/// repository, replacing the older versions of the packages if already in the index. The
+/// updated index is written.
+pub fn add_pkgs(repo: &Path, locations: &[&str]) -> Result<(), TypeErr> {
+    let mut index = load_index(repo)?;
+    for location in locations {
+        let (name, info) = read_pkg(repo, location)?;
+        let _ = index.insert(&name, info);
+    }
+    save_index(repo, &index)
+}
+
+/// Removes the given packages from the index of the repository and writes the updated index. If
+/// `check_conflicts` is `true`, the packages are only removed if no other package in the index
+/// depends on them (see `PkgDb::check_remove`).
+///
+/// **NOTE**: The binary packages are not removed from `bin`.
+pub fn remove_pkgs(repo: &Path, names: &[&str], check_conflicts: bool) -> Result<(), TypeErr> {
+    let mut index = load_index(repo)?;
+    if check_conflicts {
+        index.check_remove(names.to_vec())?;
+    }
+    for name in names {
+        index.remove(name, false)?;
+    }
+    save_index(repo, &index)
+}
+
+/// Writes the given `index` as the index of the repository.
+pub fn save_index(repo: &Path, index: &PkgDb) -> Result<(), TypeErr> {
+    index.save(&repo.join(REPO_INDEX_PATH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::test_utils::TestRepo;
+
+    /// Returns the location and version of every package of the index, sorted by name.
+    fn index_pkgs(index: &PkgDb) -> Vec<(String, String, String)> {
+        let mut pkgs: Vec<_> = index
+            .iter()
+            .map(|(name, info)| {
+                let loc = match info.set_info() {
+                    Some(SetInfo::Universe(u)) => u.location().to_string(),
+                    _ => panic!("{} is not a universe package", name),
+                };
+                (name.to_string(), loc, info.version().to_string())
+            })
+            .collect();
+        pkgs.sort();
+        pkgs
+    }
+
+    fn pkg(name: &str, loc: &str, version: &str) -> (String, String, String) {
+        (name.to_string(), loc.to_string(), version.to_string())
+    }
+
+    #[test]
+    fn generate_index_reads_all_pkgs() {
+        let repo = TestRepo::new("generate");
+        repo.add_pkg("core/foo", "1.0.0", &["bar>=2.0"]);
+        repo.add_pkg("extra/bar", "2.1.0", &[]);
+        // missing hashes are written
+        let bar_hash = pkg_hash_path(repo.path(), "extra/bar");
+        fs::remove_file(&bar_hash).unwrap();
+
+        let index = generate_index(repo.path()).unwrap();
+        assert_eq!(
+            index_pkgs(&index),
+            vec![
+                pkg("bar", "extra/bar", "2.1.0"),
+                pkg("foo", "core/foo", "1.0.0")
+            ]
+        );
+        let foo = index.get_pkg_info("foo").unwrap();
+        assert_eq!(foo.depends().unwrap()[0].0, "bar");
+        for loc in &["core/foo", "extra/bar"] {
+            let archive = pkg_archive_path(repo.path(), loc);
+            assert_eq!(
+                read_hash_file(&pkg_hash_path(repo.path(), loc)).unwrap(),
+                utils::file2hash(&archive).unwrap()
+            );
+        }
+        // the index is not written
+        assert!(!repo.path().join(REPO_INDEX_PATH).exists());
+    }
+
+    #[test]
+    fn generate_index_fails_on_hash_mismatch() {
+        let repo = TestRepo::new("generate-mismatch");
+        repo.add_pkg("core/foo", "1.0.0", &[]);
+        fs::write(pkg_hash_path(repo.path(), "core/foo"), "0000\n").unwrap();
+
+        let err = generate_index(repo.path()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NbError>(),
+            Some(NbError::HashMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn add_pkgs_replaces_older_versions() {
+        let repo = TestRepo::new("add");
+        repo.add_pkg("core/foo", "1.0.0", &[]);
+        repo.add_pkg("extra/bar", "2.1.0", &[]);
+        add_pkgs(repo.path(), &["core/foo"]).unwrap();
+        assert_eq!(
+            index_pkgs(&load_index(repo.path()).unwrap()),
+            vec![pkg("foo", "core/foo", "1.0.0")]
+        );
+
+        repo.add_pkg("core/foo", "1.1.0", &[]);
+        add_pkgs(repo.path(), &["core/foo", "extra/bar"]).unwrap();
+        assert_eq!(
+            index_pkgs(&load_index(repo.path()).unwrap()),
+            vec![
+                pkg("bar", "extra/bar", "2.1.0"),
+                pkg("foo", "core/foo", "1.1.0")
+            ]
+        );
+    }
+
+    #[test]
+    fn add_pkgs_fails_on_missing_archive() {
+        let repo = TestRepo::new("add-missing");
+        let err = add_pkgs(repo.path(), &["core/foo"]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NbError>(),
+            Some(NbError::MissingFile(_))
+        ));
+        assert!(!repo.path().join(REPO_INDEX_PATH).exists());
+    }
+
+    #[test]
+    fn remove_pkgs_updates_index() {
+        let repo = TestRepo::new("remove");
+        repo.add_pkg("core/foo", "1.0.0", &["bar"]);
+        repo.add_pkg("extra/bar", "2.1.0", &[]);
+        add_pkgs(repo.path(), &["core/foo", "extra/bar"]).unwrap();
+
+        // foo depends on bar
+        assert!(remove_pkgs(repo.path(), &["bar"], true).is_err());
+        remove_pkgs(repo.path(), &["foo"], true).unwrap();
+        assert_eq!(
+            index_pkgs(&load_index(repo.path()).unwrap()),
+            vec![pkg("bar", "extra/bar", "2.1.0")]
+        );
+        // the binary package is kept
+        assert!(pkg_archive_path(repo.path(), "core/foo").is_file());
+    }
+
+    #[test]
+    fn remove_pkgs_fails_on_unknown_pkg() {
+        let repo = TestRepo::new("remove-unknown");
+        repo.add_pkg("core/foo", "1.0.0", &[]);
+        add_pkgs(repo.path(), &["core/foo"]).unwrap();
+
+        assert!(remove_pkgs(repo.path(), &["nothing"], false).is_err());
+        assert_eq!(
+            index_pkgs(&load_index(repo.path()).unwrap()),
+            vec![pkg("foo", "core/foo", "1.0.0")]
+        );
+    }
+}
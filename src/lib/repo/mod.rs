@@ -1,8 +1,16 @@
-//! In this module contains all defenitions for nebula repostories.
+//! This module contains all defenitions for nebula repostories.
 //!
 //! **NOTE**: All paths defined below are relative to the root of the repository,
 //! the repo/{architecture} directory.
 
+pub mod check;
+pub mod errors;
+pub mod index;
+#[cfg(test)]
+pub(crate) mod test_utils;
+
+pub use errors::RepoError;
+
 /// Filename of the index `PkgDb`.
 pub const REPO_INDEX_PATH: &str = "index.toml";
 
@@ -12,5 +20,14 @@ pub const REPO_BIN_DIR: &str = "bin";
 /// Path to the directory where source file of the packages are.
 pub const REPO_SRC_DIR: &str = "src";
 
+/// File name of the script used to build the packages in `src`.
+pub const REPO_TEMPLATE: &str = "template";
+
 /// File name for the `PkgInfo` of packages.
 pub const REPO_PKG_INFO: &str = "nbinfo.toml";
+
+/// Extension of the compressed binary packages.
+pub const REPO_PKG_EXT: &str = "tar.xz";
+
+/// Extension of the files containing the SHA256 hash of the binary packages.
+pub const REPO_HASH_EXT: &str = "sha256";
@@ -0,0 +1,70 @@
+//! Helpers for the tests of the `repo` module: a throwaway repository with binary packages.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{REPO_BIN_DIR, REPO_PKG_INFO, REPO_SRC_DIR, REPO_TEMPLATE};
+use crate::builder::pack;
+
+/// A temporary repository (the repo/{architecture} directory). The directory is removed when the
+/// `TestRepo` is dropped.
+pub struct TestRepo {
+    pub dir: PathBuf,
+}
+
+impl TestRepo {
+    /// Creates an empty `TestRepo`. `name` must be unique among the tests, as it is used to name
+    /// the temporary directory.
+    pub fn new(name: &str) -> TestRepo {
+        let dir = env::temp_dir().join(format!("nbkit-test-repo-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        TestRepo { dir }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Adds the sources and the binary package (with its hash) of a package in `location`,
+    /// replacing them if they already exist. The package contains a single file,
+    /// `usr/share/<name>`.
+    pub fn add_pkg(&self, location: &str, version: &str, depends: &[&str]) {
+        let name = super::index::location_pkg_name(location);
+        let src = self.dir.join(REPO_SRC_DIR).join(location);
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join(REPO_TEMPLATE), "#!/bin/sh\n").unwrap();
+
+        let staging = self.dir.join("staging").join(location);
+        if staging.exists() {
+            fs::remove_dir_all(&staging).unwrap();
+        }
+        fs::create_dir_all(staging.join("usr/share")).unwrap();
+        fs::write(staging.join("usr/share").join(name), version).unwrap();
+        let paths = vec![
+            "usr".to_string(),
+            "usr/share".to_string(),
+            format!("usr/share/{}", name),
+        ];
+        fs::write(
+            staging.join(REPO_PKG_INFO),
+            format!(
+                "[{0}]\nversion = '{1}'\ndepends = {2:?}\ndescription = '{0}'\n\n[{0}.local]\npaths = {3:?}\n",
+                name, version, depends, paths
+            ),
+        )
+        .unwrap();
+        let pkg_dir = self.dir.join(REPO_BIN_DIR).join(location);
+        pack::pack_pkg(&staging, &paths, &pkg_dir, name).unwrap();
+        fs::remove_dir_all(&staging).unwrap();
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
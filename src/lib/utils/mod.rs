@@ -1,60 +1,68 @@
 use semver::VersionReq;
 use sha2::{Digest, Sha256};
 
+use super::fetch::{DefaultFetcher, Fetcher, NoProgress};
 use super::{core::NbError, Query, TypeErr};
 
-use std::fs::{File, OpenOptions};
-use std::io::{stdin, stdout, Read, Write};
+use std::fs::File;
+use std::io::{self, stdin, stdout, Read, Write};
 use std::path::Path;
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// parse information of a package given a string. The string format must be: pkg_name or
 /// [pkgname][comp_op][version]. Examples: "neofetch", "glibc", "linux>=5.5.3" and "make<1.0".
+/// The version part can be any semver version requirement, for example "linux>=5.5, <6".
 pub fn parse_pkg_str_info(text: &str) -> Result<Query, TypeErr> {
-    // search for comparison operator on the query
-    for operator in &["==", ">=", "<=", ">", "<"] {
-        // if an operator is present extract the name, comparison operator and version
-        if text.contains(operator) {
-            // split the name and versionreq part
-            let mut splitted = text.split(operator);
-            // its safe to call unwrap here as the `splitted` will always
-            // have at least one element
-            let name = splitted.next().unwrap();
-            let comp_ver = match splitted.next() {
-                Some(s) => VersionReq::parse(s)?,
-                None => VersionReq::any(),
+    // search for the start of the version requirement (if some) on the query
+    match text.find(|c| "=<>^~*".contains(c)) {
+        // if an operator is present extract the name and the version requirement, the
+        // comparison operator is part of the requirement
+        Some(idx) => {
+            let name = &text[..idx];
+            // semver uses `=` for exact version requirements
+            let comp_ver = match text[idx..].strip_prefix("==") {
+                Some(ver) => VersionReq::parse(&format!("={}", ver))?,
+                // a bare wildcard is the same as no requirement at all
+                None if text[idx..].trim() == "*" => VersionReq::any(),
+                None => VersionReq::parse(&text[idx..])?,
             };
-            return Ok((name.to_string(), comp_ver));
+            Ok((name.to_string(), comp_ver))
         }
+        None => Ok((text.to_string(), VersionReq::any())),
     }
-    Ok((text.to_string(), VersionReq::any()))
 }
 
-/// Downloas a file from the given `url` and saves it as `outpath`.
+/// Scheme of the URLs that point to a file in the local filesystem.
+pub const FILE_URL_SCHEME: &str = "file://";
+
+/// Given a `url`, returns the path in the local filesystem it points to. Both `file://` URLs and
+/// plain paths (strings with no scheme, for example `/mnt/repo/x86_64`) are considered local. If
+/// the `url` has any other scheme (`http://`, `https://`...), or it is a `file://` URL with a host
+/// other than `localhost` (for example, `file://server/repo`), `None` is returned.
+pub fn url_to_local_path(url: &str) -> Option<&Path> {
+    if let Some(rest) = url.strip_prefix(FILE_URL_SCHEME) {
+        // the host goes from the scheme to the start of the path
+        let path_start = rest.find('/').unwrap_or(rest.len());
+        match &rest[..path_start] {
+            "" | "localhost" => Some(Path::new(&rest[path_start..])),
+            _ => None,
+        }
+    } else if url.contains("://") {
+        None
+    } else {
+        Some(Path::new(url))
+    }
+}
+
+/// Downloads a file from the given `url` and saves it as `outpath`, using the
+/// `fetch::DefaultFetcher`. If the `url` points to the local filesystem (see
+/// `url_to_local_path`), the file is copied instead.
+///
+/// For more details about the errors this function might return, see `fetch::FileFetcher` and
+/// `fetch::HttpFetcher`.
 pub fn download(url: &str, outfile: &Path) -> Result<(), TypeErr> {
-    // delete the file/dir to download if it already exists
-    if outfile.is_dir() && outfile.exists() {
-        std::fs::remove_dir_all(&outfile)?;
-    } else if outfile.is_file() && outfile.exists() {
-        std::fs::remove_file(&outfile)?;
-    }
-
-    let resp = reqwest::blocking::get(url)?;
-    // check for errors
-    let status = resp.status();
-    if status.is_client_error() {
-        return Err(Box::new(NbError::ClientError(status.to_string())));
-    } else if status.is_server_error() {
-        return Err(Box::new(NbError::ServerError(status.to_string())));
-    }
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(&outfile)?;
-    file.write_all(&resp.bytes()?)?;
-    Ok(())
+    DefaultFetcher::new().fetch(url, outfile, &NoProgress)
 }
 
 /// Computes the SHA256 hash of the file in the given path.
@@ -65,15 +73,59 @@ pub fn file2hash(filepath: &Path) -> Result<String, TypeErr> {
     Ok(format!("{:x}", Sha256::digest(&buffer)))
 }
 
+/// Returns the current time, as seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats a time given as seconds since the Unix epoch as a UTC date, for example:
+/// `2020-09-15 18:30:00 UTC`.
+pub fn format_unix_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // convert the days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Reads a line of user input from the command line, after printing the `prompt`.
+///
+/// # Errors
+///
+/// If the input cannot be read or it ended (for example, stdin is `/dev/null`), an
+/// `io::ErrorKind::UnexpectedEof` error is returned.
 pub fn read_line(prompt: &str) -> Result<String, TypeErr> {
     let mut line = String::new();
     print!("\n{}", prompt);
     if let Err(e) = stdout().flush() {
         return Err(Box::new(e));
     }
-    let _n = stdin()
-        .read_line(&mut line)
-        .expect("Cannot read user input");
+    if stdin().read_line(&mut line)? == 0 {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "No more user input",
+        )));
+    }
     Ok(line.trim_end().to_string())
 }
 
@@ -85,6 +137,11 @@ pub fn read_line(prompt: &str) -> Result<String, TypeErr> {
 /// If the child process cannot be started, a `NbError::CmdStartChild` error is returned.
 /// If the child process exits with error status, a `NbError::CmdChildErr` error is returned.
 pub fn run_cmd(cmd: &str, args: &[&str]) -> Result<(), TypeErr> {
+    run_cmd_output(cmd, args).map(|_| ())
+}
+
+/// Same as `run_cmd`, but returns the standard output of the child process.
+pub fn run_cmd_output(cmd: &str, args: &[&str]) -> Result<String, TypeErr> {
     // create the command and add arguments if necessary
     let mut command = Command::new(cmd);
     if !args.is_empty() {
@@ -99,7 +156,7 @@ pub fn run_cmd(cmd: &str, args: &[&str]) -> Result<(), TypeErr> {
     };
     // read status and return result
     if child.status.success() {
-        Ok(())
+        Ok(String::from_utf8_lossy(&child.stdout).to_string())
     } else {
         let err_msg = String::from_utf8_lossy(&child.stderr);
         // convert the arguments string to a single and readable string
@@ -110,3 +167,121 @@ pub fn run_cmd(cmd: &str, args: &[&str]) -> Result<(), TypeErr> {
         ))))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::wrappers::DependencyWrap;
+    use semver::Version;
+
+    fn req(text: &str) -> VersionReq {
+        VersionReq::parse(text).unwrap()
+    }
+
+    fn ver(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parse_pkg_str_info_keeps_operators() {
+        for (text, name, expected) in &[
+            ("linux>=5.5.3", "linux", ">=5.5.3"),
+            ("linux<=5.5.3", "linux", "<=5.5.3"),
+            ("make>1.0", "make", ">1.0"),
+            ("make<1.0", "make", "<1.0"),
+            ("glibc~2.31", "glibc", "~2.31"),
+            ("glibc^2.31", "glibc", "^2.31"),
+            ("glibc=2.31.0", "glibc", "=2.31.0"),
+        ] {
+            let (n, r) = parse_pkg_str_info(text).unwrap();
+            assert_eq!(n, *name, "{}", text);
+            assert_eq!(r, req(expected), "{}", text);
+        }
+        // `>=` is not a caret requirement, so newer major versions match
+        let (_, r) = parse_pkg_str_info("linux>=5.5.3").unwrap();
+        assert!(r.matches(&ver("6.0.0")));
+        assert!(!r.matches(&ver("5.5.2")));
+    }
+
+    #[test]
+    fn parse_pkg_str_info_double_equals_is_exact() {
+        let (name, r) = parse_pkg_str_info("make==1.0.2").unwrap();
+        assert_eq!(name, "make");
+        assert_eq!(r, req("=1.0.2"));
+        assert!(r.matches(&ver("1.0.2")));
+        assert!(!r.matches(&ver("1.0.3")));
+    }
+
+    #[test]
+    fn parse_pkg_str_info_bare_name_is_any_version() {
+        let (name, r) = parse_pkg_str_info("neofetch").unwrap();
+        assert_eq!(name, "neofetch");
+        assert_eq!(r, VersionReq::any());
+        // written by older versions of `DependencyWrap`
+        let (name, r) = parse_pkg_str_info("neofetch*").unwrap();
+        assert_eq!(name, "neofetch");
+        assert_eq!(r, VersionReq::any());
+    }
+
+    #[test]
+    fn parse_pkg_str_info_multiple_clauses() {
+        let (name, r) = parse_pkg_str_info("a>=1.0, <2").unwrap();
+        assert_eq!(name, "a");
+        assert!(r.matches(&ver("1.0.0")));
+        assert!(r.matches(&ver("1.9.9")));
+        assert!(!r.matches(&ver("2.0.0")));
+        assert!(!r.matches(&ver("0.9.0")));
+    }
+
+    #[test]
+    fn parse_pkg_str_info_rejects_invalid_requirements() {
+        for text in &["foo>=abc", "foo>=", "foo==", "foo<1.0, >=two"] {
+            assert!(parse_pkg_str_info(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn dependency_wrap_round_trips() {
+        #[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+        struct Deps {
+            depends: Vec<DependencyWrap>,
+        }
+
+        for text in &[
+            "neofetch",
+            "linux>=5.5.3",
+            "make==1.0.2",
+            "glibc~2.31",
+            "a>=1.0, <2",
+        ] {
+            let deps = Deps {
+                depends: vec![DependencyWrap::from(parse_pkg_str_info(text).unwrap())],
+            };
+            let serialized = toml::to_string(&deps).unwrap();
+            let loaded: Deps = toml::from_str(&serialized).unwrap();
+            assert_eq!(
+                loaded.depends[0].inner(),
+                deps.depends[0].inner(),
+                "{} serialized as {}",
+                text,
+                serialized
+            );
+        }
+    }
+
+    #[test]
+    fn url_to_local_path_accepts_local_urls() {
+        let local = |url| url_to_local_path(url).map(|p| p.to_str().unwrap());
+        assert_eq!(local("file:///srv/repo"), Some("/srv/repo"));
+        assert_eq!(local("file://localhost/srv/repo"), Some("/srv/repo"));
+        assert_eq!(local("/srv/repo"), Some("/srv/repo"));
+        assert_eq!(local("build/repo"), Some("build/repo"));
+    }
+
+    #[test]
+    fn url_to_local_path_rejects_remote_urls() {
+        assert_eq!(url_to_local_path("file://server/srv/repo"), None);
+        assert_eq!(url_to_local_path("https://example.com/repo"), None);
+        assert_eq!(url_to_local_path("mock://repo"), None);
+    }
+}